@@ -0,0 +1,41 @@
+//! Compares clearing a 4K RGBA output the way `rectanglify` does against putting every pixel
+//! separately, which is what it used to do.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::{Rgba, RgbaImage};
+use rectanglify::rects::{Color, DrawCommand, ImageRenderer, Renderer, Settings};
+
+mod common;
+
+fn clear(c: &mut Criterion) {
+    let (width, height) = common::UHD;
+    let mut output = RgbaImage::new(width, height);
+
+    let mut group = c.benchmark_group("clear");
+    group.sample_size(20);
+    for (name, color) in [
+        ("white", Color::WHITE),
+        ("tinted", Color::rgb(0.9, 0.85, 0.8)),
+    ] {
+        group.bench_function(format!("put_pixel/{}", name), |b| {
+            let pixel: Rgba<u8> = color.to_pixel();
+            b.iter(|| {
+                for y in 0..height {
+                    for x in 0..width {
+                        output.put_pixel(x, y, black_box(pixel));
+                    }
+                }
+            })
+        });
+        group.bench_function(format!("renderer/{}", name), |b| {
+            b.iter(|| {
+                let mut renderer = ImageRenderer::new(&mut output, Settings::default());
+                renderer.draw(DrawCommand::Clear(black_box(color)));
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, clear);
+criterion_main!(benches);
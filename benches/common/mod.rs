@@ -0,0 +1,22 @@
+//! Inputs shared between the benchmarks.
+
+// Each benchmark only uses some of this.
+#![allow(dead_code)]
+
+use image::{Rgb, RgbImage};
+
+/// The size of a 4K UHD frame.
+pub const UHD: (u32, u32) = (3840, 2160);
+/// The size of a 1080p frame.
+pub const FULL_HD: (u32, u32) = (1920, 1080);
+
+/// Makes a `width` by `height` frame with some detail everywhere, so that the splits go all the
+/// way down all over it like they would in a photo.
+pub fn frame((width, height): (u32, u32)) -> RgbImage {
+    RgbImage::from_fn(width, height, |x, y| {
+        let (u, v) = (x as f64 / width as f64, y as f64 / height as f64);
+        let rings = ((u - 0.3).hypot(v - 0.6) * 40.0).sin() * 0.5 + 0.5;
+        let value = |offset: f64| ((rings + u * offset + v * (1.0 - offset)) / 2.0 * 255.0) as u8;
+        Rgb([value(0.2), value(0.5), value(0.8)])
+    })
+}
@@ -0,0 +1,35 @@
+//! Compares working out the darkness of a 4K frame with the 8-bit lookup table against working
+//! it out from scratch for every pixel.
+//!
+//! A custom brightness function is the only way to skip the table for 8-bit pixels, so that's
+//! what the computed case uses, with the same luma the table is built from.
+
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rectanglify::rects::{rectanglify_rects, Brightness, Color, Settings};
+
+mod common;
+
+fn lut(c: &mut Criterion) {
+    let input = common::frame(common::UHD);
+
+    let mut group = c.benchmark_group("darkness");
+    group.sample_size(10);
+    for (name, brightness) in [
+        ("lut", Brightness::Luma),
+        ("computed", Brightness::Custom(Arc::new(Color::luma))),
+    ] {
+        let settings = Settings {
+            brightness,
+            ..Settings::default()
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| rectanglify_rects(black_box(&input), settings.clone()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, lut);
+criterion_main!(benches);
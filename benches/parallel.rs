@@ -0,0 +1,29 @@
+//! Compares splitting up a 4K frame on one thread against splitting it up on all of them with
+//! `Settings::parallel`.
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::RgbImage;
+use rectanglify::rects::{rectanglify, Settings};
+
+mod common;
+
+fn parallel(c: &mut Criterion) {
+    let input = common::frame(common::UHD);
+    let mut output = RgbImage::new(input.width(), input.height());
+
+    let mut group = c.benchmark_group("uhd");
+    group.sample_size(10);
+    for (name, parallel) in [("serial", false), ("parallel", true)] {
+        let settings = Settings {
+            parallel,
+            ..Settings::default()
+        };
+        group.bench_function(name, |b| {
+            b.iter(|| rectanglify(black_box(&input), &mut output, settings.clone()))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, parallel);
+criterion_main!(benches);
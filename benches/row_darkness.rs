@@ -0,0 +1,43 @@
+//! Compares working out the darkness of every pixel in a 1080p frame straight from the channels
+//! of each 8-bit pixel type against the generic path through `Color`.
+//!
+//! Only one rectangle is asked for, so that this is almost all reading the frame rather than
+//! splitting it up. A custom brightness function is the only way to take the generic path with
+//! 8-bit pixels, so that's what the `generic` case uses.
+
+use std::sync::Arc;
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use image::{DynamicImage, GenericImageView};
+use rectanglify::rects::{rectanglify_rects, Brightness, Color, RectBudget, Settings};
+
+mod common;
+
+fn bench<I: GenericImageView>(c: &mut Criterion, name: &str, input: &I) {
+    let mut group = c.benchmark_group(format!("row_darkness/{}", name));
+    group.sample_size(20);
+    for (path, brightness) in [
+        ("specialised", Brightness::Luma),
+        ("generic", Brightness::Custom(Arc::new(Color::luma))),
+    ] {
+        let settings = Settings {
+            budget: RectBudget::Count(1),
+            brightness,
+            ..Settings::default()
+        };
+        group.bench_function(path, |b| {
+            b.iter(|| rectanglify_rects(black_box(input), settings.clone()))
+        });
+    }
+    group.finish();
+}
+
+fn row_darkness(c: &mut Criterion) {
+    let input = DynamicImage::ImageRgb8(common::frame(common::FULL_HD));
+    bench(c, "rgb", input.as_rgb8().unwrap());
+    bench(c, "rgba", &input.to_rgba8());
+    bench(c, "gray", &input.to_luma8());
+}
+
+criterion_group!(benches, row_darkness);
+criterion_main!(benches);
@@ -2,7 +2,10 @@ use gst::glib;
 use gst::prelude::*;
 
 mod plugin;
-mod rects;
+pub mod plotter;
+pub mod rects;
+pub mod svg;
+pub mod text;
 
 glib::wrapper! {
     pub struct Rectanglify(ObjectSubclass<plugin::Rectanglify>) @extends gst_base::BaseTransform, gst::Element, gst::Object;
@@ -1,25 +1,907 @@
 //! A binary which takes an image and rectanglifies it.
+//!
+//! Usage: `rectanglify [options] <input> <output>`
+//!
+//! If `output` ends in `.svg`, the dividing lines are written out as an SVG instead, if it ends
+//! in `.gcode` they're written out as G-code for a pen plotter, and if it ends in `.txt` they're
+//! written out as text with box-drawing characters (to stdout, if it's `-.txt`).
+//!
+//! Otherwise, inputs with more than 8 bits per channel are drawn at 16 bits if the output format
+//! supports it.
+//!
+//! Floating-point inputs (like OpenEXR and Radiance HDR images) are read as they are, with 1.0 as
+//! white, rather than being converted to integers first.
+//!
+//! Options:
+//! - `--fill <lines|mosaic|checkerboard|ellipses|filled-ellipses|halftone|mondrian|gradient>`: what
+//!   to draw inside each rectangle.
+//! - `--shape <ellipse|rectangle>`: whether `--fill ellipses` draws ellipses or rectangle
+//!   outlines, and `--fill halftone` draws round or square dots.
+//! - `--quantize <levels>`: fill each rectangle with one of this many evenly spaced shades of grey,
+//!   by how dark it is.
+//! - `--fill-probability <chance>`: how likely each rectangle is to be filled with `--fill
+//!   mondrian`, from 0 to 1.
+//! - `--fill-palette <#rrggbb>,...`: the colours to fill rectangles with for `--fill mondrian`,
+//!   instead of red, yellow and blue.
+//! - `--seed <number>`: the seed for anything random, like which rectangles `--fill mondrian`
+//!   fills.
+//! - `--halftone-radius <min>,<max>`: the range of sizes of the dots drawn with `--fill halftone`,
+//!   as fractions of the biggest dot that fits in each rectangle.
+//! - `--split <longest-side|alternate|balanced|quad|diagonal|vertical-only|horizontal-only>`: how
+//!   to decide which way to split each rectangle.
+//! - `--symmetry <none|left-right|four-way>`: mirror the splits in the left half (or top-left
+//!   quarter) onto the rest of the image.
+//! - `--seam-window <pixels>`: move each split to the lightest column or row up to this many
+//!   pixels away, so that the lines run between features rather than through them.
+//! - `--seam-max-imbalance <fraction>`: how much moving a split for `--seam-window` can change
+//!   the darkness on each side by, as a fraction of the darkness being split.
+//! - `--threshold <darkness>`: count pixels darker than this as black and the rest as white.
+//! - `--equalize`: spread out the darkness of the input evenly before splitting it, for
+//!   low-contrast images.
+//! - `--exposure <factor>`: multiply the brightness of the input by this first, so that HDR
+//!   images (like OpenEXR) with parts brighter than white can be brought back into range.
+//! - `--edges <weight>`: from 0 to 1, how much to put rectangles along edges in the input rather
+//!   than in dark areas.
+//! - `--smoothing <sigma>`: blur the input by this much (in pixels) before deciding where to split
+//!   it.
+//! - `--channels <combined|separate>`: whether to rectanglify the red, green and blue channels
+//!   separately.
+//! - `--shade-lines`: fade out the lines in lighter areas.
+//! - `--palette <#rrggbb>,...`: colour the lines by how many splits down they are, starting
+//!   over from the first colour when they run out.
+//! - `--key-color <#rrggbb>`: put the rectangles wherever the input is close to this colour,
+//!   instead of wherever it's dark.
+//! - `--max-aspect-ratio <ratio>`: don't make rectangles more stretched out than this, even if it
+//!   means sharing out the darkness unevenly.
+//! - `--snap`: only split the input on whole pixels.
+//! - `--deterministic`: give exactly the same output on every platform, at the cost of moving
+//!   the splits by up to 1/512 of a pixel.
+//! - `--grid <spacing>[,<x offset>,<y offset>]`: only split the input on a grid of lines this many
+//!   pixels apart.
+//! - `--overlay`: draw on top of the input instead of a blank background.
+//! - `--source-opacity <opacity>`: from 0 to 1, how much of the input shows through with
+//!   `--overlay`.
+//! - `--border`: draw a line around the edge of the output (or the region given by `--roi`).
+//! - `--wrap`: treat the input as repeating, for making a texture that tiles without seams.
+//!   `--border` is ignored.
+//! - `--num-rects <count>`: draw exactly this many rectangles, regardless of how dark the image is.
+//! - `--max-rects <count>`: never draw more than this many rectangles.
+//! - `--variance-threshold <variance>`: stop splitting areas whose darkness varies less than
+//!   this, even if that means drawing fewer rectangles.
+//! - `--min-leaf-darkness <pixels>`: stop splitting areas with less than this many black pixels'
+//!   worth of darkness, and give their share of the rectangles to the rest of the image.
+//! - `--deadline <milliseconds>`: stop splitting after this long, leaving whatever's left as it is.
+//! - `--roi <left>,<top>,<right>,<bottom>`: only rectanglify this region of the input, in pixels.
+//! - `--scale <factor>`: draw this many output pixels for each input pixel.
+//! - `--weight <mask>`: multiply the darkness of each pixel by the brightness of the same pixel in
+//!   `mask`, which must be the same size as the input.
+//! - `--mask <mask>`: only draw where `mask` is white, leaving the background wherever it's black,
+//!   for clipping the output to a shape. It must be the same size as the output.
+//! - `--thickness-falloff <factor>`: multiply the thickness of the lines by this at each level of
+//!   splits, so that later splits get thinner.
+//! - `--tone-match <strength>`: pick how thick each line is so that the output looks about as dark
+//!   as the input from a distance, going this far (from 0 to 1) from the usual thickness.
+//! - `--dashes <length>,<gap>`: draw the lines dashed, with dashes and gaps this long in output
+//!   pixels.
+//! - `--dots <gap>`: draw the lines dotted, with gaps this long in output pixels.
+//! - `--padding <pixels>`: shrink each rectangle by this much on every side, leaving gaps between
+//!   them instead of lines.
+//! - `--corner-radius <pixels>`: round off the corners of filled rectangles into quarter circles
+//!   this big in output pixels.
+//! - `--frames`: save every step of splitting up the image as a separate frame, numbered after the
+//!   name of the output (e.g. `out-0001.png`). It can't be combined with `--weight`, `--mask`,
+//!   `--heatmap`, `--stats` or `--overlay`.
+//! - `--heatmap`: also save a greyscale image of how densely packed the rectangles are, named
+//!   after the output (e.g. `out.heatmap.png`). White is one rectangle per input pixel, and it
+//!   gets 16 levels darker each time that halves.
+//! - `--debug-labels`: write each rectangle's index (in the order they were split out) in its
+//!   top-left corner.
+//! - `--stats`: print how evenly the darkness got shared out between the rectangles, how many
+//!   there are compared to how many were asked for, and how many couldn't be split as much as
+//!   they should've been.
+//! - `--plotter`: write the dividing lines out as polylines for a pen plotter, one per line of
+//!   text, instead of drawing them.
+//! - `--mm-per-pixel <mm>`: how many millimetres each input pixel is in G-code and `--plotter`
+//!   output (1 by default).
+//! - `--pen-width <mm>`: how wide the pen draws in G-code and `--plotter` output (0.5 by
+//!   default), so that lines can stop at the edge of the ones they run into.
+//! - `--feed-rate <mm per minute>`: how fast to draw in G-code output (3000 by default).
+//! - `--stroke-width <width>`: how wide to make the lines in SVG output, in input pixels.
+//! - `--text-size <columns>,<rows>`: how many characters to make text output. By default it's 80
+//!   columns wide, with as many rows as keeps the input's aspect ratio.
+//! - `--ascii`: only use `+`, `-` and `|` in text output.
 
-use anyhow::{anyhow, Context};
-use image::GrayImage;
-use rects::{rectanglify, Settings};
+use anyhow::{anyhow, bail, Context};
+use image::{
+    ColorType, EncodableLayout, GenericImageView, GrayImage, ImageBuffer, ImageFormat, Luma, Pixel,
+    PixelWithColorType, Rgb,
+};
+use rectanglify::{plotter, rects, svg, text};
+use rects::{
+    rectanglify_dynamic_as, rectanglify_progressive, rectanglify_with, Brightness, Channels, Color,
+    Completion, FillMode, LineStyle, LumaImage, Options, RectBudget, Rectangle, Settings, Shape,
+    SplitStrategy, Symmetry,
+};
 use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use text::Charset;
 
-mod rects;
+/// A greyscale image with 16 bits per pixel, as weights and masks are loaded.
+type Gray16Image = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+/// What `render` does besides rectanglifying the input and saving the output.
+#[derive(Debug, Clone, Copy, Default)]
+struct Extras<'a> {
+    /// What to multiply the darkness of the input by.
+    weight: Option<&'a Gray16Image>,
+    /// What to clip the output to.
+    mask: Option<&'a Gray16Image>,
+    /// Save every step separately instead, using `frame_path`. None of the others can be used
+    /// with this.
+    frames: bool,
+    /// Save a heatmap of the input's size as well, using `heatmap_path`.
+    heatmap: bool,
+    /// Print a report on how evenly the darkness got shared out between the rectangles.
+    stats: bool,
+}
+
+/// Rectanglifies `input` onto a new image of the given size, and saves it to `path`, doing
+/// `extras` as well.
+fn render<P>(
+    input: &impl GenericImageView,
+    settings: Settings,
+    (width, height): (u32, u32),
+    path: &OsStr,
+    extras: Extras,
+) -> anyhow::Result<()>
+where
+    P: Pixel + PixelWithColorType,
+    [P::Subpixel]: EncodableLayout,
+{
+    let mut output = ImageBuffer::<P, _>::new(width, height);
+    if extras.frames {
+        let mut result = Ok(());
+        let mut index = 0;
+        rectanglify_progressive(input, &mut output, settings, |frame| {
+            index += 1;
+            if result.is_ok() {
+                result = frame.save(frame_path(path, index));
+            }
+        });
+        return result.context("failed to save output");
+    }
+
+    let (input_width, input_height) = input.dimensions();
+    let mut heatmap = extras
+        .heatmap
+        .then(|| GrayImage::new(input_width, input_height));
+    let options = Options {
+        weight: extras.weight.map(|weight| weight as &dyn LumaImage),
+        mask: extras.mask.map(|mask| mask as &dyn LumaImage),
+        heatmap: heatmap.as_mut(),
+        ..Options::default()
+    };
+    let report = rectanglify_with(input, &mut output, settings, options)?;
+    if let Some(heatmap) = heatmap {
+        heatmap
+            .save(heatmap_path(path))
+            .context("failed to save heatmap")?;
+    }
+    if extras.stats {
+        eprintln!(
+            "{} rectangles, out of {} asked for",
+            report.rects, report.requested_rects
+        );
+        eprintln!(
+            "darkness per rectangle was {:.1}% off on average, and {:.1}% at most",
+            report.mean_error * 100.0,
+            report.max_error * 100.0
+        );
+        eprintln!(
+            "{} rectangles couldn't be split as much as they should've been",
+            report.clamped_leaves
+        );
+    }
+    warn_if_truncated(report.completion);
+    output.save(path).context("failed to save output")
+}
+
+/// Prints a warning if rectanglifying ran out of time.
+fn warn_if_truncated(completion: Completion) {
+    if completion == Completion::Truncated {
+        eprintln!("ran out of time, so some rectangles were left unsplit");
+    }
+}
+
+/// Returns the path to save frame `index` of an animation to, which is `path` with the number
+/// added to the end of the file name.
+fn frame_path(path: &OsStr, index: usize) -> PathBuf {
+    let path = Path::new(path);
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(format!("-{:04}", index));
+    if let Some(extension) = path.extension() {
+        name.push(".");
+        name.push(extension);
+    }
+    path.with_file_name(name)
+}
+
+/// How to write out the paths for a pen plotter.
+#[derive(Debug, Clone, Copy)]
+enum PlotterFormat {
+    /// As text, with one polyline per line.
+    Polylines,
+    /// As G-code, drawing at `feed_rate` millimetres per minute.
+    Gcode { feed_rate: f64 },
+}
+
+/// Writes the paths for drawing `input` with a pen plotter to `writer`, in `format`.
+fn write_plotter(
+    writer: &mut impl Write,
+    input: &impl GenericImageView,
+    settings: Settings,
+    mm_per_pixel: f64,
+    pen_width: f64,
+    format: PlotterFormat,
+) -> io::Result<()> {
+    match format {
+        PlotterFormat::Polylines => {
+            plotter::write_polylines(writer, input, settings, mm_per_pixel, pen_width)
+        }
+        PlotterFormat::Gcode { feed_rate } => {
+            plotter::write_gcode(writer, input, settings, mm_per_pixel, pen_width, feed_rate)
+        }
+    }
+}
+
+/// Returns the path to save the heatmap for an output saved to `path` to, which is `path` with
+/// its extension replaced by `.heatmap.png`.
+fn heatmap_path(path: &OsStr) -> PathBuf {
+    let path = Path::new(path);
+    let mut name = path.file_stem().unwrap_or_default().to_owned();
+    name.push(".heatmap.png");
+    path.with_file_name(name)
+}
+
+/// Parses a colour written as `#rrggbb`.
+fn parse_color(color: &str) -> anyhow::Result<Color> {
+    let invalid = || anyhow!("invalid colour (expected #rrggbb): {}", color);
+    let hex = color.strip_prefix('#').ok_or_else(invalid)?;
+    if hex.len() != 6 || !hex.is_ascii() {
+        return Err(invalid());
+    }
+    let channel = |i: usize| {
+        u8::from_str_radix(&hex[i..i + 2], 16)
+            .map(|value| value as f64 / 255.0)
+            .map_err(|_| invalid())
+    };
+    Ok(Color::rgb(channel(0)?, channel(2)?, channel(4)?))
+}
+
+/// Returns the value following the option `name`.
+fn value(args: &mut impl Iterator<Item = OsString>, name: &str) -> anyhow::Result<String> {
+    args.next()
+        .ok_or_else(|| anyhow!("expected a value after {}", name))?
+        .into_string()
+        .map_err(|value| anyhow!("invalid value for {}: {}", name, value.to_string_lossy()))
+}
 
 fn main() -> anyhow::Result<()> {
-    let [_, in_path, out_path]: [_; 3] = env::args_os()
-        .collect::<Vec<_>>()
+    let mut settings = Settings::default();
+    let mut stroke_width = None;
+    let mut text_size = None;
+    let mut charset = Charset::BoxDrawing;
+    let mut frames = false;
+    let mut heatmap = false;
+    let mut stats = false;
+    let mut plotter = false;
+    let mut mm_per_pixel = plotter::DEFAULT_MM_PER_PIXEL;
+    let mut feed_rate = plotter::DEFAULT_FEED_RATE;
+    let mut pen_width = plotter::DEFAULT_PEN_WIDTH;
+    let mut weight_path = None;
+    let mut mask_path = None;
+    let mut paths = Vec::new();
+
+    let mut args = env::args_os().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--fill") => {
+                settings.fill_mode = match value(&mut args, "--fill")?.as_str() {
+                    "lines" => FillMode::Lines,
+                    "mosaic" => FillMode::Mosaic,
+                    "checkerboard" => FillMode::Checkerboard,
+                    "ellipses" => FillMode::Ellipses,
+                    "filled-ellipses" => FillMode::FilledEllipses,
+                    "halftone" => FillMode::Halftone,
+                    "mondrian" => FillMode::Mondrian,
+                    "gradient" => FillMode::Gradient,
+                    other => bail!("unknown fill mode: {}", other),
+                }
+            }
+            Some("--shape") => {
+                settings.shape = match value(&mut args, "--shape")?.as_str() {
+                    "ellipse" => Shape::Ellipse,
+                    "rectangle" => Shape::Rectangle,
+                    other => bail!("unknown shape: {}", other),
+                }
+            }
+            Some("--seam-window") => {
+                let window = value(&mut args, "--seam-window")?;
+                settings.seam_window = window
+                    .parse()
+                    .with_context(|| format!("invalid seam window: {}", window))?;
+            }
+            Some("--seam-max-imbalance") => {
+                let imbalance = value(&mut args, "--seam-max-imbalance")?;
+                settings.seam_max_imbalance = imbalance
+                    .parse()
+                    .with_context(|| format!("invalid seam imbalance: {}", imbalance))?;
+            }
+            Some("--symmetry") => {
+                settings.symmetry = match value(&mut args, "--symmetry")?.as_str() {
+                    "none" => Symmetry::None,
+                    "left-right" => Symmetry::LeftRight,
+                    "four-way" => Symmetry::FourWay,
+                    other => bail!("unknown symmetry: {}", other),
+                }
+            }
+            Some("--split") => {
+                settings.split_strategy = match value(&mut args, "--split")?.as_str() {
+                    "longest-side" => SplitStrategy::LongestSide,
+                    "alternate" => SplitStrategy::Alternate,
+                    "balanced" => SplitStrategy::Balanced,
+                    "quad" => SplitStrategy::Quad,
+                    "diagonal" => SplitStrategy::Diagonal,
+                    "vertical-only" => SplitStrategy::VerticalOnly,
+                    "horizontal-only" => SplitStrategy::HorizontalOnly,
+                    other => bail!("unknown split strategy: {}", other),
+                }
+            }
+            Some("--edges") => {
+                let edges = value(&mut args, "--edges")?;
+                settings.edges = edges
+                    .parse()
+                    .with_context(|| format!("invalid edge weight: {}", edges))?;
+            }
+            Some("--smoothing") => {
+                let sigma = value(&mut args, "--smoothing")?;
+                settings.smoothing = Some(
+                    sigma
+                        .parse()
+                        .with_context(|| format!("invalid smoothing: {}", sigma))?,
+                );
+            }
+            Some("--channels") => {
+                settings.channels = match value(&mut args, "--channels")?.as_str() {
+                    "combined" => Channels::Combined,
+                    "separate" => Channels::Separate,
+                    other => bail!("unknown channel mode: {}", other),
+                }
+            }
+            Some("--border") => settings.border = true,
+            Some("--wrap") => settings.wrap = true,
+            Some("--overlay") => settings.overlay = true,
+            Some("--source-opacity") => {
+                let opacity = value(&mut args, "--source-opacity")?;
+                settings.source_opacity = opacity
+                    .parse()
+                    .with_context(|| format!("invalid source opacity: {}", opacity))?;
+            }
+            Some("--frames") => frames = true,
+            Some("--heatmap") => heatmap = true,
+            Some("--stats") => stats = true,
+            Some("--debug-labels") => settings.debug_labels = true,
+            Some("--plotter") => plotter = true,
+            Some("--mm-per-pixel") => {
+                let mm = value(&mut args, "--mm-per-pixel")?;
+                mm_per_pixel = mm
+                    .parse()
+                    .with_context(|| format!("invalid millimetres per pixel: {}", mm))?;
+            }
+            Some("--pen-width") => {
+                let width = value(&mut args, "--pen-width")?;
+                pen_width = width
+                    .parse()
+                    .with_context(|| format!("invalid pen width: {}", width))?;
+            }
+            Some("--feed-rate") => {
+                let rate = value(&mut args, "--feed-rate")?;
+                feed_rate = rate
+                    .parse()
+                    .with_context(|| format!("invalid feed rate: {}", rate))?;
+            }
+            Some("--snap") => settings.snap_to_pixels = true,
+            Some("--deterministic") => settings.deterministic = true,
+            Some("--shade-lines") => settings.shade_lines = true,
+            Some("--palette") => {
+                let palette = value(&mut args, "--palette")?;
+                settings.line_palette = palette
+                    .split(',')
+                    .map(parse_color)
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            Some("--fill-palette") => {
+                let palette = value(&mut args, "--fill-palette")?;
+                settings.fill_palette = palette
+                    .split(',')
+                    .map(parse_color)
+                    .collect::<anyhow::Result<_>>()?;
+            }
+            Some("--fill-probability") => {
+                let probability = value(&mut args, "--fill-probability")?;
+                settings.fill_probability = probability
+                    .parse()
+                    .with_context(|| format!("invalid fill probability: {}", probability))?;
+            }
+            Some("--seed") => {
+                let seed = value(&mut args, "--seed")?;
+                settings.seed = seed
+                    .parse()
+                    .with_context(|| format!("invalid seed: {}", seed))?;
+            }
+            Some("--key-color") => {
+                let color = value(&mut args, "--key-color")?;
+                settings.brightness = Brightness::DistanceTo(parse_color(&color)?);
+            }
+            Some("--equalize") => settings.equalize = true,
+            Some("--exposure") => {
+                let exposure = value(&mut args, "--exposure")?;
+                settings.exposure = exposure
+                    .parse()
+                    .with_context(|| format!("invalid exposure: {}", exposure))?;
+            }
+            Some("--threshold") => {
+                let threshold = value(&mut args, "--threshold")?;
+                settings.threshold = Some(
+                    threshold
+                        .parse()
+                        .with_context(|| format!("invalid threshold: {}", threshold))?,
+                );
+            }
+            Some("--num-rects") => {
+                let count = value(&mut args, "--num-rects")?;
+                settings.budget = RectBudget::Count(
+                    count
+                        .parse()
+                        .with_context(|| format!("invalid number of rectangles: {}", count))?,
+                );
+            }
+            Some("--max-rects") => {
+                let count = value(&mut args, "--max-rects")?;
+                settings.max_rects = Some(
+                    count
+                        .parse()
+                        .with_context(|| format!("invalid number of rectangles: {}", count))?,
+                );
+            }
+            Some("--max-aspect-ratio") => {
+                let ratio = value(&mut args, "--max-aspect-ratio")?;
+                settings.max_aspect_ratio = Some(
+                    ratio
+                        .parse()
+                        .with_context(|| format!("invalid aspect ratio: {}", ratio))?,
+                );
+            }
+            Some("--grid") => {
+                let grid = value(&mut args, "--grid")?;
+                let (spacing, offset) = match grid.split_once(',') {
+                    Some((spacing, offset)) => (spacing, Some(offset)),
+                    None => (grid.as_str(), None),
+                };
+                settings.grid = Some(
+                    spacing
+                        .parse()
+                        .with_context(|| format!("invalid grid spacing: {}", spacing))?,
+                );
+                if let Some(offset) = offset {
+                    let (x, y) = offset.split_once(',').ok_or_else(|| {
+                        anyhow!("expected an x and y offset for grid, got {}", grid)
+                    })?;
+                    settings.grid_offset = (
+                        x.parse()
+                            .with_context(|| format!("invalid grid offset: {}", x))?,
+                        y.parse()
+                            .with_context(|| format!("invalid grid offset: {}", y))?,
+                    );
+                }
+            }
+            Some("--variance-threshold") => {
+                let variance = value(&mut args, "--variance-threshold")?;
+                settings.variance_threshold = Some(
+                    variance
+                        .parse()
+                        .with_context(|| format!("invalid variance threshold: {}", variance))?,
+                );
+            }
+            Some("--min-leaf-darkness") => {
+                let darkness = value(&mut args, "--min-leaf-darkness")?;
+                settings.min_leaf_darkness = darkness
+                    .parse()
+                    .with_context(|| format!("invalid minimum leaf darkness: {}", darkness))?;
+            }
+            Some("--deadline") => {
+                let deadline = value(&mut args, "--deadline")?;
+                let milliseconds = deadline
+                    .parse()
+                    .with_context(|| format!("invalid deadline: {}", deadline))?;
+                settings.deadline = Some(Duration::from_millis(milliseconds));
+            }
+            Some("--roi") => {
+                let roi = value(&mut args, "--roi")?;
+                let sides = roi
+                    .split(',')
+                    .map(|side| side.parse())
+                    .collect::<Result<Vec<f64>, _>>()
+                    .with_context(|| format!("invalid region: {}", roi))?;
+                let [left, top, right, bottom]: [_; 4] = sides
+                    .try_into()
+                    .map_err(|_| anyhow!("expected 4 sides for region, got {}", roi))?;
+                settings.roi = Some(Rectangle {
+                    left,
+                    top,
+                    right,
+                    bottom,
+                });
+            }
+            Some("--scale") => {
+                let scale = value(&mut args, "--scale")?;
+                settings.scale = Some(
+                    scale
+                        .parse()
+                        .with_context(|| format!("invalid scale: {}", scale))?,
+                );
+            }
+            Some("--weight") => weight_path = Some(value(&mut args, "--weight")?),
+            Some("--mask") => mask_path = Some(value(&mut args, "--mask")?),
+            Some("--dashes") => {
+                let dashes = value(&mut args, "--dashes")?;
+                let lengths = dashes
+                    .split(',')
+                    .map(|length| length.parse())
+                    .collect::<Result<Vec<f64>, _>>()
+                    .with_context(|| format!("invalid dashes: {}", dashes))?;
+                let [dash, gap]: [_; 2] = lengths
+                    .try_into()
+                    .map_err(|_| anyhow!("expected a length and gap for dashes, got {}", dashes))?;
+                settings.line_style = LineStyle::Dashed { dash, gap };
+            }
+            Some("--quantize") => {
+                let levels = value(&mut args, "--quantize")?;
+                settings.fill_mode = FillMode::Quantized {
+                    levels: levels
+                        .parse()
+                        .with_context(|| format!("invalid number of levels: {}", levels))?,
+                };
+            }
+            Some("--halftone-radius") => {
+                let radius = value(&mut args, "--halftone-radius")?;
+                let fractions = radius
+                    .split(',')
+                    .map(|fraction| fraction.parse())
+                    .collect::<Result<Vec<f64>, _>>()
+                    .with_context(|| format!("invalid halftone radius: {}", radius))?;
+                let [min, max]: [_; 2] = fractions.try_into().map_err(|_| {
+                    anyhow!(
+                        "expected a minimum and maximum halftone radius, got {}",
+                        radius
+                    )
+                })?;
+                settings.halftone_radius = (min, max);
+            }
+            Some("--thickness-falloff") => {
+                let falloff = value(&mut args, "--thickness-falloff")?;
+                settings.thickness_falloff = falloff
+                    .parse()
+                    .with_context(|| format!("invalid thickness falloff: {}", falloff))?;
+            }
+            Some("--tone-match") => {
+                let strength = value(&mut args, "--tone-match")?;
+                settings.tone_match = true;
+                settings.tone_match_strength = strength
+                    .parse()
+                    .with_context(|| format!("invalid tone match strength: {}", strength))?;
+            }
+            Some("--dots") => {
+                let gap = value(&mut args, "--dots")?;
+                settings.line_style = LineStyle::Dotted {
+                    gap: gap
+                        .parse()
+                        .with_context(|| format!("invalid gap between dots: {}", gap))?,
+                };
+            }
+            Some("--padding") => {
+                let padding = value(&mut args, "--padding")?;
+                settings.padding = padding
+                    .parse()
+                    .with_context(|| format!("invalid padding: {}", padding))?;
+            }
+            Some("--corner-radius") => {
+                let radius = value(&mut args, "--corner-radius")?;
+                settings.corner_radius = radius
+                    .parse()
+                    .with_context(|| format!("invalid corner radius: {}", radius))?;
+            }
+            Some("--text-size") => {
+                let size = value(&mut args, "--text-size")?;
+                let lengths = size
+                    .split(',')
+                    .map(|length| length.parse())
+                    .collect::<Result<Vec<usize>, _>>()
+                    .with_context(|| format!("invalid text size: {}", size))?;
+                let [columns, rows]: [_; 2] = lengths.try_into().map_err(|_| {
+                    anyhow!("expected columns and rows for text size, got {}", size)
+                })?;
+                if columns < 2 || rows < 2 {
+                    bail!("text size must be at least 2x2, got {}", size);
+                }
+                text_size = Some((columns, rows));
+            }
+            Some("--ascii") => charset = Charset::Ascii,
+            Some("--stroke-width") => {
+                let width = value(&mut args, "--stroke-width")?;
+                stroke_width = Some(
+                    width
+                        .parse()
+                        .with_context(|| format!("invalid stroke width: {}", width))?,
+                );
+            }
+            Some(option) if option.starts_with("--") => bail!("unknown option: {}", option),
+            _ => paths.push(arg),
+        }
+    }
+
+    settings.validate()?;
+
+    let [in_path, out_path]: [_; 2] = paths
         .try_into()
-        .map_err(|vec: Vec<_>| anyhow!("expected 2 arguments, got {}", vec.len() - 1))?;
+        .map_err(|vec: Vec<_>| anyhow!("expected 2 arguments, got {}", vec.len()))?;
 
     let input = image::open(&in_path)
         .with_context(|| format!("failed to open {}", in_path.to_string_lossy()))?;
-    let mut output = GrayImage::new(input.width(), input.height());
+    let weight = match weight_path {
+        Some(path) => {
+            let weight = image::open(&path).with_context(|| format!("failed to open {}", path))?;
+            Some(weight.into_luma16())
+        }
+        None => None,
+    };
+
+    // `DynamicImage` always gives out 8-bit pixels, so images with more depth than that need to be
+    // converted to something that doesn't lose it. Floating-point images (like OpenEXR and HDR)
+    // are kept as floats, so that `--exposure` can bring back anything brighter than white.
+    let float_input = match input.color() {
+        ColorType::Rgb32F | ColorType::Rgba32F => Some(input.to_rgba32f()),
+        _ => None,
+    };
+    let deep_input = match input.color() {
+        ColorType::L8 | ColorType::La8 | ColorType::Rgb8 | ColorType::Rgba8 => None,
+        ColorType::Rgb32F | ColorType::Rgba32F => None,
+        _ => Some(input.to_rgba16()),
+    };
+
+    let is_gcode = Path::new(&out_path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("gcode"));
+    if is_gcode || plotter {
+        if weight.is_some() {
+            bail!("plotter output doesn't support --weight");
+        }
+        if mask_path.is_some() {
+            bail!("plotter output doesn't support --mask");
+        }
+        if frames {
+            bail!("plotter output doesn't support --frames");
+        }
+        if heatmap {
+            bail!("plotter output doesn't support --heatmap");
+        }
+        if stats {
+            bail!("plotter output doesn't support --stats");
+        }
+
+        let file = File::create(&out_path)
+            .with_context(|| format!("failed to create {}", out_path.to_string_lossy()))?;
+        let mut writer = BufWriter::new(file);
+        let format = match is_gcode {
+            true => PlotterFormat::Gcode { feed_rate },
+            false => PlotterFormat::Polylines,
+        };
+        match (&float_input, &deep_input) {
+            (Some(input), _) => write_plotter(
+                &mut writer,
+                input,
+                settings,
+                mm_per_pixel,
+                pen_width,
+                format,
+            ),
+            (None, Some(input)) => write_plotter(
+                &mut writer,
+                input,
+                settings,
+                mm_per_pixel,
+                pen_width,
+                format,
+            ),
+            (None, None) => write_plotter(
+                &mut writer,
+                &input,
+                settings,
+                mm_per_pixel,
+                pen_width,
+                format,
+            ),
+        }
+        .and_then(|()| writer.flush())
+        .context("failed to save output")?;
+        return Ok(());
+    }
+
+    let is_svg = Path::new(&out_path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("svg"));
+    if is_svg {
+        if settings.fill_mode != FillMode::Lines {
+            bail!("SVG output only supports --fill lines");
+        }
+        if weight.is_some() {
+            bail!("SVG output doesn't support --weight");
+        }
+        if mask_path.is_some() {
+            bail!("SVG output doesn't support --mask");
+        }
+        if settings.padding != 0.0 {
+            bail!("SVG output doesn't support --padding");
+        }
+        if frames {
+            bail!("SVG output doesn't support --frames");
+        }
+        if heatmap {
+            bail!("SVG output doesn't support --heatmap");
+        }
+        if stats {
+            bail!("SVG output doesn't support --stats");
+        }
+        if settings.overlay {
+            bail!("SVG output doesn't support --overlay");
+        }
+        if settings.thickness_falloff != 1.0 {
+            bail!("SVG output doesn't support --thickness-falloff");
+        }
+        if settings.tone_match {
+            bail!("SVG output doesn't support --tone-match");
+        }
+        if !settings.line_palette.is_empty() {
+            bail!("SVG output doesn't support --palette");
+        }
+
+        let file = File::create(&out_path)
+            .with_context(|| format!("failed to create {}", out_path.to_string_lossy()))?;
+        let mut writer = BufWriter::new(file);
+        let stroke_width = stroke_width.unwrap_or(settings.line_thickness as f64);
+        match (&float_input, &deep_input) {
+            (Some(input), _) => svg::write_svg(&mut writer, input, settings, stroke_width),
+            (None, Some(input)) => svg::write_svg(&mut writer, input, settings, stroke_width),
+            (None, None) => svg::write_svg(&mut writer, &input, settings, stroke_width),
+        }
+        .and_then(|()| writer.flush())
+        .context("failed to save output")?;
+        return Ok(());
+    }
+
+    let is_text = Path::new(&out_path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("txt"));
+    if is_text {
+        if weight.is_some() {
+            bail!("text output doesn't support --weight");
+        }
+        if mask_path.is_some() {
+            bail!("text output doesn't support --mask");
+        }
+        if frames {
+            bail!("text output doesn't support --frames");
+        }
+        if heatmap {
+            bail!("text output doesn't support --heatmap");
+        }
+        if stats {
+            bail!("text output doesn't support --stats");
+        }
+
+        // Characters are about twice as tall as they are wide.
+        let size = text_size.unwrap_or_else(|| {
+            let area = rects::region(&input, &settings);
+            let rows = match area.is_empty() {
+                true => 2,
+                false => (80.0 * area.height() / area.width() / 2.0).round() as usize,
+            };
+            (80, rows.max(2))
+        });
+        let text = match (&float_input, &deep_input) {
+            (Some(input), _) => text::rectanglify_text(input, settings, size, charset),
+            (None, Some(input)) => text::rectanglify_text(input, settings, size, charset),
+            (None, None) => text::rectanglify_text(&input, settings, size, charset),
+        };
+        if out_path == "-.txt" {
+            print!("{}", text);
+        } else {
+            std::fs::write(&out_path, text).context("failed to save output")?;
+        }
+        return Ok(());
+    }
+
+    let (width, height) = match settings.scale {
+        Some(scale) => rects::scaled_size(&input, scale),
+        None => input.dimensions(),
+    };
+
+    // Only write 16-bit output if the input has that much depth to preserve, and the output
+    // format can store it.
+    let deep_output = (float_input.is_some() || deep_input.is_some())
+        && matches!(
+            ImageFormat::from_path(&out_path),
+            Ok(ImageFormat::Png | ImageFormat::Tiff | ImageFormat::Pnm)
+        );
+    // Colour inputs keep their colour, mosaics are made out of the input's colours, separate
+    // channels are drawn into their own channels and palettes are presumably colourful, so they
+    // need a colour output.
+    let colour = matches!(settings.fill_mode, FillMode::Mosaic | FillMode::Mondrian)
+        || settings.channels == Channels::Separate
+        || !settings.line_palette.is_empty()
+        || input.color().has_color();
+    let size = (width, height);
+    let mask = match mask_path {
+        Some(path) => {
+            let mask = image::open(&path).with_context(|| format!("failed to open {}", path))?;
+            Some(mask.into_luma16())
+        }
+        None => None,
+    };
 
-    rectanglify(&input, &mut output, Settings::default());
+    if frames && (weight.is_some() || mask.is_some() || heatmap || stats || settings.overlay) {
+        bail!("--frames doesn't support --weight, --mask, --heatmap, --stats or --overlay");
+    }
+    let extras = Extras {
+        weight: weight.as_ref(),
+        mask: mask.as_ref(),
+        frames,
+        heatmap,
+        stats,
+    };
 
-    output.save(out_path).context("failed to save output")?;
+    if !(frames || heatmap || stats) && weight.is_none() && mask.is_none() {
+        let color = match (deep_output, colour) {
+            (true, true) => ColorType::Rgb16,
+            (true, false) => ColorType::L16,
+            (false, true) => ColorType::Rgb8,
+            (false, false) => ColorType::L8,
+        };
+        let (output, completion) = rectanglify_dynamic_as(&input, color, settings)?;
+        warn_if_truncated(completion);
+        return output.save(&out_path).context("failed to save output");
+    }
 
-    Ok(())
+    if let Some(input) = &float_input {
+        return match (deep_output, colour) {
+            (true, true) => render::<Rgb<u16>>(input, settings, size, &out_path, extras),
+            (true, false) => render::<Luma<u16>>(input, settings, size, &out_path, extras),
+            (false, true) => render::<Rgb<u8>>(input, settings, size, &out_path, extras),
+            (false, false) => render::<Luma<u8>>(input, settings, size, &out_path, extras),
+        };
+    }
+    match (&deep_input, deep_output, colour) {
+        (Some(input), true, true) => render::<Rgb<u16>>(input, settings, size, &out_path, extras),
+        (Some(input), true, false) => render::<Luma<u16>>(input, settings, size, &out_path, extras),
+        (Some(input), false, true) => render::<Rgb<u8>>(input, settings, size, &out_path, extras),
+        (Some(input), false, false) => render::<Luma<u8>>(input, settings, size, &out_path, extras),
+        (None, _, true) => render::<Rgb<u8>>(&input, settings, size, &out_path, extras),
+        (None, _, false) => render::<Luma<u8>>(&input, settings, size, &out_path, extras),
+    }
 }
@@ -0,0 +1,165 @@
+//! Writing the dividing lines out as paths for a pen plotter, instead of rasterising them.
+
+use std::io;
+use std::io::Write;
+
+use image::GenericImageView;
+
+use crate::rects::rectanglify_lines;
+use crate::rects::region;
+use crate::rects::Settings;
+
+pub const DEFAULT_MM_PER_PIXEL: f64 = 1.0;
+/// The default width of the pen's line, in millimetres.
+pub const DEFAULT_PEN_WIDTH: f64 = 0.5;
+/// The default speed to draw at in G-code, in millimetres per minute.
+pub const DEFAULT_FEED_RATE: f64 = 3000.0;
+
+/// The G-code for lifting the pen off the paper.
+const PEN_UP: &str = "G0 Z1";
+/// The G-code for putting the pen down on the paper.
+const PEN_DOWN: &str = "G1 Z0";
+
+/// Rectanglifies `input`, and returns the dividing lines (and the border, if `settings.border`
+/// is set and `settings.wrap` isn't) as polylines to draw in order, in millimetres with
+/// `mm_per_pixel` millimetres to each input pixel.
+///
+/// The origin is at the top left, with y going down, the same as the image. The lines come
+/// straight from the splits, and are put in order by always going to whichever end of the
+/// remaining lines is closest to where the pen is (starting from the origin), and drawing that
+/// line from there. Lines that start where the last one ended are joined onto it, so that the
+/// pen doesn't need lifting in between.
+///
+/// Wherever a line runs into another line (or the border), it's stopped half of `pen_width`
+/// millimetres short, at the edge of the other line's ink, so that the pen doesn't go over it
+/// twice.
+///
+/// `settings.line_style` and `settings.line_thickness` are ignored, since that's up to the pen.
+pub fn plotter_paths(
+    input: &impl GenericImageView,
+    settings: Settings,
+    mm_per_pixel: f64,
+    pen_width: f64,
+) -> Vec<Vec<(f64, f64)>> {
+    let settings = settings.sanitized();
+    let area = region(input, &settings);
+    let border = settings.border && !settings.wrap && !area.is_empty();
+    // Half the pen's width, in input pixels.
+    let trim = pen_width / mm_per_pixel / 2.0;
+    let mut paths: Vec<_> = rectanglify_lines(input, settings)
+        .into_iter()
+        .filter_map(|line| {
+            let [start, end] = line
+                .ends
+                .map(|end| if end.is_some() || border { trim } else { 0.0 });
+            line.trimmed(start, end)
+        })
+        .map(|line| vec![line.start, line.end])
+        .collect();
+    if border {
+        paths.push(vec![
+            (area.left, area.top),
+            (area.right, area.top),
+            (area.right, area.bottom),
+            (area.left, area.bottom),
+            (area.left, area.top),
+        ]);
+    }
+
+    let mut paths = order(paths);
+    for point in paths.iter_mut().flatten() {
+        *point = (point.0 * mm_per_pixel, point.1 * mm_per_pixel);
+    }
+    paths
+}
+
+/// Puts `paths` in order for drawing, as described in [`plotter_paths`].
+///
+/// This is quadratic in the number of paths, but that's still quick next to how long a plotter
+/// takes to draw them.
+fn order(mut paths: Vec<Vec<(f64, f64)>>) -> Vec<Vec<(f64, f64)>> {
+    let distance = |(x1, y1): (f64, f64), (x2, y2): (f64, f64)| (x2 - x1).hypot(y2 - y1);
+
+    let mut ordered: Vec<Vec<(f64, f64)>> = Vec::with_capacity(paths.len());
+    let mut position = (0.0, 0.0);
+    while !paths.is_empty() {
+        // The index of the closest path, and whether it needs drawing backwards to start at
+        // the end that's closest.
+        let (index, reversed, _) = paths
+            .iter()
+            .enumerate()
+            .flat_map(|(i, path)| {
+                [
+                    (i, false, distance(position, path[0])),
+                    (i, true, distance(position, path[path.len() - 1])),
+                ]
+            })
+            .min_by(|a, b| a.2.total_cmp(&b.2))
+            .unwrap();
+
+        let mut path = paths.swap_remove(index);
+        if reversed {
+            path.reverse();
+        }
+        position = path[path.len() - 1];
+        match ordered.last_mut() {
+            Some(last) if last.last() == path.first() => last.extend_from_slice(&path[1..]),
+            _ => ordered.push(path),
+        }
+    }
+
+    ordered
+}
+
+/// Rectanglifies `input`, writing the paths from [`plotter_paths`] to `writer` as text, with one
+/// polyline per line, written as space-separated `x,y` points in millimetres.
+pub fn write_polylines(
+    writer: &mut impl Write,
+    input: &impl GenericImageView,
+    settings: Settings,
+    mm_per_pixel: f64,
+    pen_width: f64,
+) -> io::Result<()> {
+    let settings = settings.sanitized();
+    for path in plotter_paths(input, settings, mm_per_pixel, pen_width) {
+        let points: Vec<_> = path.iter().map(|(x, y)| format!("{x},{y}")).collect();
+        writeln!(writer, "{}", points.join(" "))?;
+    }
+
+    Ok(())
+}
+
+/// Rectanglifies `input`, writing the paths from [`plotter_paths`] to `writer` as G-code.
+///
+/// It's kept basic so that it works with as many plotters as possible: coordinates are absolute
+/// and in millimetres, the pen is lifted and lowered by moving to Z 1 and Z 0, and it moves with
+/// `G0` with the pen up and `G1` at `feed_rate` millimetres per minute with it down. Unlike
+/// [`plotter_paths`], the origin is at the bottom left, as is usual for G-code, so the y
+/// coordinates are flipped.
+pub fn write_gcode(
+    writer: &mut impl Write,
+    input: &impl GenericImageView,
+    settings: Settings,
+    mm_per_pixel: f64,
+    pen_width: f64,
+    feed_rate: f64,
+) -> io::Result<()> {
+    let settings = settings.sanitized();
+    let height = input.height() as f64 * mm_per_pixel;
+
+    writeln!(writer, "G21")?;
+    writeln!(writer, "G90")?;
+    writeln!(writer, "{PEN_UP}")?;
+    for path in plotter_paths(input, settings, mm_per_pixel, pen_width) {
+        let (x, y) = path[0];
+        writeln!(writer, "G0 X{x:.3} Y{:.3}", height - y)?;
+        writeln!(writer, "{PEN_DOWN}")?;
+        for &(x, y) in &path[1..] {
+            writeln!(writer, "G1 X{x:.3} Y{:.3} F{feed_rate}", height - y)?;
+        }
+        writeln!(writer, "{PEN_UP}")?;
+    }
+    writeln!(writer, "G0 X0 Y0")?;
+
+    Ok(())
+}
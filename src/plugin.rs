@@ -1,32 +1,152 @@
 use gst::glib;
-use gst::gst_info;
 use gst::prelude::*;
 use gst::subclass::prelude::*;
 use gst::BufferRef;
+use gst::{gst_debug, gst_error, gst_info};
 use gst_base::subclass::prelude::*;
 use gst_video::subclass::prelude::*;
 use gst_video::VideoFormat;
 use gst_video::VideoFrameRef;
-use image::ImageBuffer;
 use image::Luma;
 use image::Pixel;
 use image::Rgb;
 use image::Rgba;
 
-use std::i32;
 use std::ops::Deref;
 use std::sync::Mutex;
+use std::time::Duration;
+use std::time::Instant;
 
 use once_cell::sync::Lazy;
 
-use crate::rects::rectanglify;
+use crate::rects;
+use crate::rects::Color;
+use crate::rects::Completion;
+use crate::rects::RectBudget;
+use crate::rects::Rectanglifier;
 use crate::rects::Settings;
+use crate::rects::SettingsBuilder;
+use crate::rects::SettingsError;
+use crate::rects::StridedImage;
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRectanglifyFillMode")]
+pub enum FillMode {
+    #[enum_value(name = "Lines: only draw the dividing lines", nick = "lines")]
+    Lines = 0,
+    #[enum_value(
+        name = "Mosaic: fill each rectangle with the average colour it covers",
+        nick = "mosaic"
+    )]
+    Mosaic = 1,
+    #[enum_value(
+        name = "Checkerboard: fill rectangles alternately with the line and background colours",
+        nick = "checkerboard"
+    )]
+    Checkerboard = 2,
+    #[enum_value(
+        name = "Ellipses: outline the ellipse inside each rectangle instead of drawing lines",
+        nick = "ellipses"
+    )]
+    Ellipses = 3,
+    #[enum_value(
+        name = "Filled ellipses: fill the ellipse inside each rectangle instead of drawing lines",
+        nick = "filled-ellipses"
+    )]
+    FilledEllipses = 4,
+    #[enum_value(
+        name = "Halftone: draw a dot in each rectangle sized by how dark it is instead of drawing lines",
+        nick = "halftone"
+    )]
+    Halftone = 5,
+    #[enum_value(
+        name = "Mondrian: fill a random few of the rectangles with primary colours",
+        nick = "mondrian"
+    )]
+    Mondrian = 6,
+    #[enum_value(
+        name = "Gradient: fill each rectangle with a gradient following how its darkness changes",
+        nick = "gradient"
+    )]
+    Gradient = 7,
+}
+
+impl From<FillMode> for rects::FillMode {
+    fn from(mode: FillMode) -> Self {
+        match mode {
+            FillMode::Lines => rects::FillMode::Lines,
+            FillMode::Mosaic => rects::FillMode::Mosaic,
+            FillMode::Checkerboard => rects::FillMode::Checkerboard,
+            FillMode::Ellipses => rects::FillMode::Ellipses,
+            FillMode::FilledEllipses => rects::FillMode::FilledEllipses,
+            FillMode::Halftone => rects::FillMode::Halftone,
+            FillMode::Mondrian => rects::FillMode::Mondrian,
+            FillMode::Gradient => rects::FillMode::Gradient,
+        }
+    }
+}
+
+impl From<rects::FillMode> for FillMode {
+    fn from(mode: rects::FillMode) -> Self {
+        match mode {
+            rects::FillMode::Lines => FillMode::Lines,
+            rects::FillMode::Mosaic => FillMode::Mosaic,
+            rects::FillMode::Checkerboard => FillMode::Checkerboard,
+            rects::FillMode::Ellipses => FillMode::Ellipses,
+            rects::FillMode::FilledEllipses => FillMode::FilledEllipses,
+            rects::FillMode::Halftone => FillMode::Halftone,
+            rects::FillMode::Mondrian => FillMode::Mondrian,
+            rects::FillMode::Gradient => FillMode::Gradient,
+            // The element only ever sets the fill mode through the fill-mode property.
+            rects::FillMode::Quantized { .. } => unreachable!(),
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Hash, Clone, Copy, glib::Enum)]
+#[repr(u32)]
+#[enum_type(name = "GstRectanglifyShape")]
+pub enum Shape {
+    #[enum_value(name = "Rectangle: outline or fill a rectangle", nick = "rect")]
+    Rect = 0,
+    #[enum_value(name = "Ellipse: outline or fill an ellipse", nick = "ellipse")]
+    Ellipse = 1,
+}
+
+impl From<Shape> for rects::Shape {
+    fn from(shape: Shape) -> Self {
+        match shape {
+            Shape::Rect => rects::Shape::Rectangle,
+            Shape::Ellipse => rects::Shape::Ellipse,
+        }
+    }
+}
+
+impl From<rects::Shape> for Shape {
+    fn from(shape: rects::Shape) -> Self {
+        match shape {
+            rects::Shape::Rectangle => Shape::Rect,
+            rects::Shape::Ellipse => Shape::Ellipse,
+        }
+    }
+}
 
 #[derive(Default)]
 pub struct Rectanglify {
     settings: Mutex<Settings>,
+    /// Keeps track of the previous frame, so the rectangles don't jump around. This also holds
+    /// the hysteresis and coherence-threshold properties.
+    rectanglifier: Mutex<Rectanglifier>,
+    /// A moving average of how long each frame has taken to transform, for the avg-frame-time
+    /// property, or `None` before the first frame.
+    avg_frame_time: Mutex<Option<Duration>>,
 }
 
+/// How much each frame counts towards avg-frame-time, so that it catches up with a change to the
+/// settings within a few dozen frames.
+const FRAME_TIME_WEIGHT: f64 = 1.0 / 16.0;
+
 static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
     gst::DebugCategory::new(
         "rectanglify",
@@ -42,18 +162,212 @@ impl ObjectSubclass for Rectanglify {
     type ParentType = gst_video::VideoFilter;
 }
 
+fn rects_per_pixel_of(settings: &Settings) -> f64 {
+    match settings.budget {
+        RectBudget::PerDarkness(rects_per_pixel) => rects_per_pixel,
+        // The element only ever sets the budget through rects-per-pixel.
+        RectBudget::Count(_) => unreachable!(),
+    }
+}
+
+/// Returns the settings from `builder`, or logs why they're invalid and returns `None`.
+///
+/// All changes to the settings go through this, so that invalid values get turned away when the
+/// property is set rather than failing in the middle of a frame.
+fn build_settings(
+    obj: &super::Rectanglify,
+    property: &str,
+    builder: SettingsBuilder,
+) -> Option<Settings> {
+    match builder.build() {
+        Ok(settings) => Some(settings),
+        Err(error) => {
+            gst_error!(CAT, obj: obj, "Invalid {}: {}", property, error);
+            None
+        }
+    }
+}
+
+/// Returns the max-rects property, which is 0 for no limit.
+fn max_rects_of(settings: &Settings) -> u64 {
+    settings.max_rects.map_or(0, |max_rects| max_rects as u64)
+}
+
+/// Returns the deadline property in milliseconds, which is 0 for no deadline.
+fn deadline_of(settings: &Settings) -> u64 {
+    settings.deadline.map_or(0, |deadline| {
+        deadline.as_millis().try_into().unwrap_or(u64::MAX)
+    })
+}
+
+/// Returns the source-opacity property, which is 0 unless the frame is overlaid.
+fn source_opacity_of(settings: &Settings) -> f64 {
+    if settings.overlay {
+        settings.source_opacity
+    } else {
+        0.0
+    }
+}
+
+/// Converts a colour from the `0xAARRGGBB` form the background-color property uses.
+fn color_from_argb(argb: u32) -> Color {
+    let channel = |shift: u32| ((argb >> shift) & 0xff) as f64 / 255.0;
+    Color {
+        r: channel(16),
+        g: channel(8),
+        b: channel(0),
+        a: channel(24),
+    }
+}
+
+/// Converts a colour into the `0xAARRGGBB` form the background-color property uses.
+fn argb_of(color: Color) -> u32 {
+    let channel =
+        |value: f64, shift: u32| ((value.clamp(0.0, 1.0) * 255.0).round() as u32) << shift;
+    channel(color.a, 24) | channel(color.r, 16) | channel(color.g, 8) | channel(color.b, 0)
+}
+
+/// Returns the blur-radius property, which is 0 for no blur.
+fn blur_radius_of(settings: &Settings) -> u32 {
+    settings
+        .smoothing
+        .map_or(0, |sigma| (sigma * 3.0).round() as u32)
+}
+
 impl ObjectImpl for Rectanglify {
     fn properties() -> &'static [glib::ParamSpec] {
         static PROPERTIES: Lazy<Vec<glib::ParamSpec>> = Lazy::new(|| {
-            vec![glib::ParamSpecDouble::new(
-                "rects-per-pixel",
-                "Rectangles per black pixel",
-                "The number of rectangles drawn for 1 black pixel's worth of darkness",
-                0.0,
-                f64::MAX,
-                0.0001,
-                glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
-            )]
+            vec![
+                glib::ParamSpecDouble::new(
+                    "rects-per-pixel",
+                    "Rectangles per black pixel",
+                    "The number of rectangles drawn for 1 black pixel's worth of darkness",
+                    0.0,
+                    f64::MAX,
+                    0.0001,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt64::new(
+                    "max-rects",
+                    "Maximum rectangles",
+                    "The most rectangles to draw in a frame, however dark it is (0 = no limit)",
+                    0,
+                    u64::MAX,
+                    0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt64::new(
+                    "deadline",
+                    "Deadline",
+                    "How long (in milliseconds) to spend splitting each frame before drawing it as it is (0 = no limit)",
+                    0,
+                    u64::MAX,
+                    0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecEnum::new(
+                    "fill-mode",
+                    "Fill mode",
+                    "What to draw inside each rectangle",
+                    FillMode::static_type(),
+                    FillMode::Lines as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecEnum::new(
+                    "shape",
+                    "Shape",
+                    "The shape drawn inside each rectangle with the ellipses and halftone fill modes",
+                    Shape::static_type(),
+                    Shape::Ellipse as i32,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "dot-max-coverage",
+                    "Maximum dot coverage",
+                    "How much of each rectangle the darkest halftone dots cover, as a fraction of the largest dot that fits",
+                    0.0,
+                    1.0,
+                    1.0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "edge-weight",
+                    "Edge weight",
+                    "How much to put the rectangles where the frame has edges instead of where it's dark",
+                    0.0,
+                    1.0,
+                    0.0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "blur-radius",
+                    "Blur radius",
+                    "How far (in pixels) to blur the frame before working out where the splits go, so they don't follow noise (0 = no blur)",
+                    0,
+                    32,
+                    0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt::new(
+                    "background-color",
+                    "Background color",
+                    "The colour (as 0xAARRGGBB) to fill the frame with before drawing, which can be transparent for compositing (only has alpha in RGBA)",
+                    0,
+                    u32::MAX,
+                    0xffffffff,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "source-opacity",
+                    "Source opacity",
+                    "How much of the original frame shows through underneath the lines, composited over the background (0 = none of it)",
+                    0.0,
+                    1.0,
+                    0.0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecBoolean::new(
+                    "transparent-background",
+                    "Transparent background",
+                    "Whether to leave the background transparent instead of white, for compositing over something else (only in RGBA)",
+                    false,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecBoolean::new(
+                    "preserve-alpha",
+                    "Preserve alpha",
+                    "Whether to keep the alpha channel of the input, so the output has the same silhouette, instead of the alpha from the background, source-opacity and lines (only in RGBA)",
+                    false,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "hysteresis",
+                    "Hysteresis",
+                    "How close (in pixels) a split has to be to where it was in the previous frame to be left there",
+                    0.0,
+                    f64::MAX,
+                    0.0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecDouble::new(
+                    "coherence-threshold",
+                    "Coherence threshold",
+                    "How much the darkness of an area has to change by (as a fraction) to be split again rather than reusing the previous frame's splits",
+                    0.0,
+                    f64::MAX,
+                    0.0,
+                    glib::ParamFlags::READWRITE | gst::PARAM_FLAG_MUTABLE_PLAYING,
+                ),
+                glib::ParamSpecUInt64::new(
+                    "avg-frame-time",
+                    "Average frame time",
+                    "How long (in nanoseconds) the last few frames have taken to transform on average (0 before the first frame)",
+                    0,
+                    u64::MAX,
+                    0,
+                    glib::ParamFlags::READABLE,
+                ),
+            ]
         });
 
         PROPERTIES.as_ref()
@@ -70,14 +384,270 @@ impl ObjectImpl for Rectanglify {
             "rects-per-pixel" => {
                 let mut settings = self.settings.lock().unwrap();
                 let rects_per_pixel = value.get().expect("type checked upstream");
+                let builder = settings
+                    .clone()
+                    .into_builder()
+                    .budget(RectBudget::PerDarkness(rects_per_pixel));
+                let new_settings = match build_settings(obj, "rects-per-pixel", builder) {
+                    Some(new_settings) => new_settings,
+                    None => return,
+                };
                 gst_info!(
                     CAT,
                     obj: obj,
                     "Changing rects-per-pixel from {} to {}",
-                    settings.rects_per_pixel,
+                    rects_per_pixel_of(&settings),
                     rects_per_pixel
                 );
-                settings.rects_per_pixel = rects_per_pixel;
+                *settings = new_settings;
+            }
+            "max-rects" => {
+                let mut settings = self.settings.lock().unwrap();
+                let max_rects: u64 = value.get().expect("type checked upstream");
+                let builder = settings.clone().into_builder().max_rects(match max_rects {
+                    0 => None,
+                    max_rects => Some(max_rects.try_into().unwrap_or(usize::MAX)),
+                });
+                let new_settings = match build_settings(obj, "max-rects", builder) {
+                    Some(new_settings) => new_settings,
+                    None => return,
+                };
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing max-rects from {} to {}",
+                    max_rects_of(&settings),
+                    max_rects
+                );
+                *settings = new_settings;
+            }
+            "deadline" => {
+                let mut settings = self.settings.lock().unwrap();
+                let deadline: u64 = value.get().expect("type checked upstream");
+                let builder = settings.clone().into_builder().deadline(match deadline {
+                    0 => None,
+                    deadline => Some(Duration::from_millis(deadline)),
+                });
+                let new_settings = match build_settings(obj, "deadline", builder) {
+                    Some(new_settings) => new_settings,
+                    None => return,
+                };
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing deadline from {} to {}",
+                    deadline_of(&settings),
+                    deadline
+                );
+                *settings = new_settings;
+            }
+            "fill-mode" => {
+                let mut settings = self.settings.lock().unwrap();
+                let fill_mode = value.get::<FillMode>().expect("type checked upstream");
+                let builder = settings.clone().into_builder().fill_mode(fill_mode.into());
+                let new_settings = match build_settings(obj, "fill-mode", builder) {
+                    Some(new_settings) => new_settings,
+                    None => return,
+                };
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing fill-mode from {:?} to {:?}",
+                    FillMode::from(settings.fill_mode),
+                    fill_mode
+                );
+                *settings = new_settings;
+            }
+            "shape" => {
+                let mut settings = self.settings.lock().unwrap();
+                let shape = value.get::<Shape>().expect("type checked upstream");
+                let builder = settings.clone().into_builder().shape(shape.into());
+                let new_settings = match build_settings(obj, "shape", builder) {
+                    Some(new_settings) => new_settings,
+                    None => return,
+                };
+                if !matches!(
+                    settings.fill_mode,
+                    rects::FillMode::Ellipses | rects::FillMode::Halftone
+                ) {
+                    gst_debug!(
+                        CAT,
+                        obj: obj,
+                        "shape only affects the ellipses and halftone fill modes"
+                    );
+                }
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing shape from {:?} to {:?}",
+                    Shape::from(settings.shape),
+                    shape
+                );
+                *settings = new_settings;
+            }
+            "dot-max-coverage" => {
+                let mut settings = self.settings.lock().unwrap();
+                let coverage = value.get().expect("type checked upstream");
+                let (min_radius, max_radius) = settings.halftone_radius;
+                // Keep the smallest dots from outgrowing the largest ones.
+                let builder = settings
+                    .clone()
+                    .into_builder()
+                    .halftone_radius((min_radius.min(coverage), coverage));
+                let new_settings = match build_settings(obj, "dot-max-coverage", builder) {
+                    Some(new_settings) => new_settings,
+                    None => return,
+                };
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing dot-max-coverage from {} to {}",
+                    max_radius,
+                    coverage
+                );
+                *settings = new_settings;
+            }
+            "edge-weight" => {
+                let mut settings = self.settings.lock().unwrap();
+                let edges = value.get().expect("type checked upstream");
+                let builder = settings.clone().into_builder().edges(edges);
+                let new_settings = match build_settings(obj, "edge-weight", builder) {
+                    Some(new_settings) => new_settings,
+                    None => return,
+                };
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing edge-weight from {} to {}",
+                    settings.edges,
+                    edges
+                );
+                *settings = new_settings;
+            }
+            "blur-radius" => {
+                let mut settings = self.settings.lock().unwrap();
+                let radius: u32 = value.get().expect("type checked upstream");
+                // The blur reaches 3 standard deviations either side of each pixel.
+                let builder = settings.clone().into_builder().smoothing(match radius {
+                    0 => None,
+                    radius => Some(radius as f64 / 3.0),
+                });
+                let new_settings = match build_settings(obj, "blur-radius", builder) {
+                    Some(new_settings) => new_settings,
+                    None => return,
+                };
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing blur-radius from {} to {}",
+                    blur_radius_of(&settings),
+                    radius
+                );
+                *settings = new_settings;
+            }
+            "background-color" => {
+                let mut settings = self.settings.lock().unwrap();
+                let argb: u32 = value.get().expect("type checked upstream");
+                let builder = settings
+                    .clone()
+                    .into_builder()
+                    .background_color(color_from_argb(argb));
+                let new_settings = match build_settings(obj, "background-color", builder) {
+                    Some(new_settings) => new_settings,
+                    None => return,
+                };
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing background-color from {:#010x} to {:#010x}",
+                    argb_of(settings.background_color),
+                    argb
+                );
+                *settings = new_settings;
+            }
+            "source-opacity" => {
+                let mut settings = self.settings.lock().unwrap();
+                let opacity: f64 = value.get().expect("type checked upstream");
+                // Overlaying the frame at an opacity of 0 is the same as not overlaying it, only
+                // slower.
+                let builder = settings
+                    .clone()
+                    .into_builder()
+                    .overlay(opacity > 0.0)
+                    .source_opacity(opacity);
+                let new_settings = match build_settings(obj, "source-opacity", builder) {
+                    Some(new_settings) => new_settings,
+                    None => return,
+                };
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing source-opacity from {} to {}",
+                    source_opacity_of(&settings),
+                    opacity
+                );
+                *settings = new_settings;
+            }
+            "transparent-background" => {
+                let mut settings = self.settings.lock().unwrap();
+                let transparent = value.get().expect("type checked upstream");
+                let builder = settings
+                    .clone()
+                    .into_builder()
+                    .transparent_background(transparent);
+                let new_settings = match build_settings(obj, "transparent-background", builder) {
+                    Some(new_settings) => new_settings,
+                    None => return,
+                };
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing transparent-background from {} to {}",
+                    settings.transparent_background,
+                    transparent
+                );
+                *settings = new_settings;
+            }
+            "preserve-alpha" => {
+                let mut settings = self.settings.lock().unwrap();
+                let preserve = value.get().expect("type checked upstream");
+                let builder = settings.clone().into_builder().preserve_alpha(preserve);
+                let new_settings = match build_settings(obj, "preserve-alpha", builder) {
+                    Some(new_settings) => new_settings,
+                    None => return,
+                };
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing preserve-alpha from {} to {}",
+                    settings.preserve_alpha,
+                    preserve
+                );
+                *settings = new_settings;
+            }
+            "hysteresis" => {
+                let mut rectanglifier = self.rectanglifier.lock().unwrap();
+                let hysteresis = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing hysteresis from {} to {}",
+                    rectanglifier.hysteresis,
+                    hysteresis
+                );
+                rectanglifier.hysteresis = hysteresis;
+            }
+            "coherence-threshold" => {
+                let mut rectanglifier = self.rectanglifier.lock().unwrap();
+                let threshold = value.get().expect("type checked upstream");
+                gst_info!(
+                    CAT,
+                    obj: obj,
+                    "Changing coherence-threshold from {} to {}",
+                    rectanglifier.threshold,
+                    threshold
+                );
+                rectanglifier.threshold = threshold;
             }
             _ => unimplemented!(),
         }
@@ -89,7 +659,64 @@ impl ObjectImpl for Rectanglify {
         match pspec.name() {
             "rects-per-pixel" => {
                 let settings = self.settings.lock().unwrap();
-                settings.rects_per_pixel.to_value()
+                rects_per_pixel_of(&settings).to_value()
+            }
+            "max-rects" => {
+                let settings = self.settings.lock().unwrap();
+                max_rects_of(&settings).to_value()
+            }
+            "deadline" => {
+                let settings = self.settings.lock().unwrap();
+                deadline_of(&settings).to_value()
+            }
+            "fill-mode" => {
+                let settings = self.settings.lock().unwrap();
+                FillMode::from(settings.fill_mode).to_value()
+            }
+            "shape" => {
+                let settings = self.settings.lock().unwrap();
+                Shape::from(settings.shape).to_value()
+            }
+            "dot-max-coverage" => {
+                let settings = self.settings.lock().unwrap();
+                settings.halftone_radius.1.to_value()
+            }
+            "edge-weight" => {
+                let settings = self.settings.lock().unwrap();
+                settings.edges.to_value()
+            }
+            "blur-radius" => {
+                let settings = self.settings.lock().unwrap();
+                blur_radius_of(&settings).to_value()
+            }
+            "background-color" => {
+                let settings = self.settings.lock().unwrap();
+                argb_of(settings.background_color).to_value()
+            }
+            "source-opacity" => {
+                let settings = self.settings.lock().unwrap();
+                source_opacity_of(&settings).to_value()
+            }
+            "transparent-background" => {
+                let settings = self.settings.lock().unwrap();
+                settings.transparent_background.to_value()
+            }
+            "preserve-alpha" => {
+                let settings = self.settings.lock().unwrap();
+                settings.preserve_alpha.to_value()
+            }
+            "hysteresis" => {
+                let rectanglifier = self.rectanglifier.lock().unwrap();
+                rectanglifier.hysteresis.to_value()
+            }
+            "coherence-threshold" => {
+                let rectanglifier = self.rectanglifier.lock().unwrap();
+                rectanglifier.threshold.to_value()
+            }
+            "avg-frame-time" => {
+                let avg_frame_time = self.avg_frame_time.lock().unwrap();
+                let nanos = avg_frame_time.map_or(0, |time| time.as_nanos());
+                nanos.try_into().unwrap_or(u64::MAX).to_value()
             }
             _ => unimplemented!(),
         }
@@ -162,6 +789,40 @@ impl BaseTransformImpl for Rectanglify {
     const PASSTHROUGH_ON_SAME_CAPS: bool = false;
     const TRANSFORM_IP_ON_PASSTHROUGH: bool = false;
 
+    // Called when starting, so we can initialize any per-stream state.
+    // Anything set up here needs to be dropped in `stop` so that nothing
+    // survives a READY→NULL→READY cycle.
+    fn start(&self, element: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        self.rectanglifier.lock().unwrap().reset();
+        gst_info!(CAT, obj: element, "Started");
+        Ok(())
+    }
+
+    fn stop(&self, element: &Self::Type) -> Result<(), gst::ErrorMessage> {
+        self.rectanglifier.lock().unwrap().reset();
+        *self.avg_frame_time.lock().unwrap() = None;
+        gst_info!(CAT, obj: element, "Stopped");
+        Ok(())
+    }
+
+    fn sink_event(&self, element: &Self::Type, event: gst::Event) -> bool {
+        match event.view() {
+            // After a flush or a new segment, the next frame doesn't follow on from the last one,
+            // so its splits shouldn't be reused.
+            gst::EventView::FlushStop(_) | gst::EventView::Segment(_) => {
+                gst_debug!(
+                    CAT,
+                    obj: element,
+                    "Forgetting the previous frame after {:?}",
+                    event.type_()
+                );
+                self.rectanglifier.lock().unwrap().reset();
+            }
+            _ => {}
+        }
+        self.parent_sink_event(element, event)
+    }
+
     fn transform_caps(
         &self,
         _: &Self::Type,
@@ -180,33 +841,53 @@ impl BaseTransformImpl for Rectanglify {
 }
 
 impl VideoFilterImpl for Rectanglify {
+    fn set_info(
+        &self,
+        element: &Self::Type,
+        incaps: &gst::Caps,
+        in_info: &gst_video::VideoInfo,
+        outcaps: &gst::Caps,
+        out_info: &gst_video::VideoInfo,
+    ) -> Result<(), gst::LoggableError> {
+        // The previous frame's splits don't mean anything for a different size of frame.
+        self.rectanglifier.lock().unwrap().reset();
+        self.parent_set_info(element, incaps, in_info, outcaps, out_info)
+    }
+
     fn transform_frame(
         &self,
-        _: &Self::Type,
+        element: &Self::Type,
         input: &VideoFrameRef<&BufferRef>,
         output: &mut VideoFrameRef<&mut BufferRef>,
     ) -> Result<gst::FlowSuccess, gst::FlowError> {
-        let settings = *self.settings.lock().unwrap();
+        let start = Instant::now();
+        let settings = self.settings.lock().unwrap().clone();
+        let mut rectanglifier = self.rectanglifier.lock().unwrap();
+        let mut completion = Ok(Completion::Complete);
 
         // This stupid trait is needed because we can't make generic callbacks.
         trait FormatCb<C> {
-            fn call(self, image: ImageBuffer<impl Pixel<Subpixel = u8>, C>);
+            fn call(self, image: StridedImage<impl Pixel<Subpixel = u8>, C>) -> Option<()>;
         }
 
+        /// Returns `None` if the frame's stride doesn't fit its data, which can only happen with
+        /// negative strides (flipped frames) or a broken upstream element.
         fn with_image<C: Deref<Target = [u8]>>(
             width: u32,
             height: u32,
+            stride: i32,
             format: VideoFormat,
             container: C,
             callback: impl FormatCb<C>,
-        ) {
+        ) -> Option<()> {
+            let stride = usize::try_from(stride).ok()?;
             macro_rules! formats {
                 ($($gst:ident => $image:ty,)*) => {
                     match format {
                         $(
                         VideoFormat::$gst => {
-                            let image = ImageBuffer::<$image, C>::from_raw(width, height, container).unwrap();
-                            callback.call(image);
+                            let image = StridedImage::<$image, C>::from_raw(width, height, stride, container)?;
+                            callback.call(image)
                         }
                         )*
                         _ => unimplemented!(),
@@ -223,34 +904,103 @@ impl VideoFilterImpl for Rectanglify {
             }
         }
 
-        with_image(
+        let fits = with_image(
             input.width(),
             input.height(),
+            input.plane_stride()[0],
             input.format(),
             input.plane_data(0).unwrap(),
-            (settings, output),
+            (settings, &mut *rectanglifier, output, &mut completion),
         );
+        if fits.is_none() {
+            gst::element_error!(
+                element,
+                gst::StreamError::Format,
+                ("Frame stride doesn't fit its data")
+            );
+            return Err(gst::FlowError::Error);
+        }
 
-        impl FormatCb<&[u8]> for (Settings, &mut VideoFrameRef<&mut BufferRef>) {
-            fn call(self, input: ImageBuffer<impl Pixel<Subpixel = u8>, &[u8]>) {
-                let (settings, output) = self;
+        impl FormatCb<&[u8]>
+            for (
+                Settings,
+                &mut Rectanglifier,
+                &mut VideoFrameRef<&mut BufferRef>,
+                &mut Result<Completion, SettingsError>,
+            )
+        {
+            fn call(self, input: StridedImage<impl Pixel<Subpixel = u8>, &[u8]>) -> Option<()> {
+                let (settings, rectanglifier, output, completion) = self;
                 with_image(
                     output.width(),
                     output.height(),
+                    output.plane_stride()[0],
                     output.format(),
                     output.plane_data_mut(0).unwrap(),
-                    (settings, input),
-                );
+                    (settings, rectanglifier, input, completion),
+                )
             }
         }
 
-        impl<P: Pixel<Subpixel = u8>> FormatCb<&mut [u8]> for (Settings, ImageBuffer<P, &[u8]>) {
-            fn call(self, mut output: ImageBuffer<impl Pixel<Subpixel = u8>, &mut [u8]>) {
-                let (settings, input) = self;
-                rectanglify(&input, &mut output, settings)
+        impl<P: Pixel<Subpixel = u8>> FormatCb<&mut [u8]>
+            for (
+                Settings,
+                &mut Rectanglifier,
+                StridedImage<P, &[u8]>,
+                &mut Result<Completion, SettingsError>,
+            )
+        {
+            fn call(
+                self,
+                mut output: StridedImage<impl Pixel<Subpixel = u8>, &mut [u8]>,
+            ) -> Option<()> {
+                let (settings, rectanglifier, input, completion) = self;
+                *completion = rectanglifier.rectanglify(&input, &mut output, settings);
+                Some(())
             }
         }
 
+        // The properties are checked when they're set, so this should never happen.
+        let completion = match completion {
+            Ok(completion) => completion,
+            Err(error) => {
+                gst::element_error!(
+                    element,
+                    gst::LibraryError::Settings,
+                    ("Invalid settings: {}", error)
+                );
+                return Err(gst::FlowError::Error);
+            }
+        };
+        if completion == Completion::Truncated {
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Ran out of time, so some rectangles were left unsplit"
+            );
+        }
+        if let Some(report) = rectanglifier.report() {
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Drew {} of {} rectangles, with their darkness {:.1}% off on average and {:.1}% at most, and {} not split as much as they should've been",
+                report.rects,
+                report.requested_rects,
+                report.mean_error * 100.0,
+                report.max_error * 100.0,
+                report.clamped_leaves
+            );
+        }
+
+        let frame_time = start.elapsed();
+        let mut avg_frame_time = self.avg_frame_time.lock().unwrap();
+        *avg_frame_time = Some(match *avg_frame_time {
+            Some(average) => {
+                average.mul_f64(1.0 - FRAME_TIME_WEIGHT) + frame_time.mul_f64(FRAME_TIME_WEIGHT)
+            }
+            None => frame_time,
+        });
+
         Ok(gst::FlowSuccess::Ok)
     }
 }
@@ -1,221 +1,6448 @@
 //! The actual rectangle drawing algorithm, which can work with any image integrated with the `image ` crate.
 
-use image::{GenericImage, GenericImageView, Pixel, Primitive};
-use num_traits::ToPrimitive;
+use image::math::Rect;
+use image::{
+    ColorType, DynamicImage, GenericImage, GenericImageView, GrayImage, ImageBuffer, LumaA, Pixel,
+    Primitive, Rgba,
+};
+use num_traits::{NumCast, ToPrimitive};
+use std::error::Error;
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
+use std::marker::PhantomData;
+use std::mem;
+use std::ops::{Deref, DerefMut, RangeInclusive};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 pub const DEFAULT_RECTS_PER_PIXEL: f64 = 0.1;
+pub const DEFAULT_LINE_THICKNESS: u32 = 1;
+pub const DEFAULT_GAMMA: f64 = 1.0;
+pub const DEFAULT_EXPOSURE: f64 = 1.0;
+pub const DEFAULT_SPLIT_FRACTION: f64 = 0.5;
+pub const DEFAULT_FILL_PROBABILITY: f64 = 0.2;
+pub const DEFAULT_SEAM_MAX_IMBALANCE: f64 = 0.1;
+/// The primary colours `FillMode::Mondrian` uses by default.
+pub const MONDRIAN_PALETTE: [Color; 3] = [
+    Color::rgb(0.8, 0.1, 0.1),
+    Color::rgb(0.95, 0.8, 0.1),
+    Color::rgb(0.1, 0.2, 0.6),
+];
 
-#[derive(Debug, Clone, Copy)]
+/// Options for how to rectanglify an image.
+///
+/// With the `serde` feature, these can be serialised and deserialised. Any fields that are
+/// missing get their default values, so that settings saved by older versions still load, but
+/// unknown fields are an error, to catch typos. Nothing's checked against
+/// [`Settings::validate`] when deserialising.
+#[derive(Debug, Clone)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default, deny_unknown_fields)
+)]
 pub struct Settings {
-    pub rects_per_pixel: f64,
+    /// How many rectangles to draw.
+    pub budget: RectBudget,
+    /// If set, the most rectangles to draw, whatever `budget` works out to. This puts a bound on
+    /// how long very dark images take with `RectBudget::PerDarkness`. The rectangles are still
+    /// shared out evenly by darkness, there are just fewer of them.
+    pub max_rects: Option<usize>,
+    /// The width of the dividing lines, in output pixels. 0 is treated as 1.
+    pub line_thickness: u32,
+    /// How much thinner (or thicker) each level of splits' lines are than the level before:
+    /// lines splitting a rectangle `depth` splits down are `line_thickness * falloff ^ depth`
+    /// thick, rounded and at least 1. The first split and the border are always
+    /// `line_thickness` thick, and 1 makes every line the same.
+    ///
+    /// Must be finite and positive.
+    pub thickness_falloff: f64,
+    /// Whether to pick how thick each dividing line is so that, from far enough away, the lines
+    /// and background around it look about as dark as the same part of the input, instead of
+    /// going by `line_thickness` and `thickness_falloff` alone. Otherwise, thin lines on a light
+    /// background look a lot lighter overall than the input does.
+    ///
+    /// Each line is made as thick as a grid of lines, spaced out like the rectangles on either
+    /// side of it, would need to be to cover as much of them as their mean darkness. Diagonals,
+    /// the border and the outlines drawn by the fill modes keep their usual thickness.
+    pub tone_match: bool,
+    /// How far to go from the usual thickness of each line to the one picked by `tone_match`,
+    /// from 0 (not at all) to 1 (all the way).
+    pub tone_match_strength: f64,
+    /// The colour of the dividing lines. If it's semi-transparent, the lines get composited over
+    /// whatever's underneath them, and stop at the edge of the lines they run into so that they
+    /// don't get drawn over twice.
+    pub line_color: Color,
+    /// If this isn't empty, the colours to draw the dividing lines in by how many splits down
+    /// they are, instead of `line_color`: the first split gets the first colour, the splits of
+    /// its halves the second, and so on, starting over from the start when it runs out. On
+    /// greyscale outputs, these turn into different shades of grey.
+    ///
+    /// The border and everything drawn with the fill modes still use `line_color`.
+    pub line_palette: Vec<Color>,
+    /// Whether to draw the dividing lines solid, dashed or dotted.
+    pub line_style: LineStyle,
+    /// Whether to fade each dividing line towards the background colour by how light the
+    /// rectangle it splits is, so that lines in dark areas are drawn in `line_color` and lines in
+    /// light areas are fainter.
+    ///
+    /// A line splitting a rectangle with a mean darkness of `d` (from 0 to 1) is drawn with
+    /// `background_color` mixed `d` of the way towards `line_color`.
+    pub shade_lines: bool,
+    /// The colour the output is filled with before any lines are drawn.
+    pub background_color: Color,
+    /// Whether to fill the output with transparency instead of `background_color` before
+    /// drawing, for compositing it over something else. Everything drawn on top of it is still
+    /// opaque, apart from the partially covered pixels at the edges of antialiased shapes.
+    ///
+    /// Outputs without an alpha channel have nowhere to put the transparency, so they're filled
+    /// with `background_color` as usual. This is also ignored with `Channels::Separate`, since
+    /// each channel's lines only cover that channel.
+    pub transparent_background: bool,
+    /// Whether to draw on top of a copy of the input instead of filling the output with
+    /// `background_color`, for marking out the rectangles over the original image. This makes
+    /// the most sense with `FillMode::Lines`, since the other modes cover it up.
+    ///
+    /// The input is scaled to the output the same way the rectangles are, picking the nearest
+    /// pixel, and any of the output past the edge of the input is filled with the background.
+    pub overlay: bool,
+    /// How much of the input shows through with `overlay`, from 0 (none of it) to 1 (all of it).
+    ///
+    /// The input is composited over the background with its alpha multiplied by this, so with a
+    /// transparent background the output's alpha is this much of the input's wherever nothing
+    /// gets drawn over it. Anything drawn on top keeps its own alpha, so opaque lines are still
+    /// opaque. `preserve_alpha` replaces all of that with the input's alpha afterwards.
+    pub source_opacity: f64,
+    /// Whether to fill the output with the background (or the input, with `overlay`) before
+    /// drawing. Turning this off draws over whatever's already in the output, for combining
+    /// several passes (such as ones over different `roi`s) into one image.
+    ///
+    /// Only the lines are drawn over the existing contents, though: fill modes that paint each
+    /// rectangle still cover up everything underneath them.
+    pub clear_output: bool,
+    /// Whether to copy the input's alpha channel onto the output after drawing, so that it keeps
+    /// the same silhouette. It's scaled the same way as with `overlay`, and does nothing unless
+    /// both the input and output have an alpha channel.
+    ///
+    /// This overrides the alpha that the background, `source_opacity` and everything drawn would
+    /// otherwise have given the output, lines included.
+    pub preserve_alpha: bool,
+    /// Whether to measure brightness instead of darkness, so that rectangles are concentrated in
+    /// the bright parts of the image.
+    pub invert: bool,
+    /// How to measure the brightness of each pixel. Its darkness is 1 minus that.
+    pub brightness: Brightness,
+    /// What to draw inside each rectangle.
+    pub fill_mode: FillMode,
+    /// The shape of what's drawn inside each rectangle with `FillMode::Ellipses` and
+    /// `FillMode::Halftone`. Other fill modes ignore this.
+    pub shape: Shape,
+    /// The smallest and largest radius of the dots drawn with `FillMode::Halftone`, as fractions
+    /// of the largest circle that fits in the rectangle. Each dot's radius goes from the first to
+    /// the second in proportion to how dark its rectangle is.
+    ///
+    /// Both must be between 0 and 1, and the first can't be bigger than the second.
+    pub halftone_radius: (f64, f64),
+    /// The chance of each rectangle being filled with `FillMode::Mondrian`, from 0 to 1.
+    pub fill_probability: f64,
+    /// The colours `FillMode::Mondrian` picks from. If it's empty, nothing gets filled.
+    pub fill_palette: Vec<Color>,
+    /// An exponent applied to the darkness of each pixel. Values above 1 exaggerate the
+    /// difference in density between light and dark areas, and values below 1 flatten it.
+    ///
+    /// Must be finite and positive.
+    pub gamma: f64,
+    /// What to multiply the brightness of each pixel by before working out its darkness. 1.0 is
+    /// white for floating-point images (and the maximum value for integer ones), and anything
+    /// brighter than that afterwards (like the highlights of an HDR image) counts as white, so
+    /// values below 1 bring those back into range. This is applied before everything else,
+    /// including `invert`.
+    ///
+    /// Must be finite and positive.
+    pub exposure: f64,
+    /// If set, pixels count as fully dark if their darkness is above this, and fully light
+    /// otherwise.
+    pub threshold: Option<f64>,
+    /// Whether to histogram-equalise the darkness of the input before splitting it, so that
+    /// low-contrast images still get an uneven layout.
+    ///
+    /// The darkness of each pixel (from `brightness` and `invert`) is sorted into 256 levels, and
+    /// each level is replaced by the fraction of the image's pixels that are at most that dark.
+    /// `threshold` or `gamma` are applied after that, then alpha, `edges`, `smoothing` and any
+    /// weighting, the same as without this. The total darkness usually changes, and with it the
+    /// number of rectangles a `RectBudget::PerDarkness` budget gives.
+    pub equalize: bool,
+    /// How much to put the rectangles where the image has edges instead of where it's dark, from
+    /// 0 to 1. The darkness of each pixel is mixed this much of the way towards the magnitude of
+    /// the gradient of the input's luma there, scaled so that a sharp edge from black to white
+    /// is 1. That part is affected by `exposure`, but not by `invert`, `brightness`, `gamma` or
+    /// `threshold`.
+    pub edges: f64,
+    /// If set, the standard deviation (in input pixels) of a Gaussian blur applied to the
+    /// darkness of the input before working out where the splits go, so that they follow the
+    /// structure of the image rather than noise like film grain. The drawing isn't blurred.
+    pub smoothing: Option<f64>,
+    /// Whether to draw lines at their exact fractional positions, blending them into the pixels
+    /// they partially cover, rather than snapping them to whole pixels.
+    pub antialias: bool,
+    /// If set, the most times longer than it is wide (or the other way around) a rectangle is
+    /// allowed to be, to stop long thin slivers. Splits that would make a rectangle stretched out
+    /// further than this are moved as little as possible to fix that, even though that means the
+    /// darkness won't be shared out evenly. If there's nowhere that works, the split goes in the
+    /// middle, since that's the closest it can get.
+    ///
+    /// Must be at least 1.
+    pub max_aspect_ratio: Option<f64>,
+    /// Whether to round every split to the nearest whole input pixel, so that no pixel is shared
+    /// between rectangles. This is mostly useful for pixel art.
+    ///
+    /// Rectangles that can't be split without making one half empty are left as they are, like
+    /// with `min_rect_size`. If `roi` isn't on whole pixels, the outer rectangles won't be either.
+    pub snap_to_pixels: bool,
+    /// If set, every split is moved to the nearest of a grid of lines this many input pixels
+    /// apart, starting from `grid_offset`. Rectangles that don't have a grid line going through
+    /// them are left as they are, like with `min_rect_size`.
+    pub grid: Option<u32>,
+    /// Where the vertical and horizontal lines of `grid` start from, in input pixels.
+    pub grid_offset: (f64, f64),
+    /// The smallest width or height a rectangle can have, in input pixels.
+    ///
+    /// Rectangles that can't be split without going below this are left as they are, even if
+    /// they still have more rectangles to share out; those extra rectangles are dropped rather
+    /// than given to the sibling.
+    pub min_rect_size: f64,
+    /// The deepest the splitting can go, or `None` for no limit. A depth of 0 means the image is
+    /// never split at all.
+    pub max_depth: Option<u32>,
+    /// If set, rectangles where the variance of the pixels' darkness is below this are left as
+    /// they are, even if they have more rectangles to share out, so that the budget isn't spent
+    /// on flat areas. Those extra rectangles are dropped, so fewer get drawn than asked for; see
+    /// [`rectanglify_rects`] or [`rectanglify_with`] for how many there ended up being.
+    ///
+    /// The variance is of all the pixels each rectangle overlaps, even partially.
+    pub variance_threshold: Option<f64>,
+    /// How much darkness (in black pixels' worth) each half of a split needs to be split any
+    /// further. A half with less than this is left as one rectangle, and the rest of its share
+    /// goes to the other half, so that the rectangles go where there's something to see. If both
+    /// halves have less than this, the rest of the budget is dropped instead.
+    ///
+    /// Even a pruned half still counts as one of the rectangles, so each split still uses up at
+    /// least one of them, and the splitting is sure to finish. 0 never prunes anything.
+    ///
+    /// Must be finite and not negative.
+    pub min_leaf_darkness: f64,
+    /// The fraction of the rectangles that go into the first (left or top) half of each split,
+    /// from 0 to 1 exclusive. 0.5 splits everything evenly; something like 0.618 gives
+    /// golden-ratio compositions.
+    pub split_fraction: f64,
+    /// How much to randomly move each split by, as a fraction of the darkness that would've been
+    /// on the first side of it, from 0 to 1.
+    pub jitter: f64,
+    /// The seed for anything random, like `jitter`. The same seed always gives the same output.
+    pub seed: u64,
+    /// How to pick which way to split each rectangle.
+    pub split_strategy: SplitStrategy,
+    /// How many input pixels either side of where each split would go to look for a lighter
+    /// column or row to move it to, so that the lines tend to run through the gaps between
+    /// features rather than straight through them. 0 leaves every split where it is.
+    ///
+    /// The split is moved to the middle of the lightest whole column or row in range that keeps
+    /// the first half's darkness within `seam_max_imbalance` of what it should be, preferring
+    /// the closest if several are as light, and stays put if there aren't any. This doesn't
+    /// apply to the quarters of `SplitStrategy::Quad`.
+    pub seam_window: u32,
+    /// The most that moving a split for `seam_window` is allowed to change how much darkness goes
+    /// into each half by, as a fraction of the darkness of the rectangle being split, from 0 to 1.
+    /// This is for each split, so it can add up over several levels of splits.
+    pub seam_max_imbalance: f64,
+    /// Whether to make the splits mirror-symmetric, by only splitting up one half (or quarter) of
+    /// the image and reflecting the result onto the others.
+    pub symmetry: Symmetry,
+    /// Whether to work out the splits on multiple threads. This gives exactly the same output,
+    /// and has no effect unless the `rayon` feature is enabled.
+    pub parallel: bool,
+    /// How long to spend working out the splits, or `None` for as long as it takes.
+    ///
+    /// Once it's run out, whatever's still waiting to be split is left as one rectangle, so the
+    /// result is coarser (and it's no longer the same from one run to the next) but arrives on
+    /// time, which is what matters for live video. The time is only checked every so often, so it
+    /// can overrun by a little. Everything that draws reports whether this happened as a
+    /// [`Completion`]; everything else just returns the truncated result.
+    pub deadline: Option<Duration>,
+    /// Whether to work out the splits in a way that gives bit-identical output on every platform
+    /// and optimisation level, for comparing against golden images.
+    ///
+    /// The darkness of each pixel gets rounded to a multiple of 2^-16 and summed up as an integer,
+    /// so that the totals don't depend on the order they're added up in, and split positions get
+    /// rounded to a multiple of 1/256 of a pixel. That means the splits can move by up to 1/512
+    /// of a pixel compared to normal, which occasionally moves a line over by a whole pixel
+    /// without `antialias`.
+    ///
+    /// `gamma`, `smoothing`, `edges` and `Brightness::Rec709` use functions from the platform's
+    /// maths library, which can differ in the last bit, so with those a pixel's darkness can
+    /// (very rarely) still get rounded differently.
+    pub deterministic: bool,
+    /// How many output pixels to draw for each input pixel, or `None` to stretch the drawing to
+    /// fill the output, whatever size it is.
+    ///
+    /// The splits are still worked out at the input's resolution, so this is much cheaper than
+    /// scaling up the input. Use [`scaled_size`] to work out how big to make the output; if it's
+    /// any smaller than that, whatever doesn't fit is cut off.
+    ///
+    /// Must be finite and positive.
+    pub scale: Option<f64>,
+    /// Whether to rectanglify the colour channels together or separately.
+    pub channels: Channels,
+    /// The region of the input to rectanglify, in input pixels, or `None` for the whole thing.
+    /// Everything outside it is left as the background colour.
+    ///
+    /// This is clipped to the bounds of the input, and must still have a non-zero area after
+    /// that.
+    pub roi: Option<Rectangle>,
+    /// Whether to draw a line around the edge of the rectanglified region (the whole image, or
+    /// `roi`), so that the outer rectangles are closed off like the inner ones.
+    pub border: bool,
+    /// Whether to treat the input as repeating, for making textures that tile seamlessly.
+    ///
+    /// `smoothing` and `edges` wrap around from each edge of the image to the opposite one
+    /// instead of stopping there, and `border` is ignored. The first split is also into quarters
+    /// like with `SplitStrategy::Quad` (unless the strategy only splits one way), so that the two
+    /// lines it makes carry straight on across the seams when the output is tiled. Lines further
+    /// down still just stop at the edges, leaving the rectangles either side of a seam to merge.
+    pub wrap: bool,
+    /// How far (in output pixels) to shrink each of the final rectangles by on every side, leaving
+    /// a gap of background between them. If this is non-zero, the dividing lines are left out,
+    /// and with `FillMode::Lines` each rectangle is outlined instead.
+    ///
+    /// Rectangles too small to shrink by this much aren't drawn at all.
+    pub padding: f64,
+    /// How far (in output pixels) to round off the corners of each of the rectangles that get
+    /// filled in, as quarter circles. This is clamped to half of each rectangle's shorter side,
+    /// so that a large enough radius turns squares into circles.
+    ///
+    /// Outlines, triangles and ellipses are left as they are.
+    pub corner_radius: f64,
+    /// Whether to write the index of each of the final rectangles in its top-left corner, in a
+    /// tiny 5×7 pixel font, for matching the output up with [`rectanglify_rects`],
+    /// [`rectanglify_leaves`] and so on, whose rectangles come in the same order. The numbers are
+    /// drawn in `line_color` on a box of `background_color`, on top of everything else, and
+    /// rectangles too small to fit theirs are left without one.
+    pub debug_labels: bool,
 }
 
 impl Default for Settings {
     fn default() -> Self {
         Settings {
-            rects_per_pixel: DEFAULT_RECTS_PER_PIXEL,
+            budget: RectBudget::PerDarkness(DEFAULT_RECTS_PER_PIXEL),
+            max_rects: None,
+            line_thickness: DEFAULT_LINE_THICKNESS,
+            thickness_falloff: 1.0,
+            tone_match: false,
+            tone_match_strength: 1.0,
+            line_color: Color::BLACK,
+            line_palette: Vec::new(),
+            line_style: LineStyle::Solid,
+            shade_lines: false,
+            background_color: Color::WHITE,
+            transparent_background: false,
+            overlay: false,
+            source_opacity: 1.0,
+            clear_output: true,
+            preserve_alpha: false,
+            invert: false,
+            brightness: Brightness::Luma,
+            fill_mode: FillMode::Lines,
+            shape: Shape::Ellipse,
+            halftone_radius: (0.0, 1.0),
+            fill_probability: DEFAULT_FILL_PROBABILITY,
+            fill_palette: MONDRIAN_PALETTE.to_vec(),
+            gamma: DEFAULT_GAMMA,
+            exposure: DEFAULT_EXPOSURE,
+            threshold: None,
+            equalize: false,
+            edges: 0.0,
+            smoothing: None,
+            antialias: false,
+            max_aspect_ratio: None,
+            snap_to_pixels: false,
+            grid: None,
+            grid_offset: (0.0, 0.0),
+            min_rect_size: 0.0,
+            max_depth: None,
+            variance_threshold: None,
+            min_leaf_darkness: 0.0,
+            split_fraction: DEFAULT_SPLIT_FRACTION,
+            jitter: 0.0,
+            seed: 0,
+            split_strategy: SplitStrategy::LongestSide,
+            symmetry: Symmetry::None,
+            seam_window: 0,
+            seam_max_imbalance: DEFAULT_SEAM_MAX_IMBALANCE,
+            parallel: false,
+            deadline: None,
+            deterministic: false,
+            scale: None,
+            channels: Channels::Combined,
+            roi: None,
+            border: false,
+            wrap: false,
+            padding: 0.0,
+            corner_radius: 0.0,
+            debug_labels: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Returns a [`SettingsBuilder`] starting from the default settings.
+    pub fn builder() -> SettingsBuilder {
+        SettingsBuilder::default()
+    }
+
+    /// Returns a [`SettingsBuilder`] starting from these settings, for changing some of them
+    /// without letting them become invalid.
+    pub fn into_builder(self) -> SettingsBuilder {
+        SettingsBuilder { settings: self }
+    }
+
+    /// Checks that all of these settings are in range, so that they get used as they are rather
+    /// than being sanitised (see [`Settings::sanitized`]).
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let finite_and_positive = |value: f64| value.is_finite() && value > 0.0;
+        let finite_and_not_negative = |value: f64| value.is_finite() && value >= 0.0;
+        let fraction = |value: f64| (0.0..=1.0).contains(&value);
+
+        if let RectBudget::PerDarkness(rects_per_pixel) = self.budget {
+            if !finite_and_not_negative(rects_per_pixel) {
+                return Err(SettingsError::RectsPerPixel(rects_per_pixel));
+            }
+        }
+        let style_ok = match self.line_style {
+            LineStyle::Solid => true,
+            LineStyle::Dashed { dash, gap } => {
+                finite_and_positive(dash) && finite_and_not_negative(gap)
+            }
+            LineStyle::Dotted { gap } => finite_and_not_negative(gap),
+        };
+        if !style_ok {
+            return Err(SettingsError::LineStyle(self.line_style));
+        }
+        if !finite_and_positive(self.thickness_falloff) {
+            return Err(SettingsError::ThicknessFalloff(self.thickness_falloff));
+        }
+        if !(0.0..=1.0).contains(&self.tone_match_strength) {
+            return Err(SettingsError::ToneMatchStrength(self.tone_match_strength));
+        }
+        if let FillMode::Quantized { levels } = self.fill_mode {
+            if levels < 2 {
+                return Err(SettingsError::QuantizedLevels(levels));
+            }
+        }
+        let (min_radius, max_radius) = self.halftone_radius;
+        if !(fraction(min_radius) && fraction(max_radius) && min_radius <= max_radius) {
+            return Err(SettingsError::HalftoneRadius(self.halftone_radius));
+        }
+        if !fraction(self.source_opacity) {
+            return Err(SettingsError::SourceOpacity(self.source_opacity));
+        }
+        if !fraction(self.fill_probability) {
+            return Err(SettingsError::FillProbability(self.fill_probability));
+        }
+        if !finite_and_positive(self.gamma) {
+            return Err(SettingsError::Gamma(self.gamma));
+        }
+        if !finite_and_positive(self.exposure) {
+            return Err(SettingsError::Exposure(self.exposure));
+        }
+        if let Some(threshold) = self.threshold.filter(|threshold| threshold.is_nan()) {
+            return Err(SettingsError::Threshold(threshold));
+        }
+        if !fraction(self.edges) {
+            return Err(SettingsError::Edges(self.edges));
+        }
+        if let Some(sigma) = self.smoothing.filter(|&sigma| !finite_and_positive(sigma)) {
+            return Err(SettingsError::Smoothing(sigma));
+        }
+        if let Some(ratio) = self
+            .max_aspect_ratio
+            .filter(|&ratio| ratio.is_nan() || ratio < 1.0)
+        {
+            return Err(SettingsError::MaxAspectRatio(ratio));
+        }
+        if self.grid == Some(0)
+            || !(self.grid_offset.0.is_finite() && self.grid_offset.1.is_finite())
+        {
+            return Err(SettingsError::Grid(
+                self.grid.unwrap_or(0),
+                self.grid_offset,
+            ));
+        }
+        if !finite_and_not_negative(self.min_rect_size) {
+            return Err(SettingsError::MinRectSize(self.min_rect_size));
+        }
+        if let Some(variance) = self.variance_threshold.filter(|variance| variance.is_nan()) {
+            return Err(SettingsError::VarianceThreshold(variance));
+        }
+        if !finite_and_not_negative(self.min_leaf_darkness) {
+            return Err(SettingsError::MinLeafDarkness(self.min_leaf_darkness));
+        }
+        if !(self.split_fraction > 0.0 && self.split_fraction < 1.0) {
+            return Err(SettingsError::SplitFraction(self.split_fraction));
+        }
+        if !fraction(self.jitter) {
+            return Err(SettingsError::Jitter(self.jitter));
         }
+        if !fraction(self.seam_max_imbalance) {
+            return Err(SettingsError::SeamMaxImbalance(self.seam_max_imbalance));
+        }
+        if let Some(scale) = self.scale.filter(|&scale| !finite_and_positive(scale)) {
+            return Err(SettingsError::Scale(scale));
+        }
+        if let Some(roi) = self.roi {
+            let sides = [roi.left, roi.top, roi.right, roi.bottom];
+            if !(sides.iter().all(|side| side.is_finite())
+                && roi.width() > 0.0
+                && roi.height() > 0.0)
+            {
+                return Err(SettingsError::Roi(roi));
+            }
+        }
+        if !finite_and_not_negative(self.padding) {
+            return Err(SettingsError::Padding(self.padding));
+        }
+        if !finite_and_not_negative(self.corner_radius) {
+            return Err(SettingsError::CornerRadius(self.corner_radius));
+        }
+
+        Ok(())
+    }
+
+    /// Returns these settings with every one that [`Settings::validate`] rejects put back to its
+    /// default, so that they can't produce nonsense like NaN coordinates.
+    ///
+    /// Everything that rectanglifies without returning an error does this to its settings first.
+    pub fn sanitized(mut self) -> Settings {
+        while let Err(error) = self.validate() {
+            let default = Settings::default();
+            match error {
+                SettingsError::RectsPerPixel(_) => self.budget = default.budget,
+                SettingsError::LineStyle(_) => self.line_style = default.line_style,
+                SettingsError::ThicknessFalloff(_) => {
+                    self.thickness_falloff = default.thickness_falloff
+                }
+                SettingsError::ToneMatchStrength(_) => {
+                    self.tone_match_strength = default.tone_match_strength
+                }
+                SettingsError::QuantizedLevels(_) => self.fill_mode = default.fill_mode,
+                SettingsError::SourceOpacity(_) => self.source_opacity = default.source_opacity,
+                SettingsError::HalftoneRadius(_) => self.halftone_radius = default.halftone_radius,
+                SettingsError::FillProbability(_) => {
+                    self.fill_probability = default.fill_probability
+                }
+                SettingsError::Gamma(_) => self.gamma = default.gamma,
+                SettingsError::Exposure(_) => self.exposure = default.exposure,
+                SettingsError::Threshold(_) => self.threshold = default.threshold,
+                SettingsError::Edges(_) => self.edges = default.edges,
+                SettingsError::Smoothing(_) => self.smoothing = default.smoothing,
+                SettingsError::MaxAspectRatio(_) => {
+                    self.max_aspect_ratio = default.max_aspect_ratio
+                }
+                SettingsError::Grid(..) => {
+                    self.grid = default.grid;
+                    self.grid_offset = default.grid_offset;
+                }
+                SettingsError::MinRectSize(_) => self.min_rect_size = default.min_rect_size,
+                SettingsError::VarianceThreshold(_) => {
+                    self.variance_threshold = default.variance_threshold
+                }
+                SettingsError::MinLeafDarkness(_) => {
+                    self.min_leaf_darkness = default.min_leaf_darkness
+                }
+                SettingsError::SplitFraction(_) => self.split_fraction = default.split_fraction,
+                SettingsError::Jitter(_) => self.jitter = default.jitter,
+                SettingsError::SeamMaxImbalance(_) => {
+                    self.seam_max_imbalance = default.seam_max_imbalance
+                }
+                SettingsError::Scale(_) => self.scale = default.scale,
+                SettingsError::Roi(_) => self.roi = default.roi,
+                SettingsError::Padding(_) => self.padding = default.padding,
+                SettingsError::CornerRadius(_) => self.corner_radius = default.corner_radius,
+            }
+        }
+        self
     }
 }
 
+/// The error returned by [`Settings::validate`], saying which setting is out of range and what
+/// it was set to.
 #[derive(Debug, Clone, Copy, PartialEq)]
-struct Rectangle {
-    left: f64,
-    top: f64,
-    right: f64,
-    bottom: f64,
+pub enum SettingsError {
+    /// The number of rectangles per pixel of darkness was negative or not finite.
+    RectsPerPixel(f64),
+    /// A dashed line style had dashes that weren't longer than 0, or either line style had a
+    /// negative gap, or a length that wasn't finite.
+    LineStyle(LineStyle),
+    /// `thickness_falloff` wasn't finite and positive.
+    ThicknessFalloff(f64),
+    /// `tone_match_strength` wasn't between 0 and 1.
+    ToneMatchStrength(f64),
+    /// `FillMode::Quantized` had fewer than 2 levels.
+    QuantizedLevels(u8),
+    /// `source_opacity` wasn't between 0 and 1.
+    SourceOpacity(f64),
+    /// `halftone_radius` had a fraction that wasn't between 0 and 1, or its minimum was bigger
+    /// than its maximum.
+    HalftoneRadius((f64, f64)),
+    /// `fill_probability` wasn't between 0 and 1.
+    FillProbability(f64),
+    /// `gamma` wasn't finite and positive.
+    Gamma(f64),
+    /// `exposure` wasn't finite and positive.
+    Exposure(f64),
+    /// `threshold` was NaN.
+    Threshold(f64),
+    /// `edges` wasn't between 0 and 1.
+    Edges(f64),
+    /// `smoothing` wasn't finite and positive.
+    Smoothing(f64),
+    /// `max_aspect_ratio` was less than 1.
+    MaxAspectRatio(f64),
+    /// `grid` had a spacing of 0, or `grid_offset` wasn't finite.
+    Grid(u32, (f64, f64)),
+    /// `min_rect_size` was negative or not finite.
+    MinRectSize(f64),
+    /// `variance_threshold` was NaN.
+    VarianceThreshold(f64),
+    /// `min_leaf_darkness` was negative or not finite.
+    MinLeafDarkness(f64),
+    /// `split_fraction` wasn't between 0 and 1 exclusive.
+    SplitFraction(f64),
+    /// `jitter` wasn't between 0 and 1.
+    Jitter(f64),
+    /// `seam_max_imbalance` wasn't between 0 and 1.
+    SeamMaxImbalance(f64),
+    /// `scale` wasn't finite and positive.
+    Scale(f64),
+    /// `roi` had no area, or sides that weren't finite.
+    Roi(Rectangle),
+    /// `padding` was negative or not finite.
+    Padding(f64),
+    /// `corner_radius` was negative or not finite.
+    CornerRadius(f64),
 }
 
-impl Rectangle {
-    fn width(&self) -> f64 {
-        self.right - self.left
+impl Display for SettingsError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SettingsError::RectsPerPixel(value) => write!(
+                f,
+                "rects per pixel must be finite and not negative, got {}",
+                value
+            ),
+            SettingsError::LineStyle(style) => write!(
+                f,
+                "dashes must be finite and positive and gaps finite and not negative, got {:?}",
+                style
+            ),
+            SettingsError::ThicknessFalloff(value) => write!(
+                f,
+                "thickness_falloff must be finite and positive, got {}",
+                value
+            ),
+            SettingsError::ToneMatchStrength(value) => write!(
+                f,
+                "tone_match_strength must be between 0 and 1, got {}",
+                value
+            ),
+            SettingsError::QuantizedLevels(levels) => write!(
+                f,
+                "quantized fill mode needs at least 2 levels, got {}",
+                levels
+            ),
+            SettingsError::SourceOpacity(value) => {
+                write!(f, "source_opacity must be between 0 and 1, got {}", value)
+            }
+            SettingsError::HalftoneRadius(radius) => write!(
+                f,
+                "halftone_radius must be between 0 and 1 and in order, got {:?}",
+                radius
+            ),
+            SettingsError::FillProbability(value) => {
+                write!(f, "fill_probability must be between 0 and 1, got {}", value)
+            }
+            SettingsError::Gamma(value) => {
+                write!(f, "gamma must be finite and positive, got {}", value)
+            }
+            SettingsError::Exposure(value) => {
+                write!(f, "exposure must be finite and positive, got {}", value)
+            }
+            SettingsError::Threshold(value) => {
+                write!(f, "threshold must be a number, got {}", value)
+            }
+            SettingsError::Edges(value) => {
+                write!(f, "edges must be between 0 and 1, got {}", value)
+            }
+            SettingsError::Smoothing(value) => {
+                write!(f, "smoothing must be finite and positive, got {}", value)
+            }
+            SettingsError::MaxAspectRatio(value) => {
+                write!(f, "max_aspect_ratio must be at least 1, got {}", value)
+            }
+            SettingsError::Grid(spacing, offset) => write!(
+                f,
+                "grid spacing must be positive and its offset finite, got {} and {:?}",
+                spacing, offset
+            ),
+            SettingsError::MinRectSize(value) => write!(
+                f,
+                "min_rect_size must be finite and not negative, got {}",
+                value
+            ),
+            SettingsError::VarianceThreshold(value) => {
+                write!(f, "variance_threshold must be a number, got {}", value)
+            }
+            SettingsError::MinLeafDarkness(value) => write!(
+                f,
+                "min_leaf_darkness must be finite and not negative, got {}",
+                value
+            ),
+            SettingsError::SplitFraction(value) => {
+                write!(f, "split_fraction must be between 0 and 1, got {}", value)
+            }
+            SettingsError::Jitter(value) => {
+                write!(f, "jitter must be between 0 and 1, got {}", value)
+            }
+            SettingsError::SeamMaxImbalance(value) => {
+                write!(
+                    f,
+                    "seam_max_imbalance must be between 0 and 1, got {}",
+                    value
+                )
+            }
+            SettingsError::Scale(value) => {
+                write!(f, "scale must be finite and positive, got {}", value)
+            }
+            SettingsError::Roi(roi) => {
+                write!(
+                    f,
+                    "roi must be finite and have a non-zero area, got {:?}",
+                    roi
+                )
+            }
+            SettingsError::Padding(value) => {
+                write!(f, "padding must be finite and not negative, got {}", value)
+            }
+            SettingsError::CornerRadius(value) => {
+                write!(
+                    f,
+                    "corner radius must be finite and not negative, got {}",
+                    value
+                )
+            }
+        }
     }
+}
 
-    fn height(&self) -> f64 {
-        self.bottom - self.top
+impl Error for SettingsError {}
+
+/// A way of putting together [`Settings`] that checks they're valid before handing them over, so
+/// that none of them get quietly put back to their defaults (see [`Settings::sanitized`]).
+///
+/// Each method sets the field of `Settings` with the same name.
+#[derive(Debug, Clone, Default)]
+pub struct SettingsBuilder {
+    settings: Settings,
+}
+
+macro_rules! setters {
+    ($($field:ident: $ty:ty,)*) => {
+        impl SettingsBuilder {
+            $(
+            #[doc = concat!("Sets `Settings::", stringify!($field), "`.")]
+            pub fn $field(mut self, $field: $ty) -> Self {
+                self.settings.$field = $field;
+                self
+            }
+            )*
+        }
+    };
+}
+
+setters! {
+    budget: RectBudget,
+    max_rects: Option<usize>,
+    line_thickness: u32,
+    thickness_falloff: f64,
+    tone_match: bool,
+    tone_match_strength: f64,
+    line_color: Color,
+    line_palette: Vec<Color>,
+    line_style: LineStyle,
+    shade_lines: bool,
+    background_color: Color,
+    transparent_background: bool,
+    overlay: bool,
+    source_opacity: f64,
+    clear_output: bool,
+    preserve_alpha: bool,
+    invert: bool,
+    brightness: Brightness,
+    fill_mode: FillMode,
+    shape: Shape,
+    halftone_radius: (f64, f64),
+    fill_probability: f64,
+    fill_palette: Vec<Color>,
+    gamma: f64,
+    exposure: f64,
+    threshold: Option<f64>,
+    equalize: bool,
+    edges: f64,
+    smoothing: Option<f64>,
+    antialias: bool,
+    max_aspect_ratio: Option<f64>,
+    snap_to_pixels: bool,
+    grid: Option<u32>,
+    grid_offset: (f64, f64),
+    min_rect_size: f64,
+    max_depth: Option<u32>,
+    variance_threshold: Option<f64>,
+    min_leaf_darkness: f64,
+    split_fraction: f64,
+    jitter: f64,
+    seed: u64,
+    split_strategy: SplitStrategy,
+    seam_window: u32,
+    seam_max_imbalance: f64,
+    symmetry: Symmetry,
+    parallel: bool,
+    deadline: Option<Duration>,
+    deterministic: bool,
+    scale: Option<f64>,
+    channels: Channels,
+    roi: Option<Rectangle>,
+    border: bool,
+    wrap: bool,
+    padding: f64,
+    corner_radius: f64,
+    debug_labels: bool,
+}
+
+impl SettingsBuilder {
+    /// Returns the settings, or which one is out of range if any of them are.
+    pub fn build(self) -> Result<Settings, SettingsError> {
+        self.settings.validate()?;
+        Ok(self.settings)
     }
 }
 
-fn darkness<P: Pixel>(p: P) -> f64 {
-    1.0 - p.to_luma()[0].to_f64().unwrap() / P::Subpixel::DEFAULT_MAX_VALUE.to_f64().unwrap()
+/// How many rectangles to split an image into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RectBudget {
+    /// A number of rectangles for each black pixel's worth of darkness in the image.
+    PerDarkness(f64),
+    /// An exact number of rectangles, regardless of how dark the image is.
+    Count(usize),
+}
+
+/// The pattern to draw lines with.
+///
+/// The pattern starts afresh at the start (left or top) of each line, so every line begins with a
+/// dash or dot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum LineStyle {
+    /// One unbroken line.
+    Solid,
+    /// Dashes `dash` output pixels long with gaps of `gap` between them. `dash` must be positive.
+    Dashed { dash: f64, gap: f64 },
+    /// Square dots as long as the line is thick, with gaps of `gap` output pixels between them.
+    Dotted { gap: f64 },
 }
 
-fn darkness_at(image: &impl GenericImageView, rect: Rectangle, x: u32, y: u32) -> f64 {
-    let mut darkness = darkness(image.get_pixel(x, y));
+impl LineStyle {
+    /// Returns how much of the stretch of a line from `start` to `end` (in output pixels from the
+    /// start of the line) is drawn, from 0 to 1.
+    fn coverage(self, thickness: u32, start: f64, end: f64) -> f64 {
+        let (on, gap) = match self {
+            LineStyle::Solid => return 1.0,
+            LineStyle::Dashed { dash, gap } => (dash, gap),
+            LineStyle::Dotted { gap } => (thickness.max(1) as f64, gap),
+        };
+        if end <= start {
+            return 0.0;
+        }
+        let period = on + gap.max(0.0);
+        // How much of the line from its start up to `x` is drawn.
+        let drawn = |x: f64| (x / period).floor() * on + x.rem_euclid(period).min(on);
+        (drawn(end) - drawn(start)) / (end - start)
+    }
 
-    if (x as f64) < rect.left {
-        darkness *= f64::max((x + 1) as f64 - rect.left, 0.0);
-    } else if (x + 1) as f64 > rect.right {
-        darkness *= f64::max(rect.right - x as f64, 0.0);
+    /// Returns whether the pixel `i` pixels from the start of a line is drawn when it's snapped to
+    /// whole pixels.
+    fn covers(self, thickness: u32, i: u32) -> bool {
+        self.coverage(thickness, i as f64, (i + 1) as f64) >= 0.5
     }
+}
+
+/// A way of measuring the brightness of a pixel, from 0 to 1.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Brightness {
+    /// The luma of the pixel as calculated by `image`, which weights the gamma-encoded channels.
+    Luma,
+    /// The relative luminance of the pixel, using the Rec. 709 weights on the linear (decoded
+    /// sRGB) channels.
+    Rec709,
+    /// The brightest of the red, green and blue channels.
+    MaxChannel,
+    /// How far the pixel's colour is from a key colour, as the distance between them in RGB
+    /// scaled so that black and white are 1 apart. That makes pixels close to the key colour
+    /// count as dark, so the rectangles are concentrated wherever it is.
+    ///
+    /// Greyscale pixels have no colour to compare, so their luma is compared to the key colour's
+    /// instead.
+    DistanceTo(Color),
+    /// A custom function of the pixel's colour. Its alpha is taken care of separately, so this
+    /// doesn't need to account for it.
+    ///
+    /// Functions can't be serialised, so trying to serialise this gives an error.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(Arc<dyn Fn(Color) -> f64 + Send + Sync>),
+}
 
-    if (y as f64) < rect.top {
-        darkness *= f64::max((y + 1) as f64 - rect.top, 0.0);
-    } else if (y + 1) as f64 > rect.bottom {
-        darkness *= f64::max(rect.bottom - y as f64, 0.0);
+impl Debug for Brightness {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Brightness::Luma => f.write_str("Luma"),
+            Brightness::Rec709 => f.write_str("Rec709"),
+            Brightness::MaxChannel => f.write_str("MaxChannel"),
+            Brightness::DistanceTo(key) => f.debug_tuple("DistanceTo").field(key).finish(),
+            Brightness::Custom(_) => f.debug_tuple("Custom").finish_non_exhaustive(),
+        }
     }
+}
+
+/// How to pick which way to split each rectangle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SplitStrategy {
+    /// Always split across the longer side.
+    LongestSide,
+    /// Alternate between vertical and horizontal splits at each level, starting with a vertical
+    /// one (splitting the width). Rectangles that are too thin to split the scheduled way (less
+    /// than a pixel or twice `min_rect_size`) are split the other way instead.
+    Alternate,
+    /// Work out where the split would go along both axes, and pick whichever gives the least
+    /// extreme aspect ratios. This takes twice as long to scan.
+    Balanced,
+    /// Split each rectangle into quarters at the point where the darkness is halved both ways,
+    /// sharing out the rectangles between them by darkness. Rectangles with fewer than 4 to share
+    /// out are split the same way as `LongestSide`.
+    ///
+    /// This ignores `split_fraction` and `jitter`, except in those last splits.
+    Quad,
+    /// Split the same way as `LongestSide`, except that rectangles with only 2 rectangles left to
+    /// share out are cut in half along whichever of their diagonals shares out their darkness
+    /// more evenly, giving pairs of triangles.
+    ///
+    /// Each pair comes out of [`rectanglify_rects`] as the one rectangle they make up, and the
+    /// diagonals aren't antialiased.
+    Diagonal,
+    /// Only ever split vertically (across the width), giving stripes like a barcode. Stripes
+    /// a pixel wide or less aren't split any further, and the rest of their budget is dropped.
+    VerticalOnly,
+    /// Only ever split horizontally (across the height), the same as `VerticalOnly` but the
+    /// other way around.
+    HorizontalOnly,
+}
+
+/// Which ways to make the splits symmetric.
+///
+/// The rectanglified region is first split exactly down the middle (and then across the middle
+/// of each half, for `FourWay`), even if that isn't on a whole pixel or grid line. For an odd
+/// width or height, that means the middle column or row is cut in half, and each side only
+/// counts its own half of it. Only the left half (or top-left quarter) gets split up from there,
+/// with its share of the rectangles, and the rest are mirror images of it. The darkness of the
+/// other parts is still measured for shading and fills, but it doesn't affect where they're split.
+///
+/// The splits down the middle count towards `Settings::max_depth`, and the rectangles are shared
+/// out evenly between the parts, so there can be up to 1 (or 3) fewer than asked for, or more if
+/// there are fewer than one for each part.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Symmetry {
+    /// Split the whole image normally.
+    None,
+    /// Mirror the left half onto the right.
+    LeftRight,
+    /// Mirror the top-left quarter onto the other three.
+    FourWay,
+}
 
-    darkness
+/// How to handle the colour channels of the input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Channels {
+    /// Measure the darkness of each pixel as a whole, and draw one set of rectangles.
+    Combined,
+    /// Rectanglify the red, green and blue channels independently, each using the darkness of
+    /// just that channel, and draw each set of rectangles into the matching channel of the
+    /// output, like the separations of a print.
+    ///
+    /// This only works for outputs with at least 3 channels, and falls back to `Combined` for
+    /// anything else. It's ignored by the functions that don't draw anything.
+    Separate,
 }
 
-fn black<P: Pixel>() -> P {
-    P::from_slice(&vec![
-        // make everything 0 for black
-        P::Subpixel::DEFAULT_MIN_VALUE;
-        P::CHANNEL_COUNT as usize
-    ])
-    // except alpha, which should be maxed
-    .map_with_alpha(|x| x, |_| P::Subpixel::DEFAULT_MAX_VALUE)
+/// An axis along which a rectangle can be split.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Axis {
+    /// Split at an x coordinate, with a vertical line.
+    X,
+    /// Split at a y coordinate, with a horizontal line.
+    Y,
 }
 
-fn white<P: Pixel>() -> P {
-    *P::from_slice(&vec![
-        // make everything max for white
-        P::Subpixel::DEFAULT_MAX_VALUE;
-        P::CHANNEL_COUNT as usize
-    ])
+/// What to draw inside each of the final rectangles, underneath the dividing lines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FillMode {
+    /// Leave them as the background colour.
+    Lines,
+    /// Fill each one with the average colour of the input pixels it covers.
+    Mosaic,
+    /// Fill them alternately with the line and background colours, so that the two children of
+    /// every split are different colours.
+    Checkerboard,
+    /// Draw the outline of the ellipse that fits inside each one in the line colour, instead of
+    /// any dividing lines. With `Shape::Rectangle`, the outline goes just inside the edges of
+    /// each rectangle instead.
+    Ellipses,
+    /// Fill the ellipse that fits inside each one with the line colour, instead of drawing any
+    /// dividing lines.
+    FilledEllipses,
+    /// Draw a filled circle in the line colour in the middle of each one, sized according to how
+    /// dark it is and `Settings::halftone_radius`, instead of drawing any dividing lines. With
+    /// `Shape::Rectangle`, the dots are squares as wide as the circles would be.
+    Halftone,
+    /// Fill each one with one of `levels` evenly spaced shades of grey, picked by rounding its
+    /// mean darkness down to a multiple of `1 / levels`: a rectangle with a mean darkness of `d`
+    /// gets level `floor(d * levels)` (or `levels - 1`, if `d` is 1), which is drawn with a
+    /// brightness of `1 - level / (levels - 1)`. That makes level 0 white and the last level
+    /// black, and darker rectangles never get a lighter level than lighter ones.
+    ///
+    /// Each rectangle's level is given by [`LeafInfo::level`]. On colour outputs, the grey is
+    /// written to every channel. `levels` must be at least 2.
+    Quantized { levels: u8 },
+    /// Leave most of them as the background colour, but fill a random `fill_probability` of
+    /// them with a colour picked at random from `fill_palette`, like a Mondrian painting.
+    ///
+    /// Whether each one gets filled and what with only depends on `seed` and where it is, so
+    /// rectangles that come out the same after changing other settings keep their colours.
+    Mondrian,
+    /// Fill each one with a gradient along its longer side, from the background colour towards
+    /// the line colour by how dark it is, so that big rectangles still show which way the input
+    /// gets darker. The gradient goes through the mean darkness of each half of the rectangle
+    /// at that half's middle, and carries on at the same slope out to the edges.
+    ///
+    /// Halves of diagonal splits, and rectangles drawn by [`render`] (which only records the
+    /// darkness of each rectangle as a whole), are filled with their mean darkness instead.
+    Gradient,
 }
 
-fn horizontal_line<I: GenericImage>(image: &mut I, y: f64, start_x: f64, end_x: f64) {
-    let start_x = (start_x * image.width() as f64).floor() as u32;
-    let end_x = (end_x * image.width() as f64).ceil() as u32 - 1;
-    let y = (y * image.height() as f64).floor() as u32;
+impl FillMode {
+    /// Returns the level out of `levels` that a rectangle with a mean darkness of `darkness` gets
+    /// with `FillMode::Quantized`.
+    fn level(darkness: f64, levels: u8) -> u8 {
+        ((darkness.clamp(0.0, 1.0) * levels as f64).floor() as u8).min(levels - 1)
+    }
+
+    /// Returns the colour `FillMode::Mondrian` fills `area` (in input pixels) with, or the half of
+    /// it above or below the diagonal if `above` is given, or `None` if it's left empty.
+    fn mondrian_color(area: Rectangle, above: Option<bool>, settings: &Settings) -> Option<Color> {
+        let values = [
+            area.left.to_bits(),
+            area.top.to_bits(),
+            area.right.to_bits(),
+            area.bottom.to_bits(),
+            above.map_or(0, |above| above as u64 + 1),
+        ];
+        if settings.fill_palette.is_empty()
+            || random(settings.seed, &values) >= settings.fill_probability
+        {
+            return None;
+        }
+        // Pick the colour with a different stream of random numbers, so that it isn't tied to
+        // whether the rectangle got filled.
+        let pick = random(!settings.seed, &values);
+        let index = (pick * settings.fill_palette.len() as f64) as usize;
+        Some(settings.fill_palette[index.min(settings.fill_palette.len() - 1)])
+    }
 
-    for x in start_x..=end_x {
-        image.put_pixel(x, y, black());
+    /// Returns the colour a rectangle with a mean darkness of `darkness` gets filled with when
+    /// this is `FillMode::Quantized`.
+    fn quantized_color(self, darkness: f64) -> Option<Color> {
+        match self {
+            FillMode::Quantized { levels } => {
+                let level = FillMode::level(darkness, levels) as f64;
+                let value = 1.0 - level / (levels - 1) as f64;
+                Some(Color::rgb(value, value, value))
+            }
+            _ => None,
+        }
     }
 }
 
-fn vertical_line<I: GenericImage>(image: &mut I, x: f64, start_y: f64, end_y: f64) {
-    let start_y = (start_y * image.height() as f64).floor() as u32;
-    let end_y = (end_y * image.height() as f64).ceil() as u32 - 1;
-    let x = (x * image.width() as f64).floor() as u32;
+/// The shape drawn inside each rectangle with `FillMode::Ellipses` and `FillMode::Halftone`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Shape {
+    /// Outlines of the ellipse that fits inside each rectangle, or round halftone dots.
+    Ellipse,
+    /// Outlines just inside the edges of each rectangle, or square halftone dots.
+    Rectangle,
+}
+
+/// A colour with red, green, blue and alpha components in the range 0 to 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Color {
+    pub r: f64,
+    pub g: f64,
+    pub b: f64,
+    pub a: f64,
+}
+
+impl Color {
+    pub const BLACK: Color = Color::rgb(0.0, 0.0, 0.0);
+    pub const WHITE: Color = Color::rgb(1.0, 1.0, 1.0);
+
+    /// Creates an opaque colour.
+    pub const fn rgb(r: f64, g: f64, b: f64) -> Color {
+        Color { r, g, b, a: 1.0 }
+    }
+
+    /// Returns the luma of this colour, using the same weights as `image`.
+    pub fn luma(self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    /// Converts this colour into a pixel, based on how many channels it has: 1 and 2 channel
+    /// pixels are treated as luma (+ alpha), and 3 and 4 channel pixels as RGB(A).
+    ///
+    /// Integer subpixels are scaled up to their full range, and floating-point ones are left
+    /// in the range 0 to 1.
+    pub fn to_pixel<P: Pixel>(self) -> P {
+        let channels = match P::CHANNEL_COUNT {
+            1 => [self.luma(), 0.0, 0.0, 0.0],
+            2 => [self.luma(), self.a, 0.0, 0.0],
+            3 => [self.r, self.g, self.b, 0.0],
+            _ => [self.r, self.g, self.b, self.a],
+        };
+
+        let max = P::Subpixel::DEFAULT_MAX_VALUE.to_f64().unwrap();
+        let channels = channels.map(|value| {
+            let value = value.clamp(0.0, 1.0) * max;
+            let value = if max > 1.0 { value.round() } else { value };
+            <P::Subpixel as NumCast>::from(value).unwrap()
+        });
+
+        *P::from_slice(&channels[..P::CHANNEL_COUNT as usize])
+    }
+
+    /// Linearly interpolates between this colour and `other`, where an `amount` of 0 gives this
+    /// colour and 1 gives `other`.
+    pub fn mix(self, other: Color, amount: f64) -> Color {
+        let mix = |a: f64, b: f64| a + (b - a) * amount;
+        Color {
+            r: mix(self.r, other.r),
+            g: mix(self.g, other.g),
+            b: mix(self.b, other.b),
+            a: mix(self.a, other.a),
+        }
+    }
+
+    /// Composites this colour over `below`, with this colour's alpha multiplied by `amount`.
+    /// Where neither is visible, this returns `below`.
+    pub fn over(self, below: Color, amount: f64) -> Color {
+        let alpha = self.a * amount;
+        let a = alpha + below.a * (1.0 - alpha);
+        if a <= 0.0 {
+            return below;
+        }
+        let over = |above: f64, under: f64| (above * alpha + under * below.a * (1.0 - alpha)) / a;
+        Color {
+            r: over(self.r, below.r),
+            g: over(self.g, below.g),
+            b: over(self.b, below.b),
+            a,
+        }
+    }
+
+    /// Converts a pixel into a colour, interpreting its channels the same way as `to_pixel`.
+    pub fn from_pixel<P: Pixel>(pixel: P) -> Color {
+        let max = P::Subpixel::DEFAULT_MAX_VALUE.to_f64().unwrap();
+        let mut channels = [0.0; 4];
+        for (channel, &value) in channels.iter_mut().zip(pixel.channels()) {
+            *channel = value.to_f64().unwrap() / max;
+        }
 
-    for y in start_y..=end_y {
-        image.put_pixel(x, y, black());
+        match P::CHANNEL_COUNT {
+            1 => Color::rgb(channels[0], channels[0], channels[0]),
+            2 => Color {
+                a: channels[1],
+                ..Color::rgb(channels[0], channels[0], channels[0])
+            },
+            3 => Color::rgb(channels[0], channels[1], channels[2]),
+            _ => Color {
+                r: channels[0],
+                g: channels[1],
+                b: channels[2],
+                a: channels[3],
+            },
+        }
     }
 }
 
-pub fn rectanglify<I: GenericImageView, O: GenericImage>(
-    input: &I,
-    output: &mut O,
-    mut settings: Settings,
-) {
-    let total_darkness: f64 = input.pixels().map(|(_, _, p)| darkness(p)).sum();
-    let num_rects = (total_darkness * settings.rects_per_pixel).round() as usize;
-    // Adjust this so that it actually matches the number of rectangles we're drawing.
-    settings.rects_per_pixel = num_rects as f64 / total_darkness;
+/// An axis-aligned rectangle, with fractional coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Rectangle {
+    pub left: f64,
+    pub top: f64,
+    pub right: f64,
+    pub bottom: f64,
+}
+
+impl Rectangle {
+    pub fn width(&self) -> f64 {
+        self.right - self.left
+    }
 
-    // fill the output with white to start with
-    for x in 0..output.width() {
-        for y in 0..output.height() {
-            output.put_pixel(x, y, white())
+    pub fn height(&self) -> f64 {
+        self.bottom - self.top
+    }
+
+    /// Returns whether this rectangle has no area.
+    pub fn is_empty(&self) -> bool {
+        !(self.width() > 0.0 && self.height() > 0.0)
+    }
+
+    /// Returns how many times longer the longer side is than the shorter side.
+    fn aspect_ratio(&self) -> f64 {
+        f64::max(self.width() / self.height(), self.height() / self.width())
+    }
+
+    /// Returns whether `other` is entirely inside this rectangle.
+    fn encloses(&self, other: Rectangle) -> bool {
+        self.left <= other.left
+            && self.top <= other.top
+            && self.right >= other.right
+            && self.bottom >= other.bottom
+    }
+
+    /// Splits this rectangle in two at `at` along `axis`, returning the left or top half first.
+    fn split(&self, axis: Axis, at: f64) -> (Rectangle, Rectangle) {
+        match axis {
+            Axis::X => (
+                Rectangle { right: at, ..*self },
+                Rectangle { left: at, ..*self },
+            ),
+            Axis::Y => (
+                Rectangle {
+                    bottom: at,
+                    ..*self
+                },
+                Rectangle { top: at, ..*self },
+            ),
         }
     }
 
-    draw_rects(
-        input,
-        output,
-        settings,
+    /// Converts this rectangle from pixel coordinates in an image of the given size to the range
+    /// 0 to 1.
+    fn normalize(&self, (width, height): (f64, f64)) -> Rectangle {
         Rectangle {
-            left: 0.0,
-            top: 0.0,
-            right: input.width() as f64,
-            bottom: input.height() as f64,
-        },
-        num_rects,
-    )
+            left: self.left / width,
+            top: self.top / height,
+            right: self.right / width,
+            bottom: self.bottom / height,
+        }
+    }
 }
 
-fn draw_rects(
-    input: &impl GenericImageView,
-    output: &mut impl GenericImage,
-    settings: Settings,
-    area: Rectangle,
-    rects: usize,
-) {
-    if rects == 1 {
-        return;
+/// A horizontal or vertical line segment, with fractional coordinates. With
+/// `SplitStrategy::Diagonal`, it can also be the diagonal of a rectangle.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line {
+    /// The `(x, y)` coordinates of the top or left end of the line.
+    pub start: (f64, f64),
+    /// The `(x, y)` coordinates of the bottom or right end of the line.
+    pub end: (f64, f64),
+    /// How many splits down the lines that the start and end of this line run into are, or
+    /// `None` where they run into the edge of the image (or `Settings::roi`) instead. These are
+    /// always `None` for diagonals, which only ever end at corners.
+    pub ends: [Option<u32>; 2],
+}
+
+impl Line {
+    /// Returns this line with `start` taken off its start and `end` off its end, or `None` if
+    /// there's nothing left of it. Diagonals are left alone, since they only ever meet other
+    /// lines at their corners.
+    pub(crate) fn trimmed(self, start: f64, end: f64) -> Option<Line> {
+        let ((x1, y1), (x2, y2)) = (self.start, self.end);
+        let (start, end) = if x1 == x2 {
+            ((x1, y1 + start), (x2, y2 - end))
+        } else if y1 == y2 {
+            ((x1 + start, y1), (x2 - end, y2))
+        } else {
+            return Some(self);
+        };
+        (start.0 <= end.0 && start.1 <= end.1).then_some(Line { start, end, ..self })
     }
+}
 
-    // The amount of darkness we've found so far.
-    let mut darkness = 0.0;
+/// One of the two triangles a rectangle is cut into by a diagonal split.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Half {
+    /// Whether the diagonal goes from the top left to the bottom right, rather than from the
+    /// bottom left to the top right.
+    falling: bool,
+    /// Whether this is the half above the diagonal, rather than below it.
+    above: bool,
+}
 
-    // The target number of rectangles to be in the first half.
-    let target_rects = rects / 2;
-    // The target amount of darkness in the first half.
-    let target_darkness = target_rects as f64 / settings.rects_per_pixel;
+impl Half {
+    /// Returns whether the point `x`, `y` is in this half of `area`. Points right on the
+    /// diagonal count as below it.
+    fn contains(self, area: Rectangle, x: f64, y: f64) -> bool {
+        let along = (x - area.left) / area.width();
+        let diagonal = if self.falling {
+            area.top + along * area.height()
+        } else {
+            area.bottom - along * area.height()
+        };
+        (y < diagonal) == self.above
+    }
+}
 
-    if area.width() > area.height() {
-        // split it horizontally
-        for x in area.left.floor() as u32..area.right.ceil() as u32 {
-            let mut column_darkness = 0.0;
-            for y in area.top.floor() as u32..area.bottom.ceil() as u32 {
-                column_darkness += darkness_at(input, area, x, y);
+/// Returns the luma of a pixel, from 0 to 1.
+fn luma<P: Pixel>(p: P) -> f64 {
+    p.to_luma()[0].to_f64().unwrap() / P::Subpixel::DEFAULT_MAX_VALUE.to_f64().unwrap()
+}
+
+/// Decodes an sRGB channel value into linear light.
+fn srgb_to_linear(value: f64) -> f64 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Returns how dark a pixel is according to `settings.brightness`, from 0 to 1; or how bright it
+/// is if `settings.invert` is set. If `equalizer` is given, it's applied before
+/// `settings.threshold` and `settings.gamma`.
+///
+/// For pixels with an alpha channel (the same ones as `Color::to_pixel`), this is scaled by
+/// alpha, so transparent pixels don't count at all.
+fn darkness<P: Pixel>(p: P, settings: &Settings, equalizer: Option<&Equalizer>) -> f64 {
+    with_alpha(p, tone(brightness(p, settings), settings, equalizer))
+}
+
+/// Returns how bright a pixel is according to `settings.brightness`, from 0 to 1.
+fn brightness<P: Pixel>(p: P, settings: &Settings) -> f64 {
+    match &settings.brightness {
+        Brightness::Luma => luma(p),
+        Brightness::Rec709 => {
+            let color = Color::from_pixel(p);
+            Color::rgb(
+                srgb_to_linear(color.r),
+                srgb_to_linear(color.g),
+                srgb_to_linear(color.b),
+            )
+            .luma()
+        }
+        Brightness::MaxChannel => {
+            let color = Color::from_pixel(p);
+            color.r.max(color.g).max(color.b)
+        }
+        Brightness::DistanceTo(key) => {
+            let color = Color::from_pixel(p);
+            if P::CHANNEL_COUNT < 3 {
+                (color.luma() - key.luma()).abs()
+            } else {
+                let (r, g, b) = (color.r - key.r, color.g - key.g, color.b - key.b);
+                (r * r + g * g + b * b).sqrt() / 3f64.sqrt()
             }
-            darkness += column_darkness;
+        }
+        Brightness::Custom(brightness) => brightness(Color::from_pixel(p)),
+    }
+}
 
-            if darkness >= target_darkness {
-                let overshoot = darkness - target_darkness;
-                // Find the exact point of the split by taking away the amount we overshot.
-                let split = (x + 1) as f64 - overshoot / column_darkness;
+/// Turns the brightness of a pixel into its darkness, applying `settings.invert`, `equalizer`
+/// (if given), and then `settings.threshold` and `settings.gamma`.
+fn tone(brightness: f64, settings: &Settings, equalizer: Option<&Equalizer>) -> f64 {
+    let darkness = exposed(brightness, settings);
+    let darkness = match equalizer {
+        Some(equalizer) => equalizer.equalize(darkness),
+        None => darkness,
+    };
 
-                // Draw the line dividing the two new rectangles
-                vertical_line(
-                    output,
-                    split / input.width() as f64,
-                    area.top / input.height() as f64,
-                    area.bottom / input.height() as f64,
-                );
+    if let Some(threshold) = settings.threshold {
+        if darkness > threshold {
+            1.0
+        } else {
+            0.0
+        }
+    } else if settings.gamma == 1.0 {
+        darkness
+    } else {
+        darkness.powf(settings.gamma)
+    }
+}
 
-                let left = Rectangle {
-                    right: split,
-                    ..area
-                };
-                let right = Rectangle {
-                    left: split,
-                    ..area
+/// Scales the brightness of a pixel by `settings.exposure` and turns it into its darkness, or
+/// leaves it as brightness if `settings.invert` is set.
+///
+/// Out-of-range brightnesses (like HDR highlights) are clamped from 0 to 1, and NaNs count as no
+/// darkness at all, so that the totals can't go negative or NaN.
+fn exposed(brightness: f64, settings: &Settings) -> f64 {
+    if brightness.is_nan() {
+        return 0.0;
+    }
+    let brightness = (brightness * settings.exposure).clamp(0.0, 1.0);
+    if settings.invert {
+        brightness
+    } else {
+        1.0 - brightness
+    }
+}
+
+/// How many levels `Settings::equalize` sorts the darkness of each pixel into.
+const EQUALIZE_LEVELS: usize = 256;
+
+/// A mapping from each level of darkness in an image to its histogram-equalised darkness, for
+/// `Settings::equalize`.
+#[derive(Debug, Clone)]
+struct Equalizer {
+    levels: [f64; EQUALIZE_LEVELS],
+}
+
+impl Equalizer {
+    /// Counts up how many pixels of `image` there are at each level of darkness (before
+    /// `settings.threshold` and `settings.gamma`), checking `cancel` after each row.
+    fn new<I: GenericImageView>(
+        image: &I,
+        settings: &Settings,
+        cancel: Option<&AtomicBool>,
+    ) -> Result<Self, Cancelled> {
+        let lut = DarknessLut::new::<I::Pixel>(settings, None);
+        let mut histogram = [0u64; EQUALIZE_LEVELS];
+        for y in 0..image.height() {
+            check(cancel)?;
+            for x in 0..image.width() {
+                let p = image.get_pixel(x, y);
+                let value = match &lut {
+                    Some(lut) => lut.luma(p) as f64 / 255.0,
+                    None => brightness(p, settings),
                 };
+                histogram[Self::level(exposed(value, settings))] += 1;
+            }
+        }
+
+        // Each level maps to the fraction of the pixels that are at most that dark, shifted so
+        // that the lightest level in the image maps to 0.
+        let total: u64 = histogram.iter().sum();
+        let lightest = histogram
+            .iter()
+            .copied()
+            .find(|&count| count > 0)
+            .unwrap_or(0);
+        let mut levels = [0.0; EQUALIZE_LEVELS];
+        let mut cumulative = 0;
+        for (level, (equalized, &count)) in levels.iter_mut().zip(&histogram).enumerate() {
+            cumulative += count;
+            *equalized = match total - lightest {
+                // Every pixel is the same, so there's nothing to spread out.
+                0 => level as f64 / (EQUALIZE_LEVELS - 1) as f64,
+                range => cumulative.saturating_sub(lightest) as f64 / range as f64,
+            };
+        }
+        Ok(Equalizer { levels })
+    }
+
+    /// Returns which level `darkness` is in.
+    fn level(darkness: f64) -> usize {
+        (darkness.clamp(0.0, 1.0) * (EQUALIZE_LEVELS - 1) as f64).round() as usize
+    }
 
-                draw_rects(input, output, settings, left, rects / 2);
-                draw_rects(input, output, settings, right, rects - rects / 2);
+    /// Returns the equalised version of `darkness`.
+    fn equalize(&self, darkness: f64) -> f64 {
+        self.levels[Self::level(darkness)]
+    }
+}
 
-                return;
+/// Scales `darkness` by the alpha of `p`, if it has one. Out-of-range alphas in floating-point
+/// images are clamped from 0 to 1, and NaN counts as transparent.
+fn with_alpha<P: Pixel>(p: P, darkness: f64) -> f64 {
+    match P::CHANNEL_COUNT {
+        2 | 4 => {
+            let alpha = p.channels()[P::CHANNEL_COUNT as usize - 1];
+            let alpha = alpha.to_f64().unwrap() / P::Subpixel::DEFAULT_MAX_VALUE.to_f64().unwrap();
+            match alpha.is_nan() {
+                true => 0.0,
+                false => darkness * alpha.clamp(0.0, 1.0),
             }
         }
-    } else {
-        // split it vertically
-        for y in area.top.floor() as u32..area.bottom.ceil() as u32 {
-            let mut row_darkness = 0.0;
-            for x in area.left.floor() as u32..area.right.ceil() as u32 {
-                row_darkness += darkness_at(input, area, x, y);
+        _ => darkness,
+    }
+}
+
+/// The darkness of 8-bit pixels for each possible luma, worked out up-front so that `darkness`
+/// doesn't have to convert every pixel to floating-point and apply the settings to it.
+struct DarknessLut {
+    darkness: [f64; 256],
+    /// Where to get each pixel's luma from.
+    layout: Layout,
+}
+
+/// How to get the luma of an 8-bit pixel straight from its channels, without going through
+/// `Pixel::to_luma`.
+#[derive(Debug, Clone, Copy)]
+enum Layout {
+    /// The first channel is the luma, like `Luma` and `LumaA`.
+    Gray,
+    /// The first three channels are red, green and blue, like `Rgb` and `Rgba`.
+    Rgb,
+    /// Anything else, which does have to go through `to_luma`.
+    Other,
+}
+
+impl DarknessLut {
+    /// Creates a table for pixels of type `P`, applying `equalizer` if given, or returns `None`
+    /// if they aren't 8-bit or `settings.brightness` depends on more than their luma.
+    fn new<P: Pixel>(settings: &Settings, equalizer: Option<&Equalizer>) -> Option<Self> {
+        let min = P::Subpixel::DEFAULT_MIN_VALUE.to_u64();
+        let max = P::Subpixel::DEFAULT_MAX_VALUE.to_u64();
+        if min != Some(0) || max != Some(255) || !matches!(settings.brightness, Brightness::Luma) {
+            return None;
+        }
+
+        // This has to divide the same way as `luma` does to give exactly the same results.
+        let mut darkness = [0.0; 256];
+        for (value, darkness) in darkness.iter_mut().enumerate() {
+            *darkness = tone(value as f64 / 255.0, settings, equalizer);
+        }
+        let layout = match P::COLOR_MODEL {
+            "Y" | "YA" => Layout::Gray,
+            "RGB" | "RGBA" => Layout::Rgb,
+            _ => Layout::Other,
+        };
+        Some(DarknessLut { darkness, layout })
+    }
+
+    /// Returns the same as `darkness(p, settings, equalizer)`, where `settings` and `equalizer`
+    /// are what the table was made with.
+    fn darkness<P: Pixel>(&self, p: P) -> f64 {
+        with_alpha(p, self.darkness[self.luma(p) as usize])
+    }
+
+    /// Returns the luma of `p`, rounded the same way as `Pixel::to_luma`.
+    fn luma<P: Pixel>(&self, p: P) -> u8 {
+        let channels = p.channels();
+        let channel = |i: usize| channels[i].to_u8().unwrap();
+        match self.layout {
+            Layout::Gray => channel(0),
+            // The same sum `image` uses for `to_luma`, so that this rounds the same way.
+            Layout::Rgb => {
+                let sum =
+                    2126 * channel(0) as u32 + 7152 * channel(1) as u32 + 722 * channel(2) as u32;
+                (sum / 10000) as u8
             }
-            darkness += row_darkness;
+            Layout::Other => p.to_luma()[0].to_u8().unwrap(),
+        }
+    }
 
-            if darkness >= target_darkness {
-                let overshoot = darkness - target_darkness;
-                // Find the exact point of the split by taking away the amount we overshot.
-                let split = (y + 1) as f64 - overshoot / row_darkness;
+    /// Fills `row` with the darkness of each pixel in row `y` of `image`.
+    fn row<I: GenericImageView>(&self, image: &I, y: u32, row: &mut [f64]) {
+        for (x, darkness) in (0..).zip(row) {
+            *darkness = self.darkness(image.get_pixel(x, y));
+        }
+    }
+}
 
-                // Draw the line dividing the two new rectangles
-                horizontal_line(
-                    output,
-                    split / input.height() as f64,
-                    area.left / input.width() as f64,
-                    area.right / input.width() as f64,
-                );
+/// Returns a pseudo-random number from 0 to 1 (exclusive), determined entirely by `seed` and
+/// `values`.
+fn random(seed: u64, values: &[u64]) -> f64 {
+    // SplitMix64's finaliser, applied after mixing in each value.
+    let mut state = seed;
+    for &value in values {
+        state ^= value;
+        state = state.wrapping_add(0x9e3779b97f4a7c15);
+        state = (state ^ (state >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        state = (state ^ (state >> 27)).wrapping_mul(0x94d049bb133111eb);
+        state ^= state >> 31;
+    }
+    (state >> 11) as f64 / (1u64 << 53) as f64
+}
 
-                let top = Rectangle {
-                    bottom: split,
-                    ..area
-                };
-                let bottom = Rectangle { top: split, ..area };
+/// Returns how much of pixel `i` lies between `start` and `end`, along one axis.
+fn coverage_1d(i: u32, start: f64, end: f64) -> f64 {
+    if (i as f64) < start {
+        f64::max((i + 1) as f64 - start, 0.0)
+    } else if (i + 1) as f64 > end {
+        f64::max(end - i as f64, 0.0)
+    } else {
+        1.0
+    }
+}
+
+/// Returns how much of the pixel at `x`, `y` lies inside `rect`, horizontally and vertically.
+fn coverage(rect: Rectangle, x: u32, y: u32) -> (f64, f64) {
+    (
+        coverage_1d(x, rect.left, rect.right),
+        coverage_1d(y, rect.top, rect.bottom),
+    )
+}
+
+/// A running total that uses Kahan summation to keep track of the rounding error from each
+/// addition, so that it doesn't build up over the hundreds of millions of pixels in a big image.
+#[derive(Debug, Clone, Copy, Default)]
+struct Sum {
+    total: f64,
+    /// The error in `total` from the last addition, which gets taken off the next value added.
+    compensation: f64,
+}
+
+impl Sum {
+    fn add(&mut self, value: f64) {
+        let value = value - self.compensation;
+        let total = self.total + value;
+        self.compensation = (total - self.total) - value;
+        self.total = total;
+    }
+}
+
+/// A function giving how much to multiply the darkness of the pixel at `x`, `y` by.
+type Weight<'a> = &'a dyn Fn(u32, u32) -> f64;
+
+/// What the darkness of each pixel gets rounded to a multiple of the reciprocal of with
+/// `Settings::deterministic`.
+const FIXED_POINT_ONE: f64 = (1u64 << 16) as f64;
+/// What split positions get rounded to a multiple of the reciprocal of with
+/// `Settings::deterministic`.
+const FIXED_POINT_POSITION: f64 = 256.0;
+
+/// The memory used by a `DarknessTable`, kept around between frames by `Rectanglifier` so that
+/// it doesn't need to be allocated again each time.
+#[derive(Debug, Clone, Default)]
+struct TableBuffers {
+    sums: Vec<f64>,
+    squares: Vec<f64>,
+}
 
-                draw_rects(input, output, settings, top, rects / 2);
-                draw_rects(input, output, settings, bottom, rects - rects / 2);
+/// A summed-area table of the darkness of every pixel in an image, so that the darkness of any
+/// rectangle can be found in constant time.
+///
+/// This takes `(width + 1) * (height + 1) * 8` bytes, so about 16MB for a 1080p image.
+struct DarknessTable {
+    /// The width of the table, which is 1 more than the width of the image.
+    stride: usize,
+    /// The total darkness of all the pixels above and to the left of each position.
+    sums: Vec<f64>,
+    /// The same as `sums`, but for the squares of the darkness of each pixel. This is only filled
+    /// in if `settings.variance_threshold` is set, and is empty otherwise.
+    squares: Vec<f64>,
+}
+
+impl DarknessTable {
+    /// Creates a table of the darkness of `image`, with each pixel's darkness multiplied by
+    /// `weight` if given, checking `cancel` after each row. The table is stored in `buffers`,
+    /// which are only reallocated if they're too small.
+    fn new<I: GenericImageView>(
+        image: &I,
+        weight: Option<Weight>,
+        cancel: Option<&AtomicBool>,
+        settings: &Settings,
+        buffers: TableBuffers,
+    ) -> Result<Self, Cancelled> {
+        let edges = settings.edges.clamp(0.0, 1.0);
+        let gradient = match edges > 0.0 {
+            true => Some(gradient(image, settings.exposure, settings.wrap, cancel)?),
+            false => None,
+        };
+
+        let equalizer = match settings.equalize {
+            true => Some(Equalizer::new(image, settings, cancel)?),
+            false => None,
+        };
+        let lut = DarknessLut::new::<I::Pixel>(settings, equalizer.as_ref());
+        // Fills `row` with the darkness of each pixel in row `y`.
+        let row_darkness = |y: u32, row: &mut [f64]| {
+            match &lut {
+                Some(lut) => lut.row(image, y, row),
+                None => {
+                    for (x, value) in (0..).zip(&mut *row) {
+                        *value = darkness(image.get_pixel(x, y), settings, equalizer.as_ref());
+                    }
+                }
+            }
+            if let Some(gradient) = &gradient {
+                let start = y as usize * image.width() as usize;
+                for (darkness, &edge) in row.iter_mut().zip(&gradient[start..]) {
+                    *darkness += (edge - *darkness) * edges;
+                }
+            }
+            if let Some(weight) = weight {
+                for (x, darkness) in (0..).zip(row) {
+                    *darkness *= weight(x, y);
+                }
+            }
+        };
+
+        // To be blurred, the darkness has to be worked out up-front.
+        let blurred = match settings.smoothing {
+            Some(sigma) if sigma > 0.0 => {
+                let width = image.width() as usize;
+                let mut values = vec![0.0; width * image.height() as usize];
+                for y in 0..image.height() {
+                    check(cancel)?;
+                    let start = y as usize * width;
+                    row_darkness(y, &mut values[start..start + width]);
+                }
+                blur(&mut values, width, sigma, settings.wrap);
+                Some(values)
+            }
+            _ => None,
+        };
 
-                return;
+        let stride = image.width() as usize + 1;
+        let len = stride * (image.height() as usize + 1);
+        let TableBuffers {
+            mut sums,
+            mut squares,
+        } = buffers;
+        sums.clear();
+        sums.resize(len, 0.0);
+        squares.clear();
+        if settings.variance_threshold.is_some() {
+            squares.resize(len, 0.0);
+        }
+
+        // The running totals of each column of the table, so that the error in each entry doesn't
+        // depend on how many rows are above it.
+        let mut columns = vec![Sum::default(); stride - 1];
+        let mut square_columns =
+            vec![Sum::default(); if squares.is_empty() { 0 } else { stride - 1 }];
+        // The same, in units of `1 / FIXED_POINT_ONE`, for `settings.deterministic`.
+        let fixed_len = if settings.deterministic {
+            stride - 1
+        } else {
+            0
+        };
+        let mut fixed_columns = vec![0i64; fixed_len];
+        // The darkness of each pixel in the current row, if it isn't in `blurred`.
+        let mut row_values = vec![0.0; if blurred.is_some() { 0 } else { stride - 1 }];
+        for y in 0..image.height() as usize {
+            check(cancel)?;
+            let values = match &blurred {
+                Some(values) => &values[y * (stride - 1)..(y + 1) * (stride - 1)],
+                None => {
+                    row_darkness(y as u32, &mut row_values);
+                    &row_values
+                }
+            };
+            let mut row = Sum::default();
+            let mut square_row = Sum::default();
+            let mut fixed_row = 0i64;
+            for (x, &darkness) in values.iter().enumerate() {
+                let mut darkness = darkness;
+                if settings.deterministic {
+                    let fixed = (darkness * FIXED_POINT_ONE).round() as i64;
+                    darkness = fixed as f64 / FIXED_POINT_ONE;
+                    fixed_row += fixed;
+                    fixed_columns[x] += fixed_row;
+                    sums[(y + 1) * stride + x + 1] = fixed_columns[x] as f64 / FIXED_POINT_ONE;
+                } else {
+                    row.add(darkness);
+                    columns[x].add(row.total);
+                    sums[(y + 1) * stride + x + 1] = columns[x].total;
+                }
+                if !squares.is_empty() {
+                    square_row.add(darkness * darkness);
+                    square_columns[x].add(square_row.total);
+                    squares[(y + 1) * stride + x + 1] = square_columns[x].total;
+                }
             }
         }
+
+        Ok(DarknessTable {
+            stride,
+            sums,
+            squares,
+        })
+    }
+
+    /// Gives back the memory this table was stored in, for making another one.
+    fn into_buffers(self) -> TableBuffers {
+        TableBuffers {
+            sums: self.sums,
+            squares: self.squares,
+        }
+    }
+
+    /// Returns the total darkness of the image.
+    fn total(&self) -> f64 {
+        *self.sums.last().unwrap()
+    }
+
+    /// Returns the total darkness of the pixels from `left` to `right` and `top` to `bottom`
+    /// (exclusive).
+    fn sum(&self, left: u32, top: u32, right: u32, bottom: u32) -> f64 {
+        self.rect_sum(&self.sums, left, top, right, bottom)
+    }
+
+    /// Returns the total from `left` to `right` and `top` to `bottom` (exclusive) of `table`,
+    /// which is either `sums` or `squares`.
+    fn rect_sum(&self, table: &[f64], left: u32, top: u32, right: u32, bottom: u32) -> f64 {
+        if left >= right || top >= bottom {
+            return 0.0;
+        }
+
+        let at = |x: u32, y: u32| table[y as usize * self.stride + x as usize];
+        at(right, bottom) - at(left, bottom) - at(right, top) + at(left, top)
+    }
+
+    /// Returns the variance of the darkness of all the pixels `area` overlaps.
+    ///
+    /// This is only available if `settings.variance_threshold` was set when the table was made.
+    fn variance(&self, area: Rectangle) -> f64 {
+        let left = area.left.floor() as u32;
+        let top = area.top.floor() as u32;
+        let right = area.right.ceil() as u32;
+        let bottom = area.bottom.ceil() as u32;
+        let count = ((right - left) * (bottom - top)) as f64;
+        if count == 0.0 {
+            return 0.0;
+        }
+
+        let mean = self.sum(left, top, right, bottom) / count;
+        let mean_square = self.rect_sum(&self.squares, left, top, right, bottom) / count;
+        // Rounding error can make this very slightly negative for flat areas.
+        (mean_square - mean * mean).max(0.0)
+    }
+
+    /// Returns the total darkness of `area`, including the pixels it only partially covers.
+    fn area(&self, area: Rectangle) -> f64 {
+        let mut sum = Sum::default();
+        for x in area.left.floor() as u32..area.right.ceil() as u32 {
+            sum.add(self.line(Axis::X, x, area));
+        }
+        sum.total
+    }
+
+    /// The same as `area`, but in constant time rather than going through every column, by
+    /// splitting `area` up into the whole pixels in the middle and the partially covered ones
+    /// around the edges. The result can differ from `area` by rounding error.
+    fn block(&self, area: Rectangle) -> f64 {
+        // Returns the ranges of pixels `area` covers between `start` and `end`, along with how
+        // much of each pixel in them it covers.
+        let ranges = |start: f64, end: f64| {
+            let first = start.floor() as u32;
+            let last = end.ceil() as u32;
+            if last <= first + 1 {
+                return [(first, last, end - start), (0, 0, 0.0), (0, 0, 0.0)];
+            }
+            [
+                (first, first + 1, coverage_1d(first, start, end)),
+                (first + 1, last - 1, 1.0),
+                (last - 1, last, coverage_1d(last - 1, start, end)),
+            ]
+        };
+
+        let mut sum = Sum::default();
+        for (left, right, horizontal) in ranges(area.left, area.right) {
+            for (top, bottom, vertical) in ranges(area.top, area.bottom) {
+                sum.add(self.sum(left, top, right, bottom) * horizontal * vertical);
+            }
+        }
+        sum.total
+    }
+
+    /// Returns the darkness of column or row `i` (depending on `axis`) of `area`, including the
+    /// pixels it only partially covers.
+    fn line(&self, axis: Axis, i: u32, area: Rectangle) -> f64 {
+        let (start, end, outer_start, outer_end) = match axis {
+            Axis::X => (area.top, area.bottom, area.left, area.right),
+            Axis::Y => (area.left, area.right, area.top, area.bottom),
+        };
+        // Returns the darkness of pixels `from` to `to` along the line.
+        let sum = |from: u32, to: u32| match axis {
+            Axis::X => self.sum(i, from, i + 1, to),
+            Axis::Y => self.sum(from, i, to, i + 1),
+        };
+
+        let first = start.floor() as u32;
+        let last = end.ceil() as u32;
+
+        // Work out the partially covered pixels at either end separately, the same way
+        // `coverage` does.
+        let mut darkness = 0.0;
+        let mut full_start = first;
+        let mut full_end = last;
+        if first < last && (first as f64) < start {
+            darkness += sum(first, first + 1) * coverage_1d(first, start, end);
+            full_start += 1;
+        }
+        if full_start < last && last as f64 > end {
+            darkness += sum(last - 1, last) * coverage_1d(last - 1, start, end);
+            full_end -= 1;
+        }
+        darkness += sum(full_start, full_end);
+
+        darkness * coverage_1d(i, outer_start, outer_end)
+    }
+}
+
+/// Returns the magnitude of the gradient of the luma of each pixel in `image` (as a row-major
+/// grid), found with a Sobel filter and scaled so that a sharp edge from black to white is 1.
+///
+/// The lumas are multiplied by `exposure` and clamped the same way as for `Settings::exposure`
+/// first. If `wrap` is set, pixels past each edge are taken from the opposite one.
+fn gradient(
+    image: &impl GenericImageView,
+    exposure: f64,
+    wrap: bool,
+    cancel: Option<&AtomicBool>,
+) -> Result<Vec<f64>, Cancelled> {
+    let (width, height) = image.dimensions();
+    let mut lumas = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+        check(cancel)?;
+        lumas.extend((0..width).map(|x| {
+            let luma = luma(image.get_pixel(x, y));
+            match luma.is_nan() {
+                // The same as white, like it is for the darkness.
+                true => 1.0,
+                false => (luma * exposure).clamp(0.0, 1.0),
+            }
+        }));
+    }
+
+    // Otherwise, pixels past the edges are treated as the same as the nearest edge.
+    let at = |x: i64, y: i64| {
+        let (x, y) = match wrap {
+            true => (x.rem_euclid(width as i64), y.rem_euclid(height as i64)),
+            false => (x.clamp(0, width as i64 - 1), y.clamp(0, height as i64 - 1)),
+        };
+        lumas[(y * width as i64 + x) as usize]
+    };
+
+    let mut gradient = Vec::with_capacity(lumas.len());
+    for y in 0..height as i64 {
+        check(cancel)?;
+        for x in 0..width as i64 {
+            let horizontal = (at(x + 1, y - 1) + 2.0 * at(x + 1, y) + at(x + 1, y + 1))
+                - (at(x - 1, y - 1) + 2.0 * at(x - 1, y) + at(x - 1, y + 1));
+            let vertical = (at(x - 1, y + 1) + 2.0 * at(x, y + 1) + at(x + 1, y + 1))
+                - (at(x - 1, y - 1) + 2.0 * at(x, y - 1) + at(x + 1, y - 1));
+            gradient.push((horizontal.hypot(vertical) / 4.0).min(1.0));
+        }
+    }
+
+    Ok(gradient)
+}
+
+/// Applies a Gaussian blur with a standard deviation of `sigma` to `values`, a row-major grid
+/// `width` values wide. Values past the edges are taken from the opposite edge if `wrap` is set,
+/// and are treated as the same as the nearest edge otherwise.
+fn blur(values: &mut [f64], width: usize, sigma: f64, wrap: bool) {
+    if width == 0 {
+        return;
+    }
+    let height = values.len() / width;
+
+    let radius = (sigma * 3.0).ceil() as isize;
+    let mut kernel: Vec<f64> = (-radius..=radius)
+        .map(|i| (-(i * i) as f64 / (2.0 * sigma * sigma)).exp())
+        .collect();
+    let total: f64 = kernel.iter().sum();
+    kernel.iter_mut().for_each(|weight| *weight /= total);
+
+    // Blurs the `len` values starting at `start` and `stride` apart.
+    let mut line = Vec::new();
+    let mut blur_line = |values: &mut [f64], start: usize, stride: usize, len: usize| {
+        line.clear();
+        line.extend((0..len).map(|i| values[start + i * stride]));
+        for i in 0..len {
+            values[start + i * stride] = kernel
+                .iter()
+                .enumerate()
+                .map(|(j, weight)| {
+                    let at = i as isize + j as isize - radius;
+                    let at = match wrap {
+                        true => at.rem_euclid(len as isize),
+                        false => at.clamp(0, len as isize - 1),
+                    };
+                    line[at as usize] * weight
+                })
+                .sum();
+        }
+    };
+
+    for y in 0..height {
+        blur_line(values, y * width, 1, width);
+    }
+    for x in 0..width {
+        blur_line(values, x, width, height);
+    }
+}
+
+/// Returns the average colour of the pixels in `rect`, weighted by how much of each pixel is
+/// inside it.
+///
+/// If `half` is given, only the pixels whose centres are in that half of `rect` are counted.
+fn average_color(image: &impl GenericImageView, rect: Rectangle, half: Option<Half>) -> Color {
+    let mut sum = [0.0; 4];
+    let mut total_weight = 0.0;
+    for y in rect.top.floor() as u32..rect.bottom.ceil() as u32 {
+        for x in rect.left.floor() as u32..rect.right.ceil() as u32 {
+            if half.is_some_and(|half| !half.contains(rect, x as f64 + 0.5, y as f64 + 0.5)) {
+                continue;
+            }
+            let (horizontal, vertical) = coverage(rect, x, y);
+            let weight = horizontal * vertical;
+            let color = Color::from_pixel(image.get_pixel(x, y));
+
+            sum[0] += color.r * weight;
+            sum[1] += color.g * weight;
+            sum[2] += color.b * weight;
+            sum[3] += color.a * weight;
+            total_weight += weight;
+        }
+    }
+
+    if total_weight == 0.0 {
+        return Color::WHITE;
+    }
+
+    let [r, g, b, a] = sum.map(|channel| channel / total_weight);
+    Color { r, g, b, a }
+}
+
+/// Fills the whole of `image` with `color`.
+///
+/// Only the first row is filled in a pixel at a time; the rest is copied from the rows above it
+/// with `GenericImage::copy_within`, doubling the number of rows filled in each time.
+/// `ImageBuffer` and `StridedImage` copy a whole row at a time, which for big images is far
+/// faster than putting every pixel separately.
+fn clear<I: GenericImage>(image: &mut I, color: I::Pixel) {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+
+    for x in 0..width {
+        image.put_pixel(x, 0, color);
+    }
+    let mut filled = 1;
+    while filled < height {
+        let rows = filled.min(height - filled);
+        let source = Rect {
+            x: 0,
+            y: 0,
+            width,
+            height: rows,
+        };
+        image.copy_within(source, 0, filled);
+        filled += rows;
+    }
+}
+
+/// Fills `area` (in the range 0 to 1) with `color`.
+fn fill_rect<I: GenericImage>(image: &mut I, area: Rectangle, color: I::Pixel) {
+    let start_x = (area.left * image.width() as f64).floor() as u32;
+    let end_x = (area.right * image.width() as f64).ceil() as u32;
+    let start_y = (area.top * image.height() as f64).floor() as u32;
+    let end_y = (area.bottom * image.height() as f64).ceil() as u32;
+
+    for y in start_y..end_y.min(image.height()) {
+        for x in start_x..end_x.min(image.width()) {
+            image.put_pixel(x, y, color);
+        }
+    }
+}
+
+/// Fills `area` (in the range 0 to 1) with `color`, rounding off its corners into quarter circles
+/// `radius` pixels across. `color` is given how far across and down `area` the centre of each
+/// pixel is, from 0 to 1.
+///
+/// Pixels along the straight edges are filled the same as [`fill_rect`] would. Along the curves,
+/// they're blended in by roughly how much of them is covered if `antialias` is set, or filled if
+/// their centres are inside otherwise.
+fn fill_rounded<I: GenericImage>(
+    image: &mut I,
+    area: Rectangle,
+    radius: f64,
+    antialias: bool,
+    color: impl Fn(f64, f64) -> Color,
+) {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let pixels = Rectangle {
+        left: area.left * width,
+        top: area.top * height,
+        right: area.right * width,
+        bottom: area.bottom * height,
+    };
+    let radius = radius.min(pixels.width() / 2.0).min(pixels.height() / 2.0);
+
+    for y in pixels.top.floor() as u32..(pixels.bottom.ceil() as u32).min(image.height()) {
+        for x in pixels.left.floor() as u32..(pixels.right.ceil() as u32).min(image.width()) {
+            // How far the pixel's centre is past the centre of the nearest corner's circle on
+            // each axis, which is only positive for both in the corners. The centre is kept
+            // inside the area first, so that partly covered pixels along the edges are filled.
+            let center_x = (x as f64 + 0.5).clamp(pixels.left, pixels.right);
+            let center_y = (y as f64 + 0.5).clamp(pixels.top, pixels.bottom);
+            let dx = f64::max(
+                pixels.left + radius - center_x,
+                center_x - (pixels.right - radius),
+            );
+            let dy = f64::max(
+                pixels.top + radius - center_y,
+                center_y - (pixels.bottom - radius),
+            );
+            let distance = dx.hypot(dy);
+            let color = color(
+                (center_x - pixels.left) / pixels.width(),
+                (center_y - pixels.top) / pixels.height(),
+            );
+
+            if dx <= 0.0
+                || dy <= 0.0
+                || distance <= radius - 0.5
+                || !antialias && distance <= radius
+            {
+                image.put_pixel(x, y, color.to_pixel());
+            } else if antialias && distance < radius + 0.5 {
+                blend(image, x, y, color, radius + 0.5 - distance);
+            }
+        }
+    }
+}
+
+/// Fills the pixels whose centres are in `half` of `area` (in the range 0 to 1) with `color`.
+fn fill_half<I: GenericImage>(image: &mut I, area: Rectangle, half: Half, color: I::Pixel) {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let pixels = Rectangle {
+        left: area.left * width,
+        top: area.top * height,
+        right: area.right * width,
+        bottom: area.bottom * height,
+    };
+
+    for y in pixels.top.floor() as u32..(pixels.bottom.ceil() as u32).min(image.height()) {
+        for x in pixels.left.floor() as u32..(pixels.right.ceil() as u32).min(image.width()) {
+            if half.contains(pixels, x as f64 + 0.5, y as f64 + 0.5) {
+                image.put_pixel(x, y, color);
+            }
+        }
+    }
+}
+
+/// Returns the range of pixels covered by a line of the given thickness centred on `center`
+/// (in the range 0 to 1, scaled up to `size` pixels).
+///
+/// The line is grown outwards from the pixel containing `center`, but not past `min` and `max`,
+/// so that thick lines don't bleed into neighbouring rectangles.
+fn stroke(center: f64, thickness: u32, min: f64, max: f64, size: u32) -> RangeInclusive<u32> {
+    let center = (center * size as f64).floor() as i64;
+    let min = (min * size as f64).floor() as i64;
+    let max = (max * size as f64).ceil() as i64 - 1;
+
+    let thickness = thickness.max(1) as i64;
+    let start = (center - (thickness - 1) / 2).max(min).min(center);
+    let end = (center + thickness / 2)
+        .min(max)
+        .max(center)
+        .min(size as i64 - 1);
+
+    start as u32..=end as u32
+}
+
+/// Returns the pixels overlapping the span from `start` to `end` (in pixels), along with how
+/// much of each pixel is covered.
+fn span(start: f64, end: f64, size: u32) -> impl Iterator<Item = (u32, f64)> {
+    let first = start.max(0.0).floor() as u32;
+    let last = (end.ceil().max(0.0) as u32).min(size);
+    (first..last).map(move |i| {
+        let coverage = f64::min(end, (i + 1) as f64) - f64::max(start, i as f64);
+        (i, coverage.max(0.0))
+    })
+}
+
+/// Mixes `color` into the pixel at `x`, `y` by `amount` (from 0 to 1).
+fn blend<I: GenericImage>(image: &mut I, x: u32, y: u32, color: Color, amount: f64) {
+    let existing = Color::from_pixel(image.get_pixel(x, y));
+    image.put_pixel(x, y, existing.mix(color, amount).to_pixel());
+}
+
+/// Composites `color` over the pixel at `x`, `y`, covering `amount` of it (from 0 to 1), so that
+/// whatever's already there shows through as much as `color` is transparent.
+fn composite<I: GenericImage>(image: &mut I, x: u32, y: u32, color: Color, amount: f64) {
+    let below = Color::from_pixel(image.get_pixel(x, y));
+    image.put_pixel(x, y, color.over(below, amount).to_pixel());
+}
+
+/// Draws `color` onto the pixel at `x`, `y` without antialiasing, where `pixel` is `color`
+/// already converted: opaque colours replace what's there, and semi-transparent ones get
+/// composited over it.
+fn plot<I: GenericImage>(image: &mut I, x: u32, y: u32, color: Color, pixel: I::Pixel) {
+    if color.a < 1.0 {
+        composite(image, x, y, color, 1.0);
+    } else {
+        image.put_pixel(x, y, pixel);
+    }
+}
+
+/// Draws a horizontal line at `y` across `area`, both in the range 0 to 1.
+fn horizontal_line<I: GenericImage>(image: &mut I, y: f64, area: Rectangle, settings: &Settings) {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+
+    if settings.antialias {
+        let half = settings.line_thickness.max(1) as f64 / 2.0;
+        let y = y * height;
+        let ys = span(
+            f64::max(y - half, area.top * height),
+            f64::min(y + half, area.bottom * height),
+            image.height(),
+        );
+        let left = area.left * width;
+        for (y, vertical) in ys {
+            for (x, horizontal) in span(left, area.right * width, image.width()) {
+                let start = (x as f64).max(left) - left;
+                let dashed = settings.line_style.coverage(
+                    settings.line_thickness,
+                    start,
+                    start + horizontal,
+                );
+                composite(
+                    image,
+                    x,
+                    y,
+                    settings.line_color,
+                    horizontal * vertical * dashed,
+                );
+            }
+        }
+        return;
+    }
+
+    // The area can go past the edge of the image when it's drawn at a scale that doesn't fit.
+    let start_x = (area.left * width).floor() as u32;
+    let end_x = ((area.right * width).ceil() as u32).min(image.width());
+    let color = settings.line_color;
+    let pixel = color.to_pixel();
+
+    for y in stroke(
+        y,
+        settings.line_thickness,
+        area.top,
+        area.bottom,
+        image.height(),
+    ) {
+        for x in start_x..end_x {
+            if settings
+                .line_style
+                .covers(settings.line_thickness, x - start_x)
+            {
+                plot(image, x, y, color, pixel);
+            }
+        }
+    }
+}
+
+/// Draws a vertical line at `x` down `area`, both in the range 0 to 1.
+fn vertical_line<I: GenericImage>(image: &mut I, x: f64, area: Rectangle, settings: &Settings) {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+
+    if settings.antialias {
+        let half = settings.line_thickness.max(1) as f64 / 2.0;
+        let x = x * width;
+        let xs = span(
+            f64::max(x - half, area.left * width),
+            f64::min(x + half, area.right * width),
+            image.width(),
+        );
+        let top = area.top * height;
+        for (x, horizontal) in xs {
+            for (y, vertical) in span(top, area.bottom * height, image.height()) {
+                let start = (y as f64).max(top) - top;
+                let dashed =
+                    settings
+                        .line_style
+                        .coverage(settings.line_thickness, start, start + vertical);
+                composite(
+                    image,
+                    x,
+                    y,
+                    settings.line_color,
+                    horizontal * vertical * dashed,
+                );
+            }
+        }
+        return;
+    }
+
+    let start_y = (area.top * height).floor() as u32;
+    let end_y = ((area.bottom * height).ceil() as u32).min(image.height());
+    let color = settings.line_color;
+    let pixel = color.to_pixel();
+
+    for x in stroke(
+        x,
+        settings.line_thickness,
+        area.left,
+        area.right,
+        image.width(),
+    ) {
+        for y in start_y..end_y {
+            if settings
+                .line_style
+                .covers(settings.line_thickness, y - start_y)
+            {
+                plot(image, x, y, color, pixel);
+            }
+        }
+    }
+}
+
+/// Draws the diagonal of `area` (in the range 0 to 1), from the top left to the bottom right if
+/// `falling` is set, or from the bottom left to the top right otherwise.
+///
+/// This steps along whichever way the diagonal is longer a pixel at a time, drawing
+/// `settings.line_thickness` pixels across it at each step without going outside `area`.
+fn diagonal_line<I: GenericImage>(
+    image: &mut I,
+    area: Rectangle,
+    falling: bool,
+    settings: &Settings,
+) {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let (left, top) = (area.left * width, area.top * height);
+    let (right, bottom) = (area.right * width, area.bottom * height);
+    let xs = (
+        left.floor() as u32,
+        (right.ceil() as u32).min(image.width()),
+    );
+    let ys = (
+        top.floor() as u32,
+        (bottom.ceil() as u32).min(image.height()),
+    );
+    if xs.0 >= xs.1 || ys.0 >= ys.1 {
+        return;
+    }
+
+    // Step along y if the diagonal is steep, and x otherwise.
+    let steep = bottom - top > right - left;
+    let (major, minor) = if steep { (ys, xs) } else { (xs, ys) };
+    let thickness = settings.line_thickness.max(1);
+    let color = settings.line_color;
+    let pixel = color.to_pixel();
+    for i in major.0..major.1 {
+        let center = i as f64 + 0.5;
+        // Where the diagonal crosses the middle of this row or column.
+        let cross = if steep {
+            let along = ((center - top) / (bottom - top)).clamp(0.0, 1.0);
+            let along = if falling { along } else { 1.0 - along };
+            left + along * (right - left)
+        } else {
+            let along = ((center - left) / (right - left)).clamp(0.0, 1.0);
+            let along = if falling { along } else { 1.0 - along };
+            top + along * (bottom - top)
+        };
+
+        if !settings.line_style.covers(thickness, i - major.0) {
+            continue;
+        }
+        let cross = (cross.floor() as i64).clamp(minor.0 as i64, minor.1 as i64 - 1);
+        let start = (cross - (thickness as i64 - 1) / 2).max(minor.0 as i64);
+        let end = (cross + thickness as i64 / 2).min(minor.1 as i64 - 1);
+        for j in start..=end {
+            let (x, y) = if steep { (j as u32, i) } else { (i, j as u32) };
+            plot(image, x, y, color, pixel);
+        }
+    }
+}
+
+/// Draws the ellipse that fits inside `area` (in the range 0 to 1) in `settings.line_color`,
+/// either filled in or as an outline `settings.line_thickness` wide, blending it into the pixels
+/// it partially covers.
+fn ellipse<I: GenericImage>(image: &mut I, area: Rectangle, filled: bool, settings: &Settings) {
+    /// How many rows to sample within each pixel to work out how much of it is covered.
+    const SAMPLES: u32 = 4;
+
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let center = (
+        (area.left + area.right) / 2.0 * width,
+        (area.top + area.bottom) / 2.0 * height,
+    );
+    let outer = (area.width() / 2.0 * width, area.height() / 2.0 * height);
+    let thickness = settings.line_thickness.max(1) as f64;
+    let inner = if filled {
+        None
+    } else {
+        Some((outer.0 - thickness, outer.1 - thickness)).filter(|&(x, y)| x > 0.0 && y > 0.0)
+    };
+
+    // Returns how far the ellipse with the given radii extends either side of its centre at `y`.
+    let half_width = |(radius_x, radius_y): (f64, f64), y: f64| {
+        let dy = (y - center.1) / radius_y;
+        (dy.abs() < 1.0).then(|| radius_x * (1.0 - dy * dy).sqrt())
+    };
+
+    let start_x = (area.left * width).floor() as u32;
+    let end_x = ((area.right * width).ceil() as u32).min(image.width());
+    for (y, _) in span(area.top * height, area.bottom * height, image.height()) {
+        // How far the outside and inside of the outline extend either side of the centre at each
+        // sample, if they reach that far.
+        let mut halves = [(None, None); SAMPLES as usize];
+        for (sample, halves) in (0..SAMPLES).zip(&mut halves) {
+            let sample_y = y as f64 + (sample as f64 + 0.5) / SAMPLES as f64;
+            *halves = (
+                half_width(outer, sample_y),
+                inner.and_then(|inner| half_width(inner, sample_y)),
+            );
+        }
+
+        for x in start_x..end_x {
+            // How much of this pixel is between `center.0 - half` and `center.0 + half`.
+            let covered = |half: f64| {
+                let coverage =
+                    f64::min(center.0 + half, (x + 1) as f64) - f64::max(center.0 - half, x as f64);
+                coverage.max(0.0)
+            };
+            let mut amount = 0.0;
+            for &(outer, inner) in &halves {
+                if let Some(half) = outer {
+                    amount += covered(half) / SAMPLES as f64;
+                }
+                if let Some(half) = inner {
+                    amount += -covered(half) / SAMPLES as f64;
+                }
+            }
+            if amount > 0.0 {
+                blend(image, x, y, settings.line_color, amount.min(1.0));
+            }
+        }
+    }
+}
+
+/// Draws a border of `settings.line_thickness` just inside the edges of `area` (in the range 0 to
+/// 1), snapped to whole pixels.
+fn border<I: GenericImage>(image: &mut I, area: Rectangle, settings: &Settings) {
+    let (width, height) = (image.width() as f64, image.height() as f64);
+    let left = (area.left * width).floor() as u32;
+    let right = ((area.right * width).ceil() as u32).min(image.width());
+    let top = (area.top * height).floor() as u32;
+    let bottom = ((area.bottom * height).ceil() as u32).min(image.height());
+
+    let thickness = settings.line_thickness.max(1);
+    // The top and bottom edges go all the way across, and the left and right edges fit between
+    // them, so that the corners only get drawn once.
+    let top_end = (top + thickness).min(bottom);
+    let bottom_start = bottom.saturating_sub(thickness).max(top_end);
+    let left_end = (left + thickness).min(right);
+    let right_start = right.saturating_sub(thickness).max(left_end);
+
+    let color = settings.line_color;
+    let pixel = color.to_pixel();
+    let style = settings.line_style;
+    for y in (top..top_end).chain(bottom_start..bottom) {
+        for x in left..right {
+            if style.covers(thickness, x - left) {
+                plot(image, x, y, color, pixel);
+            }
+        }
+    }
+    for y in top_end..bottom_start {
+        for x in (left..left_end).chain(right_start..right) {
+            if style.covers(thickness, y - top) {
+                plot(image, x, y, color, pixel);
+            }
+        }
+    }
+}
+
+/// One thing to draw when rectanglifying an image, in the order [`rectanglify_to`] sends them
+/// to a [`Renderer`].
+///
+/// All coordinates are fractions of the output's width and height, from 0 to 1. Lines are
+/// `thickness` output pixels thick in `Settings::line_style`, and outlines are drawn just inside
+/// the areas they're given.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DrawCommand {
+    /// Fill the whole output with a colour.
+    Clear(Color),
+    /// Fill `area` with `color`, rounding off its corners into quarter circles `radius` output
+    /// pixels across (which is never more than half of its shorter side).
+    Fill {
+        area: Rectangle,
+        color: Color,
+        radius: f64,
+    },
+    /// Fill `area` with a linear gradient along `axis`, from `colors[0]` at its left or top edge
+    /// to `colors[1]` at its right or bottom edge, with its corners rounded off like `Fill`.
+    FillGradient {
+        area: Rectangle,
+        axis: Axis,
+        colors: [Color; 2],
+        radius: f64,
+    },
+    /// Fill the half of `area` above one of its diagonals if `above` is set, or below it
+    /// otherwise, with `color`. The diagonal goes from the top left to the bottom right if
+    /// `falling` is set, or from the bottom left to the top right otherwise.
+    FillTriangle {
+        area: Rectangle,
+        falling: bool,
+        above: bool,
+        color: Color,
+    },
+    /// Draw the ellipse that fits inside `area` in `color`, either filled in or as an outline.
+    Ellipse {
+        area: Rectangle,
+        filled: bool,
+        color: Color,
+        thickness: u32,
+    },
+    /// Draw a vertical line at `x` from the top to the bottom of `area`, without going past its
+    /// left or right.
+    VLine {
+        x: f64,
+        area: Rectangle,
+        color: Color,
+        thickness: u32,
+    },
+    /// Draw a horizontal line at `y` from the left to the right of `area`, without going past
+    /// its top or bottom.
+    HLine {
+        y: f64,
+        area: Rectangle,
+        color: Color,
+        thickness: u32,
+    },
+    /// Draw one of the diagonals of `area`, as described for `FillTriangle`.
+    Diagonal {
+        area: Rectangle,
+        falling: bool,
+        color: Color,
+        thickness: u32,
+    },
+    /// Draw a border just inside the edges of `area`.
+    Border {
+        area: Rectangle,
+        color: Color,
+        thickness: u32,
+    },
+}
+
+/// Something that [`DrawCommand`]s can be drawn onto.
+pub trait Renderer {
+    /// Returns the size of the output in pixels, which `Settings::padding` and `Settings::scale`
+    /// are relative to.
+    fn size(&self) -> (u32, u32);
+
+    /// Draws `command` on top of everything drawn so far.
+    fn draw(&mut self, command: DrawCommand);
+}
+
+/// A [`Renderer`] that draws onto an image, the same way [`rectanglify`] does.
+#[derive(Debug)]
+pub struct ImageRenderer<'a, I> {
+    image: &'a mut I,
+    settings: Settings,
+}
+
+impl<'a, I: GenericImage> ImageRenderer<'a, I> {
+    /// Returns a renderer that draws onto `image`, going by `settings` for how to draw lines.
+    pub fn new(image: &'a mut I, settings: Settings) -> Self {
+        let settings = settings.sanitized();
+        Self { image, settings }
+    }
+}
+
+impl<I: GenericImage> Renderer for ImageRenderer<'_, I> {
+    fn size(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    fn draw(&mut self, command: DrawCommand) {
+        // Each line brings its own colour and thickness, which get put in place of the ones in
+        // the settings rather than copying the settings (palettes and all) for every line.
+        fn line(settings: &mut Settings, line_color: Color, line_thickness: u32) -> &Settings {
+            settings.line_color = line_color;
+            settings.line_thickness = line_thickness;
+            settings
+        }
+        let image = &mut *self.image;
+        match command {
+            DrawCommand::Clear(color) => clear(image, color.to_pixel()),
+            DrawCommand::Fill {
+                area,
+                color,
+                radius,
+            } => {
+                if radius > 0.0 {
+                    fill_rounded(image, area, radius, self.settings.antialias, |_, _| color)
+                } else {
+                    fill_rect(image, area, color.to_pixel())
+                }
+            }
+            DrawCommand::FillGradient {
+                area,
+                axis,
+                colors: [start, end],
+                radius,
+            } => fill_rounded(image, area, radius, self.settings.antialias, |x, y| {
+                let along = match axis {
+                    Axis::X => x,
+                    Axis::Y => y,
+                };
+                start.mix(end, along)
+            }),
+            DrawCommand::FillTriangle {
+                area,
+                falling,
+                above,
+                color,
+            } => fill_half(image, area, Half { falling, above }, color.to_pixel()),
+            DrawCommand::Ellipse {
+                area,
+                filled,
+                color,
+                thickness,
+            } => ellipse(
+                image,
+                area,
+                filled,
+                line(&mut self.settings, color, thickness),
+            ),
+            DrawCommand::VLine {
+                x,
+                area,
+                color,
+                thickness,
+            } => vertical_line(image, x, area, line(&mut self.settings, color, thickness)),
+            DrawCommand::HLine {
+                y,
+                area,
+                color,
+                thickness,
+            } => horizontal_line(image, y, area, line(&mut self.settings, color, thickness)),
+            DrawCommand::Diagonal {
+                area,
+                falling,
+                color,
+                thickness,
+            } => diagonal_line(
+                image,
+                area,
+                falling,
+                line(&mut self.settings, color, thickness),
+            ),
+            DrawCommand::Border {
+                area,
+                color,
+                thickness,
+            } => border(image, area, line(&mut self.settings, color, thickness)),
+        }
+    }
+}
+
+/// Rectanglifies `input`, drawing the result onto `output`.
+///
+/// [`rectanglify_with`] also says whether it got through all of the splits before
+/// `settings.deadline`.
+///
+/// Any settings that are out of range (see [`Settings::validate`]) are put back to their
+/// defaults first, the same as [`Settings::sanitized`] does. Either put them together with
+/// [`SettingsBuilder`], or use [`rectanglify_with`] to get an error for them instead.
+pub fn rectanglify<I: GenericImageView, O: GenericImage>(
+    input: &I,
+    output: &mut O,
+    settings: Settings,
+) {
+    let settings = settings.sanitized();
+    draw_plain(input, output, &settings);
+}
+
+/// Optional extras for [`rectanglify_with`], all of which are left out by default.
+#[derive(Default)]
+pub struct Options<'a> {
+    /// An image the same size as the input whose luma multiplies the darkness of each pixel of the
+    /// input, so that there are more rectangles where it's brighter.
+    pub weight: Option<&'a dyn LumaImage>,
+    /// An image the same size as the output that nothing gets drawn outside of, for clipping the
+    /// output to a shape. Only its luma matters: the rectangles are drawn as usual where it's
+    /// white, not at all where it's black, and blended into the background (or whatever's
+    /// already there, if `Settings::clear_output` isn't set) in between.
+    ///
+    /// The whole input (or `Settings::roi`) is still split up as usual; the rectangles just get
+    /// cut off at the edge of the mask.
+    pub mask: Option<&'a dyn LumaImage>,
+    /// Called each time one of the final rectangles is drawn.
+    pub progress: Option<&'a mut dyn FnMut(Progress)>,
+    /// An image to fill with how densely packed the rectangles are, for seeing where the settings
+    /// put them.
+    ///
+    /// It's stretched over the whole of the input, whatever size it is, and each pixel is set by
+    /// the rectangle covering its centre. The scale is absolute, so that heatmaps of different
+    /// images can be compared: white is one rectangle per input pixel, and it gets 16 levels
+    /// darker each time the density halves, down to black at one rectangle per 65536 pixels or
+    /// sparser. Anything not covered by a rectangle (outside `Settings::roi`, or everywhere if
+    /// nothing was drawn) is black. Diagonally split rectangles count as two rectangles, and with
+    /// `Channels::Separate`, each pixel shows whichever channel's rectangles are densest there.
+    pub heatmap: Option<&'a mut GrayImage>,
+    /// A flag to stop early if it gets set (probably from another thread).
+    ///
+    /// It's checked before each split and each thing drawn, so rectanglifying stops soon after
+    /// it's set. If it does get cancelled, the output is left partially drawn.
+    pub cancel: Option<&'a AtomicBool>,
+}
+
+/// An image that only the luma of each pixel matters for, like the weight and mask in
+/// [`Options`]. This is implemented for every `GenericImageView`.
+pub trait LumaImage {
+    /// Returns the width and height of the image.
+    fn size(&self) -> (u32, u32);
+    /// Returns the luma of the pixel at `x`, `y`, from 0 to 1 (or outside of that, for
+    /// floating-point images).
+    fn luma_at(&self, x: u32, y: u32) -> f64;
+}
+
+impl<I: GenericImageView> LumaImage for I {
+    fn size(&self) -> (u32, u32) {
+        self.dimensions()
+    }
+
+    fn luma_at(&self, x: u32, y: u32) -> f64 {
+        luma(self.get_pixel(x, y))
+    }
+}
+
+/// Rectanglifies `input` the same as [`rectanglify`], with any of the extras in `options`, and
+/// returns a [`Report`] on how evenly the darkness was shared out between the rectangles.
+///
+/// # Errors
+///
+/// Returns an error without drawing anything if `settings` is invalid (see
+/// [`Settings::validate`]), the weight isn't the same size as `input` or the mask isn't the same
+/// size as `output`, or partway through if it gets cancelled.
+pub fn rectanglify_with<I: GenericImageView, O: GenericImage>(
+    input: &I,
+    output: &mut O,
+    settings: Settings,
+    options: Options,
+) -> Result<Report, RectanglifyError> {
+    settings.validate().map_err(RectanglifyError::Settings)?;
+    if let Some(weight) = options.weight {
+        if weight.size() != input.dimensions() {
+            return Err(RectanglifyError::WeightSize {
+                weight: weight.size(),
+                input: input.dimensions(),
+            });
+        }
+    }
+    if let Some(mask) = options.mask {
+        if mask.size() != output.dimensions() {
+            return Err(RectanglifyError::MaskSize {
+                mask: mask.size(),
+                output: output.dimensions(),
+            });
+        }
+    }
+
+    // Floating-point weights can be out of range, but negative (or NaN) ones would make the
+    // totals go wrong.
+    let weight = options
+        .weight
+        .map(|weight| move |x, y| weight.luma_at(x, y).max(0.0));
+    let weight = weight.as_ref().map(|weight| weight as Weight);
+    let mut no_progress = |_| {};
+    let progress = match options.progress {
+        Some(progress) => progress,
+        None => &mut no_progress,
+    };
+    let heatmap = options.heatmap.map(|image| Heatmap {
+        image,
+        input_size: input.dimensions(),
+    });
+    let mask = match options.mask {
+        Some(mask) => mask,
+        None => {
+            return draw(
+                input,
+                weight,
+                output,
+                &settings,
+                progress,
+                heatmap,
+                options.cancel,
+            )
+            .map_err(|Cancelled| RectanglifyError::Cancelled);
+        }
+    };
+
+    // The background goes everywhere, so it gets drawn before the mask comes into it.
+    if settings.clear_output {
+        prepare_output(input, output, &settings);
+    }
+    // Draw onto a copy, and then blend that in by how much of each pixel the mask covers.
+    let mut drawn: ImageBuffer<O::Pixel, Vec<_>> =
+        ImageBuffer::from_fn(output.width(), output.height(), |x, y| {
+            output.get_pixel(x, y)
+        });
+    let settings = Settings {
+        clear_output: false,
+        ..settings
+    };
+    let report = draw(
+        input,
+        weight,
+        &mut drawn,
+        &settings,
+        progress,
+        heatmap,
+        options.cancel,
+    )
+    .map_err(|Cancelled| RectanglifyError::Cancelled)?;
+    for (x, y, &pixel) in drawn.enumerate_pixels() {
+        let coverage = mask.luma_at(x, y).clamp(0.0, 1.0);
+        if coverage >= 1.0 {
+            output.put_pixel(x, y, pixel);
+        } else if coverage > 0.0 {
+            let existing = Color::from_pixel(output.get_pixel(x, y));
+            let color = existing.mix(Color::from_pixel(pixel), coverage);
+            output.put_pixel(x, y, color.to_pixel());
+        }
+    }
+    Ok(report)
+}
+
+/// The error returned by [`rectanglify_with`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RectanglifyError {
+    /// One of the settings was out of range.
+    Settings(SettingsError),
+    /// The weight wasn't the same size as the input.
+    WeightSize {
+        weight: (u32, u32),
+        input: (u32, u32),
+    },
+    /// The mask wasn't the same size as the output.
+    MaskSize {
+        mask: (u32, u32),
+        output: (u32, u32),
+    },
+    /// The cancel flag got set before it finished.
+    Cancelled,
+}
+
+impl Display for RectanglifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            RectanglifyError::Settings(error) => write!(f, "invalid settings: {}", error),
+            RectanglifyError::WeightSize { weight, input } => write!(
+                f,
+                "weight is {}x{}, but the input is {}x{}",
+                weight.0, weight.1, input.0, input.1
+            ),
+            RectanglifyError::MaskSize { mask, output } => write!(
+                f,
+                "mask is {}x{}, but the output is {}x{}",
+                mask.0, mask.1, output.0, output.1
+            ),
+            RectanglifyError::Cancelled => f.write_str("rectanglifying was cancelled"),
+        }
+    }
+}
+
+impl Error for RectanglifyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RectanglifyError::Settings(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+/// Rectanglifies `input` the same as [`rectanglify`], onto a new image of the same colour type,
+/// and returns it along with whether it got through all of the splits before
+/// `settings.deadline`.
+///
+/// The new image is the same size as `input`, or scaled up to fit with `settings.scale` (see
+/// [`scaled_size`]). 16-bit and floating-point inputs are read without losing any precision.
+///
+/// # Errors
+///
+/// Returns an error if `input` has a colour type this doesn't know about.
+pub fn rectanglify_dynamic(
+    input: &DynamicImage,
+    settings: Settings,
+) -> Result<(DynamicImage, Completion), UnsupportedColor> {
+    rectanglify_dynamic_as(input, input.color(), settings)
+}
+
+/// The same as [`rectanglify_dynamic`], but drawing onto an image with the colour type `color`
+/// instead, such as `ColorType::L8` for an 8-bit greyscale image.
+///
+/// # Errors
+///
+/// Returns an error if `input` or `color` is a colour type this doesn't know about.
+pub fn rectanglify_dynamic_as(
+    input: &DynamicImage,
+    color: ColorType,
+    settings: Settings,
+) -> Result<(DynamicImage, Completion), UnsupportedColor> {
+    let settings = settings.sanitized();
+    let size = match settings.scale {
+        Some(scale) => scaled_size(input, scale),
+        None => input.dimensions(),
+    };
+    // `DynamicImage` only gives out 8-bit pixels itself, so deeper images get converted to
+    // something that can hold all of their depth.
+    match input {
+        DynamicImage::ImageLuma8(_)
+        | DynamicImage::ImageLumaA8(_)
+        | DynamicImage::ImageRgb8(_)
+        | DynamicImage::ImageRgba8(_) => rectanglify_into(input, size, color, settings),
+        DynamicImage::ImageLuma16(_)
+        | DynamicImage::ImageLumaA16(_)
+        | DynamicImage::ImageRgb16(_)
+        | DynamicImage::ImageRgba16(_) => {
+            rectanglify_into(&input.to_rgba16(), size, color, settings)
+        }
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            rectanglify_into(&input.to_rgba32f(), size, color, settings)
+        }
+        _ => Err(UnsupportedColor(input.color())),
+    }
+}
+
+/// Rectanglifies `input` onto a new image `size` pixels big with the colour type `color`, for
+/// `rectanglify_dynamic_as`.
+fn rectanglify_into(
+    input: &impl GenericImageView,
+    (width, height): (u32, u32),
+    color: ColorType,
+    settings: Settings,
+) -> Result<(DynamicImage, Completion), UnsupportedColor> {
+    let mut output = match color {
+        ColorType::L8 => DynamicImage::new_luma8(width, height),
+        ColorType::La8 => DynamicImage::new_luma_a8(width, height),
+        ColorType::Rgb8 => DynamicImage::new_rgb8(width, height),
+        ColorType::Rgba8 => DynamicImage::new_rgba8(width, height),
+        ColorType::L16 => DynamicImage::new_luma16(width, height),
+        ColorType::La16 => DynamicImage::new_luma_a16(width, height),
+        ColorType::Rgb16 => DynamicImage::new_rgb16(width, height),
+        ColorType::Rgba16 => DynamicImage::new_rgba16(width, height),
+        ColorType::Rgb32F => DynamicImage::new_rgb32f(width, height),
+        ColorType::Rgba32F => DynamicImage::new_rgba32f(width, height),
+        color => return Err(UnsupportedColor(color)),
+    };
+    // Drawing onto the `DynamicImage` itself would go through 8-bit pixels, so it has to be done
+    // onto whichever buffer is inside.
+    let completion = match &mut output {
+        DynamicImage::ImageLuma8(output) => draw_plain(input, output, &settings),
+        DynamicImage::ImageLumaA8(output) => draw_plain(input, output, &settings),
+        DynamicImage::ImageRgb8(output) => draw_plain(input, output, &settings),
+        DynamicImage::ImageRgba8(output) => draw_plain(input, output, &settings),
+        DynamicImage::ImageLuma16(output) => draw_plain(input, output, &settings),
+        DynamicImage::ImageLumaA16(output) => draw_plain(input, output, &settings),
+        DynamicImage::ImageRgb16(output) => draw_plain(input, output, &settings),
+        DynamicImage::ImageRgba16(output) => draw_plain(input, output, &settings),
+        DynamicImage::ImageRgb32F(output) => draw_plain(input, output, &settings),
+        DynamicImage::ImageRgba32F(output) => draw_plain(input, output, &settings),
+        _ => unreachable!(),
+    };
+    Ok((output, completion))
+}
+
+/// Rectanglifies `input` onto `output` without any extras, and returns whether it got through
+/// all of the splits before `settings.deadline`.
+fn draw_plain(
+    input: &impl GenericImageView,
+    output: &mut impl GenericImage,
+    settings: &Settings,
+) -> Completion {
+    uncancelled(draw(input, None, output, settings, &mut |_| {}, None, None)).completion
+}
+
+/// The error returned by [`rectanglify_dynamic`] when an image has a colour type it doesn't
+/// support.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedColor(pub ColorType);
+
+impl Display for UnsupportedColor {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "unsupported colour type: {:?}", self.0)
+    }
+}
+
+impl Error for UnsupportedColor {}
+
+/// Whether rectanglifying got through all of its splits, or had to stop early because
+/// `Settings::deadline` ran out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Completion {
+    Complete,
+    /// Some of the rectangles were left unsplit.
+    Truncated,
+}
+
+impl Completion {
+    /// Returns `Truncated` if either `self` or `other` is.
+    fn and(self, other: Completion) -> Completion {
+        match (self, other) {
+            (Completion::Complete, Completion::Complete) => Completion::Complete,
+            _ => Completion::Truncated,
+        }
+    }
+}
+
+/// How well the darkness got shared out between the rectangles, as returned by
+/// [`rectanglify_with`], for tuning settings.
+///
+/// Ideally, every rectangle would have the same total darkness: the total darkness of the input
+/// divided by the number of rectangles asked for. The errors are how far each rectangle's
+/// darkness is from that, as a fraction of it, with the two halves of a diagonally split
+/// rectangle counted separately.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Report {
+    /// Whether it got through all of the splits before `Settings::deadline`.
+    pub completion: Completion,
+    /// The number of rectangles the budget asked for, after `Settings::max_rects`.
+    pub requested_rects: usize,
+    /// The number of rectangles there actually are, with each diagonally split rectangle counted
+    /// as two.
+    pub rects: usize,
+    /// The mean error of all the rectangles, or 0 if there aren't any.
+    pub mean_error: f64,
+    /// The biggest error of any of the rectangles, or 0 if there aren't any.
+    pub max_error: f64,
+    /// The number of rectangles that had a share of more than one rectangle's worth of the
+    /// budget, but didn't get split any further: because of `Settings::min_rect_size`,
+    /// `max_aspect_ratio`, `max_depth` and so on, because they were too small to split at all,
+    /// or because the deadline ran out.
+    pub clamped_leaves: usize,
+}
+
+impl Report {
+    /// Returns the report for nothing having been drawn, out of `requested_rects`.
+    fn empty(requested_rects: usize) -> Report {
+        Report {
+            completion: Completion::Complete,
+            requested_rects,
+            rects: 0,
+            mean_error: 0.0,
+            max_error: 0.0,
+            clamped_leaves: 0,
+        }
+    }
+
+    /// Combines the reports for two sets of rectangles drawn together.
+    fn and(self, other: Report) -> Report {
+        let rects = self.rects + other.rects;
+        Report {
+            completion: self.completion.and(other.completion),
+            requested_rects: self.requested_rects + other.requested_rects,
+            rects,
+            mean_error: match rects {
+                0 => 0.0,
+                _ => {
+                    (self.mean_error * self.rects as f64 + other.mean_error * other.rects as f64)
+                        / rects as f64
+                }
+            },
+            max_error: self.max_error.max(other.max_error),
+            clamped_leaves: self.clamped_leaves + other.clamped_leaves,
+        }
+    }
+}
+
+/// The result of something being cancelled partway through, before it's turned into a
+/// `RectanglifyError`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Cancelled;
+
+/// Returns `Err(Cancelled)` if `cancel` has been set.
+fn check(cancel: Option<&AtomicBool>) -> Result<(), Cancelled> {
+    match cancel {
+        Some(cancel) if cancel.load(Ordering::Relaxed) => Err(Cancelled),
+        _ => Ok(()),
+    }
+}
+
+/// Unwraps the result of something that wasn't given anything to cancel it with.
+fn uncancelled<T>(result: Result<T, Cancelled>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(Cancelled) => unreachable!(),
+    }
+}
+
+/// How far through drawing a call to [`rectanglify_with`] is, as passed to `Options::progress`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// The number of rectangles drawn so far.
+    pub done: usize,
+    /// The total number of rectangles that will be drawn.
+    pub total: usize,
+}
+
+/// Keeps track of how many rectangles have been drawn, and reports it.
+struct Tracker<'a> {
+    progress: Progress,
+    callback: &'a mut dyn FnMut(Progress),
+    /// Where to record how densely packed the rectangles are, if anywhere.
+    heatmap: Option<Heatmap<'a>>,
+}
+
+impl Tracker<'_> {
+    /// Records that `op` (a leaf) has been drawn.
+    fn leaf_drawn(&mut self, op: DrawOp) {
+        self.progress.done += 1;
+        (self.callback)(self.progress);
+        if let Some(heatmap) = &mut self.heatmap {
+            match op {
+                DrawOp::Leaf { area, .. } => heatmap.record(area, 1.0),
+                DrawOp::Diagonal { area, .. } => heatmap.record(area, 2.0),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// How many grey levels darker a pixel of a heatmap gets each time the density of the
+/// rectangles covering it halves.
+const HEATMAP_LEVELS_PER_HALVING: f64 = 16.0;
+
+/// An image to draw the density of the rectangles onto, for `Options::heatmap`.
+struct Heatmap<'a> {
+    image: &'a mut GrayImage,
+    /// The size of the input, which `image` is stretched over.
+    input_size: (u32, u32),
+}
+
+impl Heatmap<'_> {
+    /// Clears the heatmap to black, for nothing covering it yet.
+    fn clear(&mut self) {
+        for pixel in self.image.pixels_mut() {
+            pixel.0 = [0];
+        }
+    }
+
+    /// Records that `rects` rectangles cover `area` (in input pixels), keeping whichever's
+    /// denser where something's been recorded already.
+    fn record(&mut self, area: Rectangle, rects: f64) {
+        let density = rects / (area.width() * area.height());
+        let value = (255.0 + density.log2() * HEATMAP_LEVELS_PER_HALVING)
+            .round()
+            .clamp(0.0, 255.0) as u8;
+
+        let (width, height) = self.image.dimensions();
+        let scale_x = width as f64 / self.input_size.0 as f64;
+        let scale_y = height as f64 / self.input_size.1 as f64;
+        // The pixels whose centres are inside `area`.
+        let pixels = |start: f64, end: f64, size: u32| {
+            let first = (start - 0.5).ceil().max(0.0) as u32;
+            let last = ((end - 0.5).ceil().max(0.0) as u32).min(size);
+            first..last
+        };
+        for y in pixels(area.top * scale_y, area.bottom * scale_y, height) {
+            for x in pixels(area.left * scale_x, area.right * scale_x, width) {
+                let pixel = self.image.get_pixel_mut(x, y);
+                pixel.0[0] = pixel.0[0].max(value);
+            }
+        }
+    }
+}
+
+/// Rectanglifies `input` onto `output`, with its darkness optionally weighted by `weight`,
+/// reporting progress to `progress`, recording the density of the rectangles in `heatmap` if
+/// given, and stopping if `cancel` is set.
+fn draw<I: GenericImageView, O: GenericImage>(
+    input: &I,
+    weight: Option<Weight>,
+    output: &mut O,
+    settings: &Settings,
+    progress: &mut dyn FnMut(Progress),
+    mut heatmap: Option<Heatmap>,
+    cancel: Option<&AtomicBool>,
+) -> Result<Report, Cancelled> {
+    if let Some(heatmap) = &mut heatmap {
+        heatmap.clear();
+    }
+    let separate = settings.channels == Channels::Separate && O::Pixel::CHANNEL_COUNT >= 3;
+    if settings.clear_output {
+        prepare_output(input, output, settings);
+    }
+
+    let leaves = |ops: &[DrawOp]| {
+        ops.iter()
+            .filter(|op| matches!(op, DrawOp::Leaf { .. } | DrawOp::Diagonal { .. }))
+            .count()
+    };
+
+    let report = if separate {
+        // Plan all of the channels first, so that we know how many rectangles there are in total.
+        let passes: Vec<(Settings, (Vec<DrawOp>, Report))> = (0..3)
+            .map(|channel| {
+                let settings = channel_settings(settings, channel);
+                let view = ChannelView {
+                    image: input,
+                    channel,
+                };
+                let plan = plan(&view, weight, cancel, &settings)?;
+                Ok((settings, plan))
+            })
+            .collect::<Result<_, _>>()?;
+
+        let mut tracker = Tracker {
+            progress: Progress {
+                done: 0,
+                total: passes.iter().map(|(_, (ops, _))| leaves(ops)).sum(),
+            },
+            callback: progress,
+            heatmap,
+        };
+        let report = passes
+            .iter()
+            .map(|(_, (_, report))| *report)
+            .reduce(Report::and)
+            .unwrap();
+        for (channel, (settings, (ops, _))) in passes.iter().enumerate() {
+            draw_channel(input, output, channel, settings, ops, &mut tracker, cancel)?;
+        }
+        report
+    } else {
+        let (ops, report) = plan(input, weight, cancel, settings)?;
+        let mut tracker = Tracker {
+            progress: Progress {
+                done: 0,
+                total: leaves(&ops),
+            },
+            callback: progress,
+            heatmap,
+        };
+        let output = &mut ImageRenderer::new(&mut *output, settings.clone());
+        draw_ops(input, output, settings, &ops, &mut tracker, cancel)?;
+        report
+    };
+
+    if settings.preserve_alpha {
+        copy_alpha(input, output, settings);
+    }
+    Ok(report)
+}
+
+/// Returns the settings for drawing just `channel` (0 to 2, for red, green and blue) with
+/// `Channels::Separate`, which only draw that channel's share of each colour.
+fn channel_settings(settings: &Settings, channel: usize) -> Settings {
+    let only_channel = |color: Color| {
+        let value = [color.r, color.g, color.b][channel];
+        Color {
+            a: color.a,
+            ..Color::rgb(value, value, value)
+        }
+    };
+    Settings {
+        line_color: only_channel(settings.line_color),
+        background_color: only_channel(settings.background_color),
+        ..settings.clone()
+    }
+}
+
+/// Draws `ops` (planned from just `channel` of `input`) onto just `channel` of `output`, on top
+/// of whatever's already there, stopping if `cancel` is set.
+fn draw_channel<I: GenericImageView, O: GenericImage>(
+    input: &I,
+    output: &mut O,
+    channel: usize,
+    settings: &Settings,
+    ops: &[DrawOp],
+    tracker: &mut Tracker,
+    cancel: Option<&AtomicBool>,
+) -> Result<(), Cancelled> {
+    // Draw the channel on its own, then put it back in place of the output's.
+    let existing = ChannelView {
+        image: &*output,
+        channel,
+    };
+    let mut buffer: ImageBuffer<LumaA<<O::Pixel as Pixel>::Subpixel>, _> =
+        ImageBuffer::from_fn(output.width(), output.height(), |x, y| {
+            existing.get_pixel(x, y)
+        });
+    draw_ops(
+        &ChannelView {
+            image: input,
+            channel,
+        },
+        &mut ImageRenderer::new(&mut buffer, settings.clone()),
+        settings,
+        ops,
+        tracker,
+        cancel,
+    )?;
+    for (x, y, pixel) in buffer.enumerate_pixels() {
+        let mut existing = output.get_pixel(x, y);
+        existing.channels_mut()[channel] = pixel[0];
+        output.put_pixel(x, y, existing);
+    }
+    Ok(())
+}
+
+/// Fills `output` with what goes underneath the rectangles: a copy of `input` if
+/// `settings.overlay` is set, or the background otherwise.
+fn prepare_output<I: GenericImageView, O: GenericImage>(
+    input: &I,
+    output: &mut O,
+    settings: &Settings,
+) {
+    let separate = settings.channels == Channels::Separate && O::Pixel::CHANNEL_COUNT >= 3;
+    if settings.overlay {
+        copy_input(
+            input,
+            output,
+            &Settings {
+                transparent_background: settings.transparent_background && !separate,
+                ..settings.clone()
+            },
+        );
+    } else {
+        let background = if separate {
+            settings.background_color
+        } else {
+            background(settings)
+        };
+        ImageRenderer::new(output, settings.clone()).draw(DrawCommand::Clear(background));
+    }
+}
+
+/// Draws `ops` onto `output`, on top of whatever's already there, stopping if `cancel` is set.
+fn draw_ops(
+    input: &impl GenericImageView,
+    output: &mut impl Renderer,
+    settings: &Settings,
+    ops: &[DrawOp],
+    tracker: &mut Tracker,
+    cancel: Option<&AtomicBool>,
+) -> Result<(), Cancelled> {
+    for &op in ops {
+        check(cancel)?;
+        op.draw(input, output, settings);
+        if let DrawOp::Leaf { .. } | DrawOp::Diagonal { .. } = op {
+            tracker.leaf_drawn(op);
+        }
+    }
+    if settings.debug_labels {
+        let size = (input.width() as f64, input.height() as f64);
+        draw_labels(ops, size, output, settings);
+    }
+
+    Ok(())
+}
+
+/// The digits 0 to 9 in a 5×7 pixel font for `Settings::debug_labels`, as rows from top to
+/// bottom, with the highest of the 5 bits in each row being the leftmost pixel.
+const DIGITS: [[u8; 7]; 10] = [
+    [
+        0b01110, 0b10001, 0b10011, 0b10101, 0b11001, 0b10001, 0b01110,
+    ],
+    [
+        0b00100, 0b01100, 0b00100, 0b00100, 0b00100, 0b00100, 0b01110,
+    ],
+    [
+        0b01110, 0b10001, 0b00001, 0b00010, 0b00100, 0b01000, 0b11111,
+    ],
+    [
+        0b11111, 0b00010, 0b00100, 0b00010, 0b00001, 0b10001, 0b01110,
+    ],
+    [
+        0b00010, 0b00110, 0b01010, 0b10010, 0b11111, 0b00010, 0b00010,
+    ],
+    [
+        0b11111, 0b10000, 0b11110, 0b00001, 0b00001, 0b10001, 0b01110,
+    ],
+    [
+        0b00110, 0b01000, 0b10000, 0b11110, 0b10001, 0b10001, 0b01110,
+    ],
+    [
+        0b11111, 0b00001, 0b00010, 0b00100, 0b01000, 0b01000, 0b01000,
+    ],
+    [
+        0b01110, 0b10001, 0b10001, 0b01110, 0b10001, 0b10001, 0b01110,
+    ],
+    [
+        0b01110, 0b10001, 0b10001, 0b01111, 0b00001, 0b00010, 0b01100,
+    ],
+];
+
+/// Writes the index of each of the final rectangles in `ops` in its top-left corner onto
+/// `output`, for an input of `input_size` pixels, as described for `Settings::debug_labels`.
+fn draw_labels(
+    ops: &[DrawOp],
+    input_size: (f64, f64),
+    output: &mut impl Renderer,
+    settings: &Settings,
+) {
+    let (width, height) = output.size();
+    let size = covered_size(input_size, (width, height), settings);
+    let (width, height) = (width as f64, height as f64);
+    // Fills the given number of whole output pixels, going in a little from each side so that
+    // rounding can't make it spill over into the next pixel.
+    let mut fill = |x: f64, y: f64, columns: f64, rows: f64, color: Color| {
+        output.draw(DrawCommand::Fill {
+            area: Rectangle {
+                left: (x + 0.25) / width,
+                top: (y + 0.25) / height,
+                right: (x + columns - 0.25) / width,
+                bottom: (y + rows - 0.25) / height,
+            },
+            color,
+            radius: 0.0,
+        })
+    };
+
+    // How far in from the corner of each rectangle to go (in output pixels), to stay clear of
+    // the lines and padding.
+    let inset = (settings.line_thickness as f64 / 2.0).ceil() + settings.padding.ceil() + 1.0;
+    let leaves = ops.iter().filter_map(|op| match *op {
+        DrawOp::Leaf { area, .. } | DrawOp::Diagonal { area, .. } => Some(area),
+        DrawOp::Line { .. } | DrawOp::Border { .. } => None,
+    });
+    for (index, area) in leaves.enumerate() {
+        let bounds = area.normalize(size);
+        let left = (bounds.left * width).ceil() + inset;
+        let top = (bounds.top * height).ceil() + inset;
+        let label = index.to_string();
+        // Each digit is 5 pixels wide, with a gap of 1 in between.
+        let label_width = (label.len() * 6 - 1) as f64;
+        if left + label_width + inset > (bounds.right * width).floor()
+            || top + 7.0 + inset > (bounds.bottom * height).floor()
+        {
+            continue;
+        }
+
+        // The box behind it keeps it readable whatever the rectangle's filled with.
+        fill(
+            left - 1.0,
+            top - 1.0,
+            label_width + 2.0,
+            9.0,
+            settings.background_color,
+        );
+        for (i, digit) in label.bytes().enumerate() {
+            let glyph = DIGITS[(digit - b'0') as usize];
+            for (y, row) in glyph.into_iter().enumerate() {
+                for x in 0..5 {
+                    if row & (0b10000 >> x) != 0 {
+                        let x = left + (i * 6 + x) as f64;
+                        fill(x, top + y as f64, 1.0, 1.0, settings.line_color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Rectanglifies `input` the same as [`rectanglify`], but sending everything it draws to
+/// `renderer` instead of drawing it onto an image. `settings.channels` and
+/// `settings.preserve_alpha` are ignored, and with `settings.overlay` (or without
+/// `settings.clear_output`) nothing is cleared first, leaving whatever `renderer` already has
+/// drawn (such as the input) underneath.
+pub fn rectanglify_to(
+    input: &impl GenericImageView,
+    renderer: &mut impl Renderer,
+    settings: Settings,
+) -> Completion {
+    let settings = settings.sanitized();
+    if settings.clear_output && !settings.overlay {
+        renderer.draw(DrawCommand::Clear(background(&settings)));
+    }
+    let (ops, report) = uncancelled(plan(input, None, None, &settings));
+    for &op in &ops {
+        op.draw(input, renderer, &settings);
+    }
+    if settings.debug_labels {
+        let size = (input.width() as f64, input.height() as f64);
+        draw_labels(&ops, size, renderer, &settings);
+    }
+    report.completion
+}
+
+/// Works out how `input` would be rectanglified, and returns the final rectangles in input pixel
+/// coordinates instead of drawing them.
+///
+/// Together they exactly cover `input` (or `settings.roi`), unless the budget works out to 0
+/// rectangles or there's no darkness to share them out by, in which case this returns none at all.
+pub fn rectanglify_rects(input: &impl GenericImageView, settings: Settings) -> Vec<Rectangle> {
+    let settings = settings.sanitized();
+    uncancelled(plan(input, None, None, &settings))
+        .0
+        .into_iter()
+        .filter_map(|op| match op {
+            DrawOp::Leaf { area, .. } | DrawOp::Diagonal { area, .. } => Some(area),
+            DrawOp::Line { .. } | DrawOp::Border { .. } => None,
+        })
+        .collect()
+}
+
+/// One of the final rectangles from [`rectanglify_leaves`], along with some statistics about it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LeafInfo {
+    /// The rectangle, in input pixel coordinates.
+    pub rect: Rectangle,
+    /// How many splits down it is; the whole image is at depth 0.
+    pub depth: u32,
+    /// The mean darkness of the input inside `rect`, from 0 to 1, as measured for sharing out
+    /// the rectangles (so after `gamma`, `threshold` and so on).
+    pub mean_darkness: f64,
+    /// The number of pixels whose centres are inside `rect`. Every pixel in the rectanglified
+    /// region is counted in exactly one leaf.
+    pub pixel_count: usize,
+    /// Which of the levels of `FillMode::Quantized` this is filled with, from 0 (white) up, or
+    /// `None` with any other fill mode.
+    pub level: Option<u8>,
+}
+
+/// The same as [`rectanglify_rects`], but returning some statistics about each rectangle too.
+pub fn rectanglify_leaves(input: &impl GenericImageView, settings: Settings) -> Vec<LeafInfo> {
+    let settings = settings.sanitized();
+    // Returns the number of pixel centres between `start` and `end`.
+    let centers = |start: f64, end: f64| ((end - 0.5).ceil() - (start - 0.5).ceil()).max(0.0);
+
+    uncancelled(plan(input, None, None, &settings))
+        .0
+        .into_iter()
+        .filter_map(|op| match op {
+            DrawOp::Leaf {
+                area,
+                depth,
+                darkness,
+                ..
+            }
+            | DrawOp::Diagonal {
+                area,
+                depth,
+                darkness,
+                ..
+            } => Some(LeafInfo {
+                rect: area,
+                depth,
+                mean_darkness: darkness,
+                pixel_count: (centers(area.left, area.right) * centers(area.top, area.bottom))
+                    as usize,
+                level: match settings.fill_mode {
+                    FillMode::Quantized { levels } => Some(FillMode::level(darkness, levels)),
+                    _ => None,
+                },
+            }),
+            DrawOp::Line { .. } | DrawOp::Border { .. } => None,
+        })
+        .collect()
+}
+
+/// Works out how `input` would be rectanglified, and returns the lines dividing up the rectangles
+/// in input pixel coordinates instead of drawing them.
+///
+/// These don't include the edges of the image.
+pub fn rectanglify_lines(input: &impl GenericImageView, settings: Settings) -> Vec<Line> {
+    let settings = settings.sanitized();
+    uncancelled(plan(input, None, None, &settings))
+        .0
+        .into_iter()
+        .filter_map(|op| match op {
+            DrawOp::Leaf { .. } | DrawOp::Border { .. } => None,
+            DrawOp::Line {
+                axis: Axis::X,
+                position,
+                area,
+                ends,
+                ..
+            } => Some(Line {
+                start: (position, area.top),
+                end: (position, area.bottom),
+                ends: ends.map(|end| end.map(|(depth, _)| depth)),
+            }),
+            DrawOp::Line {
+                axis: Axis::Y,
+                position,
+                area,
+                ends,
+                ..
+            } => Some(Line {
+                start: (area.left, position),
+                end: (area.right, position),
+                ends: ends.map(|end| end.map(|(depth, _)| depth)),
+            }),
+            DrawOp::Diagonal {
+                area,
+                falling: true,
+                ..
+            } => Some(Line {
+                start: (area.left, area.top),
+                end: (area.right, area.bottom),
+                ends: [None; 2],
+            }),
+            DrawOp::Diagonal {
+                area,
+                falling: false,
+                ..
+            } => Some(Line {
+                start: (area.left, area.bottom),
+                end: (area.right, area.top),
+                ends: [None; 2],
+            }),
+        })
+        .collect()
+}
+
+/// A record of how an image was split up, which can be drawn again later with [`render`], at
+/// any size and with different settings.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SplitTree {
+    /// The size of the image that was split up, in pixels.
+    pub size: (f64, f64),
+    /// The region of the image that was split up, which the border goes around.
+    pub area: Rectangle,
+    /// All the nodes of the tree, in the order they're drawn: children always come before their
+    /// parents, so the root is the last one. This is empty if nothing was split up at all.
+    pub nodes: Vec<TreeNode>,
+}
+
+impl SplitTree {
+    /// Returns the final rectangle containing the point `x`, `y` (in input pixels): either a
+    /// `TreeNode::Leaf`, or a `TreeNode::Diagonal` for the pair of triangles it's in. This walks
+    /// down from the root, so it only looks at as many nodes as the leaf is deep.
+    ///
+    /// Points right on a dividing line count as being in the rectangle to the right of or below
+    /// it, and points outside `area` (including on its right and bottom edges) return `None`, as
+    /// does every point if nothing was split up.
+    pub fn leaf_at(&self, x: f64, y: f64) -> Option<&TreeNode> {
+        let area = self.area;
+        if !(x >= area.left && x < area.right && y >= area.top && y < area.bottom) {
+            return None;
+        }
+
+        let mut node = self.nodes.last()?;
+        while let TreeNode::Split {
+            axis,
+            position,
+            children: [first, second],
+            ..
+        } = *node
+        {
+            let along = match axis {
+                Axis::X => x,
+                Axis::Y => y,
+            };
+            node = &self.nodes[if along < position { first } else { second }];
+        }
+        Some(node)
+    }
+}
+
+/// A node in a [`SplitTree`], in input pixel coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TreeNode {
+    /// One of the final rectangles.
+    Leaf {
+        area: Rectangle,
+        /// Which colour this gets with `FillMode::Checkerboard`.
+        odd: bool,
+        /// The average colour of the input inside `area`, for `FillMode::Mosaic`.
+        color: Color,
+        /// The mean darkness of `area`, for `FillMode::Halftone`.
+        darkness: f64,
+    },
+    /// A rectangle that's split in two at `position` along `axis`.
+    Split {
+        area: Rectangle,
+        axis: Axis,
+        position: f64,
+        /// The indices of the two halves in `SplitTree::nodes`, left or top first.
+        children: [usize; 2],
+        /// The mean darkness of `area`, for `Settings::shade_lines`.
+        darkness: f64,
+        /// How many splits down this is, for `Settings::thickness_falloff`.
+        depth: u32,
+    },
+    /// A pair of final rectangles (well, triangles) made by cutting `area` along a diagonal,
+    /// from the top left to the bottom right if `falling` is set, or from the bottom left to the
+    /// top right otherwise.
+    Diagonal {
+        area: Rectangle,
+        falling: bool,
+        /// Which colour the half above the diagonal gets with `FillMode::Checkerboard`; the
+        /// other half gets the other one.
+        odd: bool,
+        /// The average colours of the input in the halves above and below the diagonal, for
+        /// `FillMode::Mosaic`.
+        colors: [Color; 2],
+        /// The mean darkness of `area`, for `Settings::shade_lines` and `FillMode::Halftone`.
+        darkness: f64,
+        /// How many splits down this is, for `Settings::thickness_falloff`.
+        depth: u32,
+    },
+}
+
+/// Works out how `input` would be rectanglified, and returns the result as a [`SplitTree`]
+/// instead of drawing it.
+///
+/// Only the settings that affect where the splits go matter here; everything about how they're
+/// drawn can be changed when passing the tree to [`render`]. `settings.channels` is ignored.
+pub fn rectanglify_tree(input: &impl GenericImageView, settings: Settings) -> SplitTree {
+    let settings = settings.sanitized();
+    // Always work out the shade of the lines, in case they're rendered with `shade_lines`.
+    let settings = Settings {
+        shade_lines: true,
+        ..settings
+    };
+    let ops = uncancelled(plan(input, None, None, &settings)).0;
+    tree_from_ops(input, ops, &settings)
+}
+
+/// Works out a rough version of how `input` would be rectanglified, for showing something
+/// quickly while [`refine`] works out the real thing.
+///
+/// This splits up a copy of `input` shrunk `downscale` times in each direction, and then scales
+/// the result back up to the size of `input`. `settings.snap_to_pixels` and `settings.grid` are
+/// ignored, since the pixels of the shrunk copy don't line up with the real ones.
+///
+/// # Panics
+///
+/// Panics if `downscale` is 0.
+pub fn rectanglify_preview(
+    input: &impl GenericImageView,
+    settings: Settings,
+    downscale: u32,
+) -> SplitTree {
+    let settings = settings.sanitized();
+    assert!(downscale > 0, "downscale must be at least 1");
+    let factor = downscale as f64;
+    let (width, height) = input.dimensions();
+
+    // Shrink the input by averaging each block of pixels, into floating-point pixels so that
+    // nothing gets lost to rounding.
+    let small = ImageBuffer::from_fn(
+        width.div_ceil(downscale),
+        height.div_ceil(downscale),
+        |x, y| {
+            let block = Rectangle {
+                left: (x * downscale) as f64,
+                top: (y * downscale) as f64,
+                right: ((x + 1) * downscale).min(width) as f64,
+                bottom: ((y + 1) * downscale).min(height) as f64,
+            };
+            let color = average_color(input, block, None);
+            Rgba([color.r, color.g, color.b, color.a].map(|value| value as f32))
+        },
+    );
+
+    let shrink = |rect: Rectangle| Rectangle {
+        left: rect.left / factor,
+        top: rect.top / factor,
+        right: rect.right / factor,
+        bottom: rect.bottom / factor,
+    };
+    let small_settings = Settings {
+        // Each pixel of `small` stands in for `factor * factor` of the real ones.
+        budget: match settings.budget {
+            RectBudget::PerDarkness(rects_per_pixel) => {
+                RectBudget::PerDarkness(rects_per_pixel * factor * factor)
+            }
+            budget => budget,
+        },
+        min_rect_size: settings.min_rect_size / factor,
+        smoothing: settings.smoothing.map(|sigma| sigma / factor),
+        snap_to_pixels: false,
+        grid: None,
+        roi: settings.roi.map(shrink),
+        ..settings.clone()
+    };
+    let mut tree = rectanglify_tree(&small, small_settings);
+
+    // Scale it back up, keeping it inside the input where the last blocks went past the edge.
+    let area = region(input, &settings);
+    let grow = |rect: Rectangle| Rectangle {
+        left: (rect.left * factor).min(area.right),
+        top: (rect.top * factor).min(area.bottom),
+        right: (rect.right * factor).min(area.right),
+        bottom: (rect.bottom * factor).min(area.bottom),
+    };
+    for node in &mut tree.nodes {
+        match node {
+            TreeNode::Leaf { area, .. } | TreeNode::Diagonal { area, .. } => *area = grow(*area),
+            TreeNode::Split {
+                area: node_area,
+                axis,
+                position,
+                ..
+            } => {
+                *node_area = grow(*node_area);
+                *position = match axis {
+                    Axis::X => (*position * factor).min(area.right),
+                    Axis::Y => (*position * factor).min(area.bottom),
+                };
+            }
+        }
+    }
+    tree.size = (width as f64, height as f64);
+    tree.area = area;
+    tree
+}
+
+/// Works out how `input` would be rectanglified, the same as [`rectanglify_tree`], but using
+/// `preview` (from [`rectanglify_preview`], with the same `settings`) as a starting point.
+///
+/// Wherever the real splits go the same way as the ones in `preview`, the search for where to
+/// put them starts from where they were in `preview` instead of from scratch. The result is the
+/// same as [`rectanglify_tree`] would give, apart from rounding error. `settings.parallel` is
+/// ignored.
+pub fn refine(preview: &SplitTree, input: &impl GenericImageView, settings: Settings) -> SplitTree {
+    let settings = settings.sanitized();
+    let settings = Settings {
+        shade_lines: true,
+        ..settings
+    };
+    let (ops, _) = uncancelled(plan_with(
+        input,
+        None,
+        None,
+        &settings,
+        0.0,
+        &mut TableBuffers::default(),
+        |splitter, root| Ok(splitter.split_hinted(root, preview)),
+    ));
+    tree_from_ops(input, ops, &settings)
+}
+
+/// Turns the ops for rectanglifying `input` into a [`SplitTree`].
+fn tree_from_ops(
+    input: &impl GenericImageView,
+    ops: Vec<DrawOp>,
+    settings: &Settings,
+) -> SplitTree {
+    let mut nodes = Vec::new();
+    // The indices of the nodes that don't have a parent yet.
+    let mut orphans = Vec::new();
+    for op in ops {
+        let node = match op {
+            DrawOp::Leaf {
+                area,
+                odd,
+                darkness,
+                ..
+            } => TreeNode::Leaf {
+                area,
+                odd,
+                color: average_color(input, area, None),
+                darkness,
+            },
+            DrawOp::Line {
+                axis,
+                position,
+                area,
+                shade,
+                depth,
+                ..
+            } => {
+                // Lines come straight after both of the halves they divide.
+                let second = orphans.pop().unwrap();
+                let first = orphans.pop().unwrap();
+                TreeNode::Split {
+                    area,
+                    axis,
+                    position,
+                    children: [first, second],
+                    darkness: shade,
+                    depth,
+                }
+            }
+            DrawOp::Diagonal {
+                area,
+                falling,
+                odd,
+                darkness,
+                depth,
+                ..
+            } => TreeNode::Diagonal {
+                area,
+                falling,
+                odd,
+                colors: [true, false]
+                    .map(|above| average_color(input, area, Some(Half { falling, above }))),
+                darkness,
+                depth,
+            },
+            DrawOp::Border { .. } => continue,
+        };
+        orphans.push(nodes.len());
+        nodes.push(node);
+    }
+
+    SplitTree {
+        size: (input.width() as f64, input.height() as f64),
+        area: region(input, settings),
+        nodes,
+    }
+}
+
+/// Draws `tree` onto `output`, the same as [`rectanglify`] would've drawn the image it came from
+/// with `settings`.
+///
+/// The settings that affect where the splits go are ignored, since that's already been worked
+/// out, as are `settings.channels`, `settings.overlay` and `settings.preserve_alpha`.
+pub fn render(tree: &SplitTree, output: &mut impl GenericImage, settings: Settings) {
+    let settings = settings.sanitized();
+    let output = &mut ImageRenderer::new(output, settings.clone());
+    render_nodes(tree, tree.nodes.iter().copied(), output, &settings)
+}
+
+/// The same as `render`, but only drawing `nodes` instead of all of `tree.nodes`.
+fn render_nodes(
+    tree: &SplitTree,
+    nodes: impl Iterator<Item = TreeNode>,
+    output: &mut impl Renderer,
+    settings: &Settings,
+) {
+    if settings.clear_output {
+        output.draw(DrawCommand::Clear(background(settings)));
+    }
+
+    let (mut ops, colors): (Vec<_>, Vec<_>) = nodes
+        .map(|node| match node {
+            TreeNode::Leaf {
+                area,
+                odd,
+                color,
+                darkness,
+            } => {
+                let op = DrawOp::Leaf {
+                    area,
+                    odd,
+                    darkness,
+                    depth: 0,
+                    rects: 1,
+                    halves: None,
+                };
+                (op, [color; 2])
+            }
+            TreeNode::Split {
+                area,
+                axis,
+                position,
+                darkness,
+                depth,
+                ..
+            } => {
+                let op = DrawOp::Line {
+                    axis,
+                    position,
+                    area,
+                    shade: if settings.shade_lines { darkness } else { 1.0 },
+                    depth,
+                    ends: [None; 2],
+                    tone: None,
+                };
+                (op, [settings.background_color; 2])
+            }
+            TreeNode::Diagonal {
+                area,
+                falling,
+                odd,
+                colors,
+                darkness,
+                depth,
+            } => {
+                let op = DrawOp::Diagonal {
+                    area,
+                    falling,
+                    odd,
+                    darkness,
+                    shade: if settings.shade_lines { darkness } else { 1.0 },
+                    depth,
+                };
+                (op, colors)
+            }
+        })
+        .unzip();
+    if settings.tone_match {
+        match_tone(&mut ops);
+    }
+    join_lines(&mut ops);
+
+    for (&op, colors) in ops.iter().zip(colors) {
+        op.draw_with(tree.size, output, settings, |_, half| match half {
+            Some(Half { above: false, .. }) => colors[1],
+            _ => colors[0],
+        });
+    }
+
+    if settings.border && !settings.wrap {
+        let op = DrawOp::Border { area: tree.area };
+        op.draw_with(tree.size, output, settings, |_, _| {
+            settings.background_color
+        });
+    }
+    if settings.debug_labels {
+        draw_labels(&ops, tree.size, output, settings);
+    }
+}
+
+/// Rectanglifies `input` onto `output` one level of splits at a time, calling `frame` after
+/// drawing each level, for animating the image being split up.
+///
+/// The first frame is a single rectangle, the second has it split in two, and each one after
+/// that splits every rectangle from the one before that still needs splitting. `output` ends up
+/// the same as [`rectanglify`] would've drawn it. `settings.channels`, `settings.overlay` and
+/// `settings.preserve_alpha` are ignored.
+pub fn rectanglify_progressive<I: GenericImageView, O: GenericImage>(
+    input: &I,
+    output: &mut O,
+    settings: Settings,
+    mut frame: impl FnMut(&O),
+) {
+    let settings = settings.sanitized();
+    let tree = rectanglify_tree(input, settings.clone());
+
+    // Work out the depth of each node, and which colour it'd be in a checkerboard. Parents always
+    // come after their children, so going backwards reaches each parent first.
+    let mut depths = vec![0; tree.nodes.len()];
+    let mut odd = vec![false; tree.nodes.len()];
+    for (i, node) in tree.nodes.iter().enumerate().rev() {
+        if let TreeNode::Split {
+            children: [first, second],
+            ..
+        } = *node
+        {
+            depths[first] = depths[i] + 1;
+            depths[second] = depths[i] + 1;
+            odd[first] = odd[i];
+            odd[second] = !odd[i];
+        }
+    }
+
+    let levels = depths.iter().copied().max().unwrap_or(0);
+    for level in 0..=levels {
+        // Everything at this level gets drawn as a leaf, and everything below it is left out.
+        let nodes = tree
+            .nodes
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| depths[i] <= level)
+            .map(|(i, &node)| match node {
+                TreeNode::Split { area, darkness, .. } if depths[i] == level => TreeNode::Leaf {
+                    area,
+                    odd: odd[i],
+                    color: average_color(input, area, None),
+                    darkness,
+                },
+                node => node,
+            });
+        render_nodes(
+            &tree,
+            nodes,
+            &mut ImageRenderer::new(output, settings.clone()),
+            &settings,
+        );
+        frame(output);
+    }
+}
+
+/// Returns the size an output needs to be to fit `input` drawn at `scale`.
+pub fn scaled_size(input: &impl GenericImageView, scale: f64) -> (u32, u32) {
+    let (width, height) = input.dimensions();
+    (
+        (width as f64 * scale).ceil() as u32,
+        (height as f64 * scale).ceil() as u32,
+    )
+}
+
+/// Returns the region of `input` that gets rectanglified: either `settings.roi` clamped to the
+/// bounds of `input`, or the whole thing.
+///
+/// If `settings.roi` doesn't overlap `input`, this is empty (see [`Rectangle::is_empty`]), and
+/// nothing gets drawn besides the background.
+pub fn region(input: &impl GenericImageView, settings: &Settings) -> Rectangle {
+    let (width, height) = (input.width() as f64, input.height() as f64);
+    match settings.roi {
+        Some(roi) => Rectangle {
+            left: roi.left.clamp(0.0, width),
+            top: roi.top.clamp(0.0, height),
+            right: roi.right.clamp(0.0, width),
+            bottom: roi.bottom.clamp(0.0, height),
+        },
+        None => Rectangle {
+            left: 0.0,
+            top: 0.0,
+            right: width,
+            bottom: height,
+        },
+    }
+}
+
+/// Works out everything that needs to be drawn to rectanglify `input` (with its darkness
+/// weighted by `weight`, if given), in the order it should be drawn.
+///
+/// This stops early if `cancel` is set, and returns a `Report` on the result too, including
+/// whether it got through all of the splits before `settings.deadline`.
+fn plan(
+    input: &impl GenericImageView,
+    weight: Option<Weight>,
+    cancel: Option<&AtomicBool>,
+    settings: &Settings,
+) -> Result<(Vec<DrawOp>, Report), Cancelled> {
+    plan_with(
+        input,
+        weight,
+        cancel,
+        settings,
+        0.0,
+        &mut TableBuffers::default(),
+        |splitter, root| splitter.plan(root),
+    )
+}
+
+/// The same as `plan`, but using `split` to split up the root node if there's anything to split,
+/// with `hysteresis` passed on to the `Splitter`, and storing the darkness table in `buffers`
+/// (and leaving them there afterwards).
+///
+/// The deadline counts from when this is called, so it includes building the darkness table.
+fn plan_with(
+    input: &impl GenericImageView,
+    weight: Option<Weight>,
+    cancel: Option<&AtomicBool>,
+    settings: &Settings,
+    hysteresis: f64,
+    buffers: &mut TableBuffers,
+    split: impl FnOnce(&Splitter, Node) -> Result<Vec<DrawOp>, Cancelled>,
+) -> Result<(Vec<DrawOp>, Report), Cancelled> {
+    // Everything calling this has either sanitised the settings or returned an error for them.
+    debug_assert_eq!(settings.validate(), Ok(()));
+    // A deadline too far away to represent may as well not be there.
+    let deadline = settings
+        .deadline
+        .and_then(|deadline| Instant::now().checked_add(deadline));
+
+    let area = region(input, settings);
+
+    let table = DarknessTable::new(input, weight, cancel, settings, mem::take(buffers))?;
+    let total_darkness = match settings.roi {
+        Some(_) => table.area(area),
+        None => table.total(),
+    };
+    let num_rects = match settings.budget {
+        RectBudget::PerDarkness(rects_per_pixel) => {
+            (total_darkness * rects_per_pixel).round() as usize
+        }
+        RectBudget::Count(count) => count,
+    };
+    let num_rects = num_rects.min(settings.max_rects.unwrap_or(usize::MAX));
+
+    // The border goes on top of everything else.
+    let border = (settings.border && !settings.wrap && !area.is_empty())
+        .then_some(DrawOp::Border { area })
+        .into_iter();
+
+    // If there's no darkness, there's nothing to share the rectangles out by, so there's nothing
+    // to draw (even if the budget is a fixed number of rectangles).
+    if num_rects == 0 || total_darkness <= 0.0 {
+        *buffers = table.into_buffers();
+        return Ok((border.collect(), Report::empty(num_rects)));
+    }
+
+    let splitter = Splitter {
+        settings,
+        table,
+        cancel,
+        hysteresis,
+        deadline,
+        truncated: AtomicBool::new(false),
+    };
+
+    let root = Node {
+        area,
+        rects: num_rects,
+        odd: false,
+        depth: 0,
+        forced: None,
+        hint: None,
+    };
+    let ops = match settings.symmetry {
+        Symmetry::None => split(&splitter, root),
+        symmetry => splitter.split_symmetric(root, symmetry, split),
+    };
+    let mut ops = ops?;
+    let report = splitter.report(&ops, num_rects, total_darkness);
+    if settings.fill_mode == FillMode::Gradient {
+        splitter.shade_halves(&mut ops);
+    }
+    *buffers = splitter.table.into_buffers();
+    if settings.tone_match {
+        match_tone(&mut ops);
+    }
+    join_lines(&mut ops);
+    ops.extend(border);
+    Ok((ops, report))
+}
+
+/// Fills in the `ends` of the `DrawOp::Line`s in `ops`, which need to be in the order `plan`
+/// returns them in, with each line straight after everything in the two halves it splits.
+fn join_lines(ops: &mut [DrawOp]) {
+    // The axis, position, area, depth and tone of the lines splitting the areas that the current
+    // op is inside of, innermost last.
+    let mut outer: Vec<(Axis, f64, Rectangle, u32, Option<f64>)> = Vec::new();
+    // Going backwards reaches each line before anything in the halves it splits.
+    for op in ops.iter_mut().rev() {
+        if let DrawOp::Line {
+            axis,
+            position,
+            area,
+            depth,
+            ends,
+            tone,
+            ..
+        } = op
+        {
+            while outer.last().is_some_and(|line| !line.2.encloses(*area)) {
+                outer.pop();
+            }
+            let edges = match axis {
+                Axis::X => [area.top, area.bottom],
+                Axis::Y => [area.left, area.right],
+            };
+            *ends = edges.map(|edge| {
+                outer
+                    .iter()
+                    .rev()
+                    .find(|line| line.0 != *axis && line.1 == edge)
+                    .map(|line| (line.3, line.4))
+            });
+            outer.push((*axis, *position, *area, *depth, *tone));
+        }
+    }
+}
+
+/// Fills in the `tone` of the `DrawOp::Line`s in `ops` for `Settings::tone_match`. `ops` need to
+/// be in the order `plan` returns them in, with each line straight after everything in the two
+/// halves it splits.
+fn match_tone(ops: &mut [DrawOp]) {
+    // The number of leaves in and total darkness of each of the areas that haven't had the line
+    // splitting them from the area next to them reached yet, latest last.
+    let mut halves: Vec<(f64, f64)> = Vec::new();
+    for op in ops {
+        match op {
+            DrawOp::Leaf { area, darkness, .. } => {
+                halves.push((1.0, *darkness * area.width() * area.height()))
+            }
+            DrawOp::Diagonal { area, darkness, .. } => {
+                halves.push((2.0, *darkness * area.width() * area.height()))
+            }
+            DrawOp::Line { area, tone, .. } => {
+                let second = halves.pop().unwrap();
+                let first = halves.pop().unwrap();
+                let (leaves, darkness) = (first.0 + second.0, first.1 + second.1);
+
+                let size = area.width() * area.height();
+                let mean = (darkness / size).clamp(0.0, 1.0);
+                let spacing = (size / leaves).sqrt();
+                // A grid of lines `t` thick and `spacing` apart covers `1 - (1 - t / spacing)^2`
+                // of the area.
+                *tone = Some(spacing * (1.0 - (1.0 - mean).sqrt()));
+
+                halves.push((leaves, darkness));
+            }
+            // Borders only get added once everything's been split up.
+            DrawOp::Border { .. } => {}
+        }
+    }
+}
+
+/// A rectangle that's yet to be split.
+#[derive(Debug, Clone, Copy)]
+struct Node {
+    area: Rectangle,
+    /// The number of rectangles to split it into.
+    rects: usize,
+    /// Whether this is an odd child in a checkerboard pattern, where the first child has the same
+    /// parity as its parent and the second has the opposite.
+    odd: bool,
+    /// How many splits deep this is.
+    depth: u32,
+    /// A split that's already been decided on, as the axis, position and number of rectangles in
+    /// the first half.
+    forced: Option<(Axis, f64, usize)>,
+    /// Roughly where this is expected to be split, as the axis and position, so the scan for the
+    /// split can start from there instead of from the start.
+    hint: Option<(Axis, f64)>,
+}
+
+/// What to do with a `Node`.
+enum Step {
+    /// Leave it as one of the final rectangles, either because it's used up its budget or
+    /// because it can't be split any further.
+    Leaf,
+    /// Split it in two at `position` along `axis`.
+    Split {
+        axis: Axis,
+        position: f64,
+        children: [Node; 2],
+    },
+    /// Cut it into two triangles along one of its diagonals, as described by `Half::falling`,
+    /// leaving them as final rectangles.
+    Diagonal { falling: bool },
+}
+
+/// Something to draw onto the output, in input pixel coordinates.
+///
+/// The splitting is done up-front into a list of these so that it doesn't need access to the
+/// output, and so can happen in parallel.
+#[derive(Debug, Clone, Copy)]
+enum DrawOp {
+    /// Fill in one of the final rectangles, `depth` splits down, according to
+    /// `settings.fill_mode`. `darkness` is the mean darkness of `area`, and `rects` is how many
+    /// rectangles it was meant to be split into, which is more than 1 if something stopped it
+    /// from being split any further.
+    ///
+    /// `halves` are the mean darkness of the left and right halves of `area` if it's wider than
+    /// it is tall, or the top and bottom halves otherwise, for `FillMode::Gradient`. They start
+    /// out as `None`, and get filled in by `Splitter::shade_halves` if they're needed.
+    Leaf {
+        area: Rectangle,
+        odd: bool,
+        darkness: f64,
+        depth: u32,
+        rects: usize,
+        halves: Option<[f64; 2]>,
+    },
+    /// Draw the line at `position` along `axis` that splits `area`, with `line_color` mixed
+    /// `shade` of the way in from the background colour, as thick as lines at `depth` get.
+    ///
+    /// `ends` are the depths and `tone`s of the lines that its top or left and bottom or right
+    /// ends run into, or `None` where they run into the edge of the area being split up. They
+    /// start out as `None`, and get filled in by `join_lines` once all the ops are known.
+    ///
+    /// `tone` is how thick (in input pixels) `Settings::tone_match` would have it be, which is
+    /// filled in by `match_tone` the same way, if that's set.
+    Line {
+        axis: Axis,
+        position: f64,
+        area: Rectangle,
+        shade: f64,
+        depth: u32,
+        ends: [Option<(u32, Option<f64>)>; 2],
+        tone: Option<f64>,
+    },
+    /// Fill in the two halves of `area` either side of one of its diagonals (as described by
+    /// `Half::falling`) like leaves, the one above the diagonal first, and then draw the
+    /// diagonal shaded like a `Line`. `darkness` is the mean darkness of `area`, in case it
+    /// gets drawn as one leaf.
+    Diagonal {
+        area: Rectangle,
+        falling: bool,
+        odd: bool,
+        darkness: f64,
+        shade: f64,
+        depth: u32,
+    },
+    /// Draw a border around `area`.
+    Border { area: Rectangle },
+}
+
+impl DrawOp {
+    fn draw(self, input: &impl GenericImageView, output: &mut impl Renderer, settings: &Settings) {
+        let size = (input.width() as f64, input.height() as f64);
+        self.draw_with(size, output, settings, |area, half| {
+            average_color(input, area, half)
+        })
+    }
+
+    /// Draws this onto `output`, for an input of `input_size` pixels, using `color` to get the
+    /// colour to fill a leaf's area (or half of it) with for `FillMode::Mosaic`.
+    fn draw_with(
+        self,
+        input_size: (f64, f64),
+        output: &mut impl Renderer,
+        settings: &Settings,
+        mut color: impl FnMut(Rectangle, Option<Half>) -> Color,
+    ) {
+        let (width, height) = output.size();
+        let size = covered_size(input_size, (width, height), settings);
+
+        // When there's no room for the diagonal, a diagonal split gets drawn like one leaf
+        // instead, using the colour of both halves together.
+        let mut leaf_color = None;
+        let op = match self {
+            DrawOp::Diagonal {
+                area,
+                falling,
+                odd,
+                darkness,
+                ..
+            } if settings.padding != 0.0
+                || matches!(
+                    settings.fill_mode,
+                    FillMode::Ellipses | FillMode::FilledEllipses | FillMode::Halftone
+                ) =>
+            {
+                let [above, below] =
+                    [true, false].map(|above| color(area, Some(Half { falling, above })));
+                leaf_color = Some(above.mix(below, 0.5));
+                DrawOp::Leaf {
+                    area,
+                    odd,
+                    darkness,
+                    depth: 0,
+                    rects: 2,
+                    halves: None,
+                }
+            }
+            op => op,
+        };
+
+        match op {
+            DrawOp::Leaf {
+                area,
+                odd,
+                darkness,
+                halves,
+                ..
+            } => {
+                let padded = settings.padding != 0.0;
+                let mut bounds = area.normalize(size);
+                if padded {
+                    let x = settings.padding / width as f64;
+                    let y = settings.padding / height as f64;
+                    bounds = Rectangle {
+                        left: bounds.left + x,
+                        top: bounds.top + y,
+                        right: bounds.right - x,
+                        bottom: bounds.bottom - y,
+                    };
+                    if bounds.width() <= 0.0 || bounds.height() <= 0.0 {
+                        return;
+                    }
+                }
+
+                let radius = settings
+                    .corner_radius
+                    .min(bounds.width() * width as f64 / 2.0)
+                    .min(bounds.height() * height as f64 / 2.0);
+                let command = match settings.fill_mode {
+                    FillMode::Lines if padded => DrawCommand::Border {
+                        area: bounds,
+                        color: settings.line_color,
+                        thickness: settings.line_thickness,
+                    },
+                    FillMode::Lines => return,
+                    FillMode::Mosaic => DrawCommand::Fill {
+                        area: bounds,
+                        color: leaf_color.unwrap_or_else(|| color(area, None)),
+                        radius,
+                    },
+                    FillMode::Checkerboard => DrawCommand::Fill {
+                        area: bounds,
+                        color: if odd {
+                            settings.line_color
+                        } else {
+                            settings.background_color
+                        },
+                        radius,
+                    },
+                    FillMode::Quantized { .. } => DrawCommand::Fill {
+                        area: bounds,
+                        color: settings.fill_mode.quantized_color(darkness).unwrap(),
+                        radius,
+                    },
+                    FillMode::Gradient => {
+                        let [first, second] = halves.unwrap_or([darkness; 2]);
+                        // The halves' darkness goes in the middle of each half, a quarter of the
+                        // way in from each edge, so the edges are half as far again out.
+                        let slope = second - first;
+                        let ends = [first - slope / 2.0, second + slope / 2.0];
+                        DrawCommand::FillGradient {
+                            area: bounds,
+                            axis: match area.width() >= area.height() {
+                                true => Axis::X,
+                                false => Axis::Y,
+                            },
+                            colors: ends.map(|darkness| gradient_color(settings, darkness)),
+                            radius,
+                        }
+                    }
+                    FillMode::Mondrian => match FillMode::mondrian_color(area, None, settings) {
+                        Some(color) => DrawCommand::Fill {
+                            area: bounds,
+                            color,
+                            radius,
+                        },
+                        None => return,
+                    },
+                    FillMode::Ellipses if settings.shape == Shape::Rectangle => {
+                        DrawCommand::Border {
+                            area: bounds,
+                            color: settings.line_color,
+                            thickness: settings.line_thickness,
+                        }
+                    }
+                    FillMode::Ellipses | FillMode::FilledEllipses => DrawCommand::Ellipse {
+                        area: bounds,
+                        filled: settings.fill_mode == FillMode::FilledEllipses,
+                        color: settings.line_color,
+                        thickness: settings.line_thickness,
+                    },
+                    FillMode::Halftone => {
+                        // Work in output pixels so that the dots come out round.
+                        let (width, height) = (width as f64, height as f64);
+                        let (min_radius, max_radius) = settings.halftone_radius;
+                        let fraction = min_radius + (max_radius - min_radius) * darkness;
+                        let radius =
+                            fraction * (bounds.width() * width).min(bounds.height() * height) / 2.0;
+                        if radius <= 0.0 {
+                            return;
+                        }
+                        let center = (
+                            (bounds.left + bounds.right) / 2.0,
+                            (bounds.top + bounds.bottom) / 2.0,
+                        );
+                        let dot = Rectangle {
+                            left: center.0 - radius / width,
+                            top: center.1 - radius / height,
+                            right: center.0 + radius / width,
+                            bottom: center.1 + radius / height,
+                        };
+                        match settings.shape {
+                            Shape::Ellipse => DrawCommand::Ellipse {
+                                area: dot,
+                                filled: true,
+                                color: settings.line_color,
+                                thickness: settings.line_thickness,
+                            },
+                            Shape::Rectangle => DrawCommand::Fill {
+                                area: dot,
+                                color: settings.line_color,
+                                radius: 0.0,
+                            },
+                        }
+                    }
+                };
+                output.draw(command);
+            }
+            // The gaps between padded rectangles take the place of the lines.
+            DrawOp::Line { .. } if settings.padding != 0.0 => {}
+            DrawOp::Line { .. }
+                if matches!(
+                    settings.fill_mode,
+                    FillMode::Ellipses | FillMode::FilledEllipses | FillMode::Halftone
+                ) => {}
+            DrawOp::Line {
+                axis,
+                position,
+                area,
+                shade,
+                depth,
+                ends,
+                tone,
+            } => {
+                // How many output pixels there are to each input pixel across the line, and
+                // along it.
+                let (across, along) = match axis {
+                    Axis::X => (width as f64 / size.0, height as f64 / size.1),
+                    Axis::Y => (height as f64 / size.1, width as f64 / size.0),
+                };
+                let color = line_color(settings, shade, depth);
+                let mut area = area.normalize(size);
+                // Wherever one line runs into another (or the border), stop it at the edge of
+                // the other line instead of drawing over it twice, since that shows up wherever
+                // lines get blended in: along antialiased edges, or anywhere with a
+                // semi-transparent colour. Otherwise, the second line just replaces the pixels,
+                // so this isn't needed.
+                if settings.antialias || color.a < 1.0 {
+                    let border = settings.border && !settings.wrap;
+                    // How thick the thing each end runs into is, and whether it's the border.
+                    let [start_stop, end_stop] = ends.map(|end| match end {
+                        Some((depth, tone)) => {
+                            Some((line_thickness(settings, depth, tone, along).max(1), false))
+                        }
+                        None if border => Some((settings.line_thickness.max(1), true)),
+                        None => None,
+                    });
+                    let (start, end, pixels) = match axis {
+                        Axis::X => (&mut area.top, &mut area.bottom, height as f64),
+                        Axis::Y => (&mut area.left, &mut area.right, width as f64),
+                    };
+                    // Without antialiasing, lines cover whole pixels (see `stroke`), and the
+                    // border always does, so those get stopped at pixel boundaries. They're
+                    // nudged a quarter of a pixel in, so that they round to the right pixels.
+                    let nudge = if settings.antialias { 0.0 } else { 0.25 };
+                    if let Some((thickness, border)) = start_stop {
+                        let edge = *start * pixels;
+                        *start = match (border, settings.antialias) {
+                            (false, true) => edge + thickness as f64 / 2.0,
+                            (false, false) => edge.floor() + (thickness / 2) as f64 + 1.0 + nudge,
+                            (true, _) => edge.floor() + thickness as f64 + nudge,
+                        } / pixels;
+                    }
+                    if let Some((thickness, border)) = end_stop {
+                        let edge = *end * pixels;
+                        *end = match (border, settings.antialias) {
+                            (false, true) => edge - thickness as f64 / 2.0,
+                            (false, false) => edge.floor() - ((thickness - 1) / 2) as f64 - nudge,
+                            (true, _) => edge.ceil() - thickness as f64 - nudge,
+                        } / pixels;
+                    }
+                    if area.width() <= 0.0 || area.height() <= 0.0 {
+                        return;
+                    }
+                }
+                let thickness = line_thickness(settings, depth, tone, across);
+                output.draw(match axis {
+                    Axis::X => DrawCommand::VLine {
+                        x: position / size.0,
+                        area,
+                        color,
+                        thickness,
+                    },
+                    Axis::Y => DrawCommand::HLine {
+                        y: position / size.1,
+                        area,
+                        color,
+                        thickness,
+                    },
+                });
+            }
+            DrawOp::Diagonal {
+                area,
+                falling,
+                odd,
+                darkness,
+                shade,
+                depth,
+            } => {
+                let bounds = area.normalize(size);
+                for (above, odd) in [(true, odd), (false, !odd)] {
+                    let color = match settings.fill_mode {
+                        FillMode::Mosaic => color(area, Some(Half { falling, above })),
+                        FillMode::Checkerboard if odd => settings.line_color,
+                        FillMode::Checkerboard => settings.background_color,
+                        // Only the darkness of the whole area is known, so both halves get the
+                        // same level.
+                        FillMode::Quantized { .. } => {
+                            settings.fill_mode.quantized_color(darkness).unwrap()
+                        }
+                        FillMode::Gradient => gradient_color(settings, darkness),
+                        FillMode::Mondrian => {
+                            match FillMode::mondrian_color(area, Some(above), settings) {
+                                Some(color) => color,
+                                None => continue,
+                            }
+                        }
+                        _ => continue,
+                    };
+                    output.draw(DrawCommand::FillTriangle {
+                        area: bounds,
+                        falling,
+                        above,
+                        color,
+                    });
+                }
+
+                output.draw(DrawCommand::Diagonal {
+                    area: bounds,
+                    falling,
+                    color: line_color(settings, shade, depth),
+                    thickness: thickness(settings, depth),
+                });
+            }
+            DrawOp::Border { area } => output.draw(DrawCommand::Border {
+                area: area.normalize(size),
+                color: settings.line_color,
+                thickness: settings.line_thickness,
+            }),
+        }
+    }
+}
+
+/// Returns the size of the area of the input that `output_size` covers, in input pixels.
+fn covered_size(
+    input_size: (f64, f64),
+    output_size: (u32, u32),
+    settings: &Settings,
+) -> (f64, f64) {
+    match settings.scale {
+        Some(scale) => (output_size.0 as f64 / scale, output_size.1 as f64 / scale),
+        None => input_size,
+    }
+}
+
+/// Calls `f` with each pixel of `output` and the nearest pixel of `input` to it, scaling `input`
+/// to `output` the same way the rectangles are. `input` is `None` for the parts of `output` past
+/// the edge of `input`.
+fn for_each_source<I: GenericImageView, O: GenericImage>(
+    input: &I,
+    output: &mut O,
+    settings: &Settings,
+    mut f: impl FnMut(&mut O, u32, u32, Option<I::Pixel>),
+) {
+    let (width, height) = output.dimensions();
+    let input_size = (input.width() as f64, input.height() as f64);
+    let size = covered_size(input_size, (width, height), settings);
+    for y in 0..height {
+        let input_y = ((y as f64 + 0.5) / height as f64 * size.1).floor() as u32;
+        for x in 0..width {
+            let input_x = ((x as f64 + 0.5) / width as f64 * size.0).floor() as u32;
+            let source = (input_x < input.width() && input_y < input.height())
+                .then(|| input.get_pixel(input_x, input_y));
+            f(output, x, y, source);
+        }
+    }
+}
+
+/// Fills `output` with `input` for `Settings::overlay`, composited over the background with
+/// `Settings::source_opacity` and converted to the output's format.
+fn copy_input<I: GenericImageView, O: GenericImage>(
+    input: &I,
+    output: &mut O,
+    settings: &Settings,
+) {
+    let background = background(settings);
+    let background_pixel = background.to_pixel();
+    for_each_source(input, output, settings, |output, x, y, source| {
+        let pixel = match source {
+            Some(source) => Color::from_pixel(source)
+                .over(background, settings.source_opacity)
+                .to_pixel(),
+            None => background_pixel,
+        };
+        output.put_pixel(x, y, pixel);
+    });
+}
+
+/// Copies the alpha channel of `input` onto `output` for `Settings::preserve_alpha`, if they
+/// both have one.
+fn copy_alpha<I: GenericImageView, O: GenericImage>(
+    input: &I,
+    output: &mut O,
+    settings: &Settings,
+) {
+    let has_alpha = |channels| channels == 2 || channels == 4;
+    if !has_alpha(I::Pixel::CHANNEL_COUNT) || !has_alpha(O::Pixel::CHANNEL_COUNT) {
+        return;
+    }
+
+    let alpha_channel = O::Pixel::CHANNEL_COUNT as usize - 1;
+    let max = <O::Pixel as Pixel>::Subpixel::DEFAULT_MAX_VALUE
+        .to_f64()
+        .unwrap();
+    for_each_source(input, output, settings, |output, x, y, source| {
+        if let Some(source) = source {
+            let alpha = Color::from_pixel(source).a.clamp(0.0, 1.0) * max;
+            let alpha = if max > 1.0 { alpha.round() } else { alpha };
+            let mut pixel = output.get_pixel(x, y);
+            pixel.channels_mut()[alpha_channel] = NumCast::from(alpha).unwrap();
+            output.put_pixel(x, y, pixel);
+        }
+    });
+}
+
+/// Returns how thick to draw a line splitting a rectangle `depth` splits down, according to
+/// `settings.thickness_falloff`.
+fn thickness(settings: &Settings, depth: u32) -> u32 {
+    if settings.thickness_falloff == 1.0 {
+        return settings.line_thickness;
+    }
+    let base = settings.line_thickness.max(1) as f64;
+    let depth = depth.min(i32::MAX as u32) as i32;
+    (base * settings.thickness_falloff.powi(depth))
+        .round()
+        .clamp(1.0, u32::MAX as f64) as u32
+}
+
+/// Returns how thick to draw a line splitting a rectangle `depth` splits down, given its `tone`
+/// from `match_tone` and the number of output pixels to each input pixel across it, `scale`.
+fn line_thickness(settings: &Settings, depth: u32, tone: Option<f64>, scale: f64) -> u32 {
+    let usual = thickness(settings, depth);
+    match tone {
+        Some(tone) => {
+            let usual = usual.max(1) as f64;
+            (usual + (tone * scale - usual) * settings.tone_match_strength)
+                .round()
+                .clamp(1.0, u32::MAX as f64) as u32
+        }
+        None => usual,
+    }
+}
+
+/// Returns the colour `FillMode::Gradient` uses for a darkness of `darkness`, which is clamped to
+/// between 0 and 1.
+fn gradient_color(settings: &Settings, darkness: f64) -> Color {
+    settings
+        .background_color
+        .mix(settings.line_color, darkness.clamp(0.0, 1.0))
+}
+
+/// Returns the colour to fill the output with before drawing anything.
+fn background(settings: &Settings) -> Color {
+    if settings.transparent_background {
+        // The colour doesn't matter, except in what antialiased lines blend into; using the line
+        // colour keeps their edges from picking up the background's colour.
+        Color {
+            a: 0.0,
+            ..settings.line_color
+        }
+    } else {
+        settings.background_color
+    }
+}
+
+/// Returns the colour to draw a line `depth` splits down in with the given shade (from 0 to 1),
+/// fading it into the background for lower shades.
+fn line_color(settings: &Settings, shade: f64, depth: u32) -> Color {
+    let color = match settings.line_palette.as_slice() {
+        [] => settings.line_color,
+        palette => palette[depth as usize % palette.len()],
+    };
+    if shade < 1.0 {
+        settings.background_color.mix(color, shade)
+    } else {
+        color
+    }
+}
+
+/// Moves `position` to the nearest of the lines `spacing` apart starting from `offset` that's
+/// strictly between `start` and `end`, or returns `None` if there aren't any.
+fn snap(position: f64, start: f64, end: f64, spacing: f64, offset: f64) -> Option<f64> {
+    // The indices of the first and last lines in range.
+    let first = ((start - offset) / spacing).floor() + 1.0;
+    let last = ((end - offset) / spacing).ceil() - 1.0;
+    if first > last {
+        return None;
+    }
+
+    // Not `clamp`, since that panics if `first > last` due to rounding error.
+    let index = ((position - offset) / spacing).round().max(first).min(last);
+    Some(offset + index * spacing).filter(|&position| position > start && position < end)
+}
+
+/// Returns the shade to draw a line splitting `area` with if `settings.shade_lines` is set: the
+/// mean darkness of `area`, given that it has `darkness` in total.
+fn shade(darkness: f64, area: Rectangle) -> f64 {
+    (darkness / (area.width() * area.height())).clamp(0.0, 1.0)
+}
+
+/// The state needed to work out where the splits go.
+struct Splitter<'a> {
+    settings: &'a Settings,
+    table: DarknessTable,
+    /// If set, splitting stops.
+    cancel: Option<&'a AtomicBool>,
+    /// How close (in input pixels) a split has to be to the previous position passed to `step` for
+    /// it to be kept there instead.
+    hysteresis: f64,
+    /// When `settings.deadline` runs out, if there is one.
+    deadline: Option<Instant>,
+    /// Set once the deadline's passed, after which nothing else gets split.
+    truncated: AtomicBool,
+}
+
+/// How many splits to do between checking the time, since that's a lot slower than a split.
+const DEADLINE_INTERVAL: usize = 64;
+
+/// Nodes with fewer rectangles than this aren't worth splitting across threads.
+#[cfg(feature = "rayon")]
+const PARALLEL_THRESHOLD: usize = 64;
+/// How deep `split_parallel` is allowed to recurse.
+#[cfg(feature = "rayon")]
+const PARALLEL_MAX_DEPTH: u32 = 64;
+
+impl Splitter<'_> {
+    /// Returns whether the deadline has passed, only actually checking the time if `splits` (the
+    /// number of splits done so far) is a multiple of `DEADLINE_INTERVAL`.
+    fn out_of_time(&self, splits: usize) -> bool {
+        if self.truncated.load(Ordering::Relaxed) {
+            return true;
+        }
+        // Not `is_multiple_of`, which needs Rust 1.87.
+        #[allow(clippy::manual_is_multiple_of)]
+        let expired = splits % DEADLINE_INTERVAL == 0
+            && self
+                .deadline
+                .is_some_and(|deadline| Instant::now() >= deadline);
+        if expired {
+            self.truncated.store(true, Ordering::Relaxed);
+        }
+        expired
+    }
+
+    /// Works out everything that needs to be drawn to split up `root`, in the order it should be
+    /// drawn.
+    fn plan(&self, root: Node) -> Result<Vec<DrawOp>, Cancelled> {
+        #[cfg(feature = "rayon")]
+        if self.settings.parallel {
+            return self.split_parallel(root);
+        }
+
+        let mut ops = Vec::new();
+        self.split(root, &mut ops)?;
+        Ok(ops)
+    }
+
+    /// Splits `node`, adding what needs to be drawn to `ops`.
+    ///
+    /// Each line is added after everything inside the two rectangles it divides, so that it ends
+    /// up on top of anything the leaves were filled with.
+    ///
+    /// This uses an explicit stack rather than recursion, since lopsided splits of large budgets
+    /// can go deep enough to overflow the (fairly small) stack of a streaming thread.
+    fn split(&self, node: Node, ops: &mut Vec<DrawOp>) -> Result<(), Cancelled> {
+        enum Task {
+            Split(Node),
+            Draw(DrawOp),
+        }
+
+        let mut stack = vec![Task::Split(node)];
+        let mut splits = 0;
+        while let Some(task) = stack.pop() {
+            let node = match task {
+                Task::Split(node) => node,
+                Task::Draw(op) => {
+                    ops.push(op);
+                    continue;
+                }
+            };
+
+            check(self.cancel)?;
+            if self.out_of_time(splits) {
+                ops.push(self.leaf(node));
+                continue;
+            }
+            splits += 1;
+            match self.step(node, None) {
+                Step::Leaf => ops.push(self.leaf(node)),
+                Step::Diagonal { falling } => ops.push(self.diagonal(node, falling)),
+                Step::Split {
+                    axis,
+                    position,
+                    children: [first, second],
+                } => {
+                    // These get popped off in the reverse order, so the first child is done first
+                    // and the line last.
+                    stack.push(Task::Draw(self.line(axis, position, node)));
+                    stack.push(Task::Split(second));
+                    stack.push(Task::Split(first));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The same as `split`, but using the splits in `preview` as hints for where to split each
+    /// node, for as long as the two trees match up.
+    fn split_hinted(&self, node: Node, preview: &SplitTree) -> Vec<DrawOp> {
+        enum Task {
+            Split(Node, Option<usize>),
+            Draw(DrawOp),
+        }
+
+        let mut ops = Vec::new();
+        let mut stack = vec![Task::Split(node, preview.nodes.len().checked_sub(1))];
+        let mut splits = 0;
+        while let Some(task) = stack.pop() {
+            let (mut node, index) = match task {
+                Task::Split(node, index) => (node, index),
+                Task::Draw(op) => {
+                    ops.push(op);
+                    continue;
+                }
+            };
+
+            if self.out_of_time(splits) {
+                ops.push(self.leaf(node));
+                continue;
+            }
+            splits += 1;
+
+            let hint = index.and_then(|index| match preview.nodes[index] {
+                TreeNode::Split {
+                    axis,
+                    position,
+                    children,
+                    ..
+                } => Some((axis, position, children)),
+                _ => None,
+            });
+            node.hint = hint.map(|(axis, position, _)| (axis, position));
+            match self.step(node, None) {
+                Step::Leaf => ops.push(self.leaf(node)),
+                Step::Diagonal { falling } => ops.push(self.diagonal(node, falling)),
+                Step::Split {
+                    axis,
+                    position,
+                    children: [first, second],
+                } => {
+                    // The children only match up if this was split the same way.
+                    let children = hint
+                        .filter(|&(hint_axis, _, _)| hint_axis == axis)
+                        .map(|(_, _, children)| children);
+                    stack.push(Task::Draw(self.line(axis, position, node)));
+                    stack.push(Task::Split(second, children.map(|children| children[1])));
+                    stack.push(Task::Split(first, children.map(|children| children[0])));
+                }
+            }
+        }
+
+        ops
+    }
+
+    /// The same as `split`, but splitting the two halves of large nodes on different threads.
+    /// The result is exactly the same.
+    #[cfg(feature = "rayon")]
+    fn split_parallel(&self, node: Node) -> Result<Vec<DrawOp>, Cancelled> {
+        // Past a certain depth, fall back to `split` so this can't overflow the stack either.
+        if node.rects < PARALLEL_THRESHOLD || node.depth >= PARALLEL_MAX_DEPTH {
+            let mut ops = Vec::new();
+            self.split(node, &mut ops)?;
+            return Ok(ops);
+        }
+
+        check(self.cancel)?;
+        // There aren't many of these, so it's fine to check the time for every one.
+        if self.out_of_time(0) {
+            return Ok(vec![self.leaf(node)]);
+        }
+        match self.step(node, None) {
+            Step::Leaf => Ok(vec![self.leaf(node)]),
+            Step::Diagonal { falling } => Ok(vec![self.diagonal(node, falling)]),
+            Step::Split {
+                axis,
+                position,
+                children: [first, second],
+            } => {
+                let (first, second) = rayon::join(
+                    || self.split_parallel(first),
+                    || self.split_parallel(second),
+                );
+                let mut ops = first?;
+                ops.extend(second?);
+                ops.push(self.line(axis, position, node));
+                Ok(ops)
+            }
+        }
+    }
+
+    /// Splits up `root` symmetrically for `Settings::symmetry`, using `split` to split up the
+    /// left half or top-left quarter of it.
+    fn split_symmetric(
+        &self,
+        root: Node,
+        symmetry: Symmetry,
+        split: impl FnOnce(&Self, Node) -> Result<Vec<DrawOp>, Cancelled>,
+    ) -> Result<Vec<DrawOp>, Cancelled> {
+        let area = root.area;
+        let (left, _) = area.split(Axis::X, (area.left + area.right) / 2.0);
+        // The part to split up, how many copies of it there are, and how many splits down it is.
+        let (part, parts, levels) = match symmetry {
+            Symmetry::FourWay => (left.split(Axis::Y, (left.top + left.bottom) / 2.0).0, 4, 2),
+            _ => (left, 2, 1),
+        };
+        let ops = split(
+            self,
+            Node {
+                area: part,
+                rects: (root.rects / parts).max(1),
+                depth: root.depth + levels,
+                ..root
+            },
+        )?;
+
+        let ops = match symmetry {
+            Symmetry::FourWay => self.mirror(ops, Axis::Y, left, root.depth + 1),
+            _ => ops,
+        };
+        Ok(self.mirror(ops, Axis::X, area, root.depth))
+    }
+
+    /// Takes `ops`, which split up the left or top half of `area` (depending on `axis`), and adds
+    /// their reflection onto the other half, followed by the line between the halves, `depth`
+    /// splits down.
+    ///
+    /// The reflection keeps the order ops come in (children before their parents, left or top
+    /// first), so the result can still be turned into a `SplitTree`.
+    fn mirror(&self, ops: Vec<DrawOp>, axis: Axis, area: Rectangle, depth: u32) -> Vec<DrawOp> {
+        let (start, end) = match axis {
+            Axis::X => (area.left, area.right),
+            Axis::Y => (area.top, area.bottom),
+        };
+        let reflect = |rect: Rectangle| match axis {
+            Axis::X => Rectangle {
+                left: start + end - rect.right,
+                right: start + end - rect.left,
+                ..rect
+            },
+            Axis::Y => Rectangle {
+                top: start + end - rect.bottom,
+                bottom: start + end - rect.top,
+                ..rect
+            },
+        };
+
+        // The reflected subtrees that haven't been joined up to their parent yet, in order.
+        let mut subtrees: Vec<Vec<DrawOp>> = Vec::new();
+        for &op in &ops {
+            let reflected = match op {
+                DrawOp::Leaf {
+                    area,
+                    odd,
+                    depth,
+                    rects,
+                    ..
+                } => {
+                    let area = reflect(area);
+                    DrawOp::Leaf {
+                        area,
+                        odd: !odd,
+                        darkness: shade(self.table.area(area), area),
+                        depth,
+                        rects,
+                        halves: None,
+                    }
+                }
+                DrawOp::Diagonal {
+                    area,
+                    falling,
+                    odd,
+                    depth,
+                    ..
+                } => {
+                    let area = reflect(area);
+                    DrawOp::Diagonal {
+                        area,
+                        falling: !falling,
+                        odd: !odd,
+                        darkness: shade(self.table.area(area), area),
+                        shade: self.shade(area),
+                        depth,
+                    }
+                }
+                DrawOp::Line {
+                    axis: line_axis,
+                    position,
+                    area,
+                    depth,
+                    ..
+                } => {
+                    let area = reflect(area);
+                    let second = subtrees.pop().unwrap();
+                    let first = subtrees.pop().unwrap();
+                    // Reflecting across a line the same way round swaps its two halves over.
+                    let (mut first, second, position) = if line_axis == axis {
+                        (second, first, start + end - position)
+                    } else {
+                        (first, second, position)
+                    };
+                    first.extend(second);
+                    first.push(DrawOp::Line {
+                        axis: line_axis,
+                        position,
+                        area,
+                        shade: self.shade(area),
+                        depth,
+                        ends: [None; 2],
+                        tone: None,
+                    });
+                    subtrees.push(first);
+                    continue;
+                }
+                // Borders only get added once everything's been split up.
+                DrawOp::Border { .. } => unreachable!(),
+            };
+            subtrees.push(vec![reflected]);
+        }
+
+        let mut mirrored = ops;
+        mirrored.extend(subtrees.into_iter().flatten());
+        mirrored.push(DrawOp::Line {
+            axis,
+            position: (start + end) / 2.0,
+            area,
+            shade: self.shade(area),
+            depth,
+            ends: [None; 2],
+            tone: None,
+        });
+        mirrored
+    }
+
+    /// Returns the op for the line at `position` along `axis` that splits `node`.
+    fn line(&self, axis: Axis, position: f64, node: Node) -> DrawOp {
+        DrawOp::Line {
+            axis,
+            position,
+            area: node.area,
+            shade: self.shade(node.area),
+            depth: node.depth,
+            ends: [None; 2],
+            tone: None,
+        }
+    }
+
+    /// Returns the op for leaving `node` as one of the final rectangles.
+    fn leaf(&self, node: Node) -> DrawOp {
+        DrawOp::Leaf {
+            area: node.area,
+            odd: node.odd,
+            darkness: shade(self.table.area(node.area), node.area),
+            depth: node.depth,
+            rects: node.rects,
+            halves: None,
+        }
+    }
+
+    /// Fills in the `halves` of the `DrawOp::Leaf`s in `ops`.
+    fn shade_halves(&self, ops: &mut [DrawOp]) {
+        for op in ops {
+            if let DrawOp::Leaf { area, halves, .. } = op {
+                let (first, second) = match area.width() >= area.height() {
+                    true => area.split(Axis::X, (area.left + area.right) / 2.0),
+                    false => area.split(Axis::Y, (area.top + area.bottom) / 2.0),
+                };
+                *halves = Some([first, second].map(|half| shade(self.table.area(half), half)));
+            }
+        }
+    }
+
+    /// Returns the op for cutting `node` along a diagonal.
+    fn diagonal(&self, node: Node, falling: bool) -> DrawOp {
+        DrawOp::Diagonal {
+            area: node.area,
+            falling,
+            odd: node.odd,
+            darkness: shade(self.table.area(node.area), node.area),
+            shade: self.shade(node.area),
+            depth: node.depth,
+        }
+    }
+
+    /// Returns the shade to draw a line splitting `area` with.
+    fn shade(&self, area: Rectangle) -> f64 {
+        if self.settings.shade_lines {
+            shade(self.table.area(area), area)
+        } else {
+            1.0
+        }
+    }
+
+    /// Works out what to do with `node`, given where it was split before (if it was).
+    fn step(&self, node: Node, previous: Option<(Axis, f64)>) -> Step {
+        let Node {
+            area,
+            rects,
+            odd,
+            depth,
+            forced,
+            ..
+        } = node;
+        let settings = self.settings;
+
+        if rects == 1
+            || settings
+                .max_depth
+                .is_some_and(|max_depth| depth >= max_depth)
+        {
+            return Step::Leaf;
+        }
+        if let Some(threshold) = settings.variance_threshold {
+            if self.table.variance(area) < threshold {
+                return Step::Leaf;
+            }
+        }
+        let stripe_width = match settings.split_strategy {
+            SplitStrategy::VerticalOnly => Some(area.width()),
+            SplitStrategy::HorizontalOnly => Some(area.height()),
+            _ => None,
+        };
+        if stripe_width.is_some_and(|width| width <= 1.0) {
+            return Step::Leaf;
+        }
+        if settings.split_strategy == SplitStrategy::Diagonal && rects == 2 {
+            return match self.diagonal_split(area) {
+                Some(falling) => Step::Diagonal { falling },
+                None => Step::Leaf,
+            };
+        }
+
+        // Any splits the children have to make at the same time as this one.
+        let mut child_splits = [None, None];
+        let split = match forced {
+            Some(split) => Some(split),
+            None if rects >= 4 && self.quartered(node) => {
+                self.quad_split(area, rects).map(|(x, y, [tl, tr, bl, _])| {
+                    // Split vertically first, and then have each half split horizontally at the
+                    // same place.
+                    child_splits = [Some((Axis::Y, y, tl)), Some((Axis::Y, y, tr))];
+                    (Axis::X, x, tl + bl)
+                })
+            }
+            None => self.binary_split(node, previous),
+        };
+        let (axis, mut position, target_rects) = match split {
+            Some(split) => split,
+            // There isn't enough darkness left to split it.
+            None => return Step::Leaf,
+        };
+
+        let (start, end) = match axis {
+            Axis::X => (area.left, area.right),
+            Axis::Y => (area.top, area.bottom),
+        };
+        if let Some(ratio) = settings.max_aspect_ratio {
+            // The length of the side being split, and the other one, which both halves share.
+            let (length, across) = match axis {
+                Axis::X => (area.width(), area.height()),
+                Axis::Y => (area.height(), area.width()),
+            };
+            // The range the length of the first half can be in for both halves to be in bounds.
+            let min = f64::max(across / ratio, length - across * ratio);
+            let max = f64::min(across * ratio, length - across / ratio);
+            position = if min <= max {
+                position.max(start + min).min(start + max)
+            } else {
+                (start + end) / 2.0
+            };
+        }
+        if settings.deterministic {
+            position = (position * FIXED_POINT_POSITION).round() / FIXED_POINT_POSITION;
+        }
+        if settings.snap_to_pixels {
+            match snap(position, start, end, 1.0, 0.0) {
+                Some(snapped) => position = snapped,
+                // There's no whole pixel to split it at.
+                None => return Step::Leaf,
+            }
+        }
+        if let Some(grid) = settings.grid {
+            let offset = match axis {
+                Axis::X => settings.grid_offset.0,
+                Axis::Y => settings.grid_offset.1,
+            };
+            match snap(position, start, end, grid.max(1) as f64, offset) {
+                Some(snapped) => position = snapped,
+                // There's no grid line to split it at.
+                None => return Step::Leaf,
+            }
+        }
+        if position <= start
+            || position >= end
+            || position - start < settings.min_rect_size
+            || end - position < settings.min_rect_size
+        {
+            // Splitting would make a rectangle that's too small, so stop here and drop the rest of
+            // the budget.
+            return Step::Leaf;
+        }
+
+        let (first, second) = area.split(axis, position);
+        let (target_rects, second_rects) = self.prune(first, second, target_rects, rects);
+        Step::Split {
+            axis,
+            position,
+            children: [
+                Node {
+                    area: first,
+                    rects: target_rects,
+                    odd,
+                    depth: depth + 1,
+                    forced: child_splits[0],
+                    hint: None,
+                },
+                Node {
+                    area: second,
+                    rects: second_rects,
+                    odd: !odd,
+                    depth: depth + 1,
+                    forced: child_splits[1],
+                    hint: None,
+                },
+            ],
+        }
+    }
+
+    /// Works out the `Report` for `ops`, which share out `total_darkness` between
+    /// `requested_rects` rectangles. This only looks at the darkness table, not the input.
+    fn report(&self, ops: &[DrawOp], requested_rects: usize, total_darkness: f64) -> Report {
+        let ideal = total_darkness / requested_rects as f64;
+        let mut rects = 0;
+        let mut total_error = Sum::default();
+        let mut max_error: f64 = 0.0;
+        let mut add = |darkness: f64| {
+            let error = (darkness - ideal).abs() / ideal;
+            rects += 1;
+            total_error.add(error);
+            max_error = max_error.max(error);
+        };
+
+        let mut clamped_leaves = 0;
+        for &op in ops {
+            match op {
+                DrawOp::Leaf { area, rects, .. } => {
+                    if rects > 1 {
+                        clamped_leaves += 1;
+                    }
+                    add(self.table.area(area));
+                }
+                DrawOp::Diagonal { area, falling, .. } => {
+                    let above = self.darkness_above(area, falling);
+                    add(above);
+                    add(self.table.area(area) - above);
+                }
+                DrawOp::Line { .. } | DrawOp::Border { .. } => {}
+            }
+        }
+
+        Report {
+            completion: match self.truncated.load(Ordering::Relaxed) {
+                true => Completion::Truncated,
+                false => Completion::Complete,
+            },
+            requested_rects,
+            rects,
+            mean_error: match rects {
+                0 => 0.0,
+                _ => total_error.total / rects as f64,
+            },
+            max_error,
+            clamped_leaves,
+        }
+    }
+
+    /// Returns whether `node` gets split into quarters, which it does with `SplitStrategy::Quad`,
+    /// or if it's the first split with `settings.wrap`.
+    fn quartered(&self, node: Node) -> bool {
+        let settings = self.settings;
+        match settings.split_strategy {
+            SplitStrategy::Quad => true,
+            SplitStrategy::VerticalOnly | SplitStrategy::HorizontalOnly => false,
+            _ => settings.wrap && node.depth == 0,
+        }
+    }
+
+    /// Works out how many of `rects` rectangles the halves `first` and `second` of a split get,
+    /// given that `first` was meant to get `target_rects`, taking `settings.min_leaf_darkness`
+    /// into account.
+    fn prune(
+        &self,
+        first: Rectangle,
+        second: Rectangle,
+        target_rects: usize,
+        rects: usize,
+    ) -> (usize, usize) {
+        let min_darkness = self.settings.min_leaf_darkness;
+        if min_darkness <= 0.0 {
+            return (target_rects, rects - target_rects);
+        }
+
+        match [first, second].map(|half| self.table.area(half) < min_darkness) {
+            [true, true] => (1, 1),
+            [true, false] => (1, rects - 1),
+            [false, true] => (rects - 1, 1),
+            [false, false] => (target_rects, rects - target_rects),
+        }
+    }
+
+    /// Works out which diagonal to cut `area` along, returning whether it's the one from the top
+    /// left to the bottom right, or `None` if there isn't any darkness to share out.
+    fn diagonal_split(&self, area: Rectangle) -> Option<bool> {
+        let total = self.table.area(area);
+        if total <= 0.0 {
+            return None;
+        }
+
+        let imbalance = |falling| (self.darkness_above(area, falling) - total / 2.0).abs();
+        Some(imbalance(true) <= imbalance(false))
+    }
+
+    /// Returns the darkness above one of the diagonals of `area` (as described by
+    /// `Half::falling`), going by where it crosses the middle of each column.
+    fn darkness_above(&self, area: Rectangle, falling: bool) -> f64 {
+        let mut sum = Sum::default();
+        for x in area.left.floor() as u32..area.right.ceil() as u32 {
+            let center = (x as f64 + 0.5).clamp(area.left, area.right);
+            let along = (center - area.left) / area.width();
+            let along = if falling { along } else { 1.0 - along };
+            let column = Rectangle {
+                bottom: area.top + along * area.height(),
+                ..area
+            };
+            sum.add(self.table.line(Axis::X, x, column));
+        }
+        sum.total
+    }
+
+    /// Works out where to split `node` in two, returning the axis, position and number of
+    /// rectangles in the first half, or `None` if there isn't enough darkness to split it.
+    fn binary_split(
+        &self,
+        node: Node,
+        previous: Option<(Axis, f64)>,
+    ) -> Option<(Axis, f64, usize)> {
+        let Node {
+            area,
+            rects,
+            depth,
+            hint,
+            ..
+        } = node;
+        let settings = self.settings;
+
+        // The target number of rectangles to be in the first half, making sure both halves get at
+        // least one.
+        let target_rects =
+            ((rects as f64 * settings.split_fraction).floor() as usize).clamp(1, rects - 1);
+        // The target amount of darkness in the first half. This is based on how much darkness is
+        // actually in this area rather than the average darkness per rectangle, so that any error
+        // in earlier splits doesn't keep building up.
+        let area_darkness = self.table.area(area);
+        let mut target_darkness = area_darkness * target_rects as f64 / rects as f64;
+
+        if settings.jitter != 0.0 {
+            let offset = random(
+                settings.seed,
+                &[
+                    depth as u64,
+                    area.left.to_bits(),
+                    area.top.to_bits(),
+                    area.right.to_bits(),
+                    area.bottom.to_bits(),
+                ],
+            ) * 2.0
+                - 1.0;
+            // Don't let it go past the end of the area, or the scan won't find a split.
+            target_darkness = f64::min(
+                target_darkness * (1.0 + settings.jitter.clamp(0.0, 1.0) * offset),
+                area_darkness,
+            );
+        }
+
+        let (axis, mut position) = self.choose_split(area, depth, target_darkness, hint)?;
+        if settings.seam_window > 0 {
+            position = self.seam(area, axis, position, target_darkness, area_darkness);
+        }
+
+        if let Some((previous_axis, previous_position)) = previous {
+            let (start, end) = match axis {
+                Axis::X => (area.left, area.right),
+                Axis::Y => (area.top, area.bottom),
+            };
+            if previous_axis == axis
+                && (position - previous_position).abs() < self.hysteresis
+                && previous_position > start
+                && previous_position < end
+            {
+                position = previous_position;
+            }
+        }
+
+        Some((axis, position, target_rects))
+    }
+
+    /// Looks for the lightest column or row along `axis` within `settings.seam_window` of
+    /// `position` to move a split of `area` to, as long as that leaves the darkness before it
+    /// within `settings.seam_max_imbalance` of `target_darkness`. Returns `position` unchanged if
+    /// there isn't one.
+    fn seam(
+        &self,
+        area: Rectangle,
+        axis: Axis,
+        position: f64,
+        target_darkness: f64,
+        area_darkness: f64,
+    ) -> f64 {
+        let settings = self.settings;
+        let (start, end) = match axis {
+            Axis::X => (area.left, area.right),
+            Axis::Y => (area.top, area.bottom),
+        };
+        let max_imbalance = settings.seam_max_imbalance.clamp(0.0, 1.0) * area_darkness;
+        let center = position.floor() as i64;
+        let window = settings.seam_window as i64;
+
+        // The best line so far, as its darkness, distance from `position` and position.
+        let mut best: Option<(f64, f64, f64)> = None;
+        for i in (center - window).max(start.floor() as i64)..=center + window {
+            // Only whole lines count, so that the split goes through the middle of one.
+            let (line_start, line_end) = (i as f64, (i + 1) as f64);
+            if line_start < start || line_end > end {
+                continue;
+            }
+            let middle = line_start + 0.5;
+            let (before, _) = area.split(axis, middle);
+            if (self.table.block(before) - target_darkness).abs() > max_imbalance {
+                continue;
+            }
+
+            let darkness = self.table.line(axis, i as u32, area);
+            let distance = (middle - position).abs();
+            if best.is_none_or(|(best_darkness, best_distance, _)| {
+                darkness < best_darkness || (darkness == best_darkness && distance < best_distance)
+            }) {
+                best = Some((darkness, distance, middle));
+            }
+        }
+
+        match best {
+            Some((_, _, middle)) => middle,
+            None => position,
+        }
+    }
+
+    /// Works out where to split `area` into quarters, returning the x and y positions of the
+    /// splits and how many of the `rects` rectangles go into the top-left, top-right,
+    /// bottom-left and bottom-right quarters, in proportion to their darkness.
+    fn quad_split(&self, area: Rectangle, rects: usize) -> Option<(f64, f64, [usize; 4])> {
+        let half = self.table.area(area) / 2.0;
+        let x = self.find_split(area, Axis::X, half, None)?;
+        let y = self.find_split(area, Axis::Y, half, None)?;
+
+        let (left, right) = area.split(Axis::X, x);
+        let (top_left, bottom_left) = left.split(Axis::Y, y);
+        let (top_right, bottom_right) = right.split(Axis::Y, y);
+        let darkness = [top_left, top_right, bottom_left, bottom_right]
+            .map(|quarter| self.table.area(quarter));
+        let total: f64 = darkness.iter().sum();
+        if total <= 0.0 {
+            return None;
+        }
+
+        // Give each quarter at least 1 rectangle, and then share out the rest by darkness,
+        // rounding down and giving what's left over to the quarters that lost the most.
+        let spare = rects - 4;
+        let exact = darkness.map(|darkness| spare as f64 * darkness / total);
+        let mut shares = exact.map(|exact| exact.floor() as usize);
+        let mut order = [0, 1, 2, 3];
+        order.sort_by(|&a, &b| {
+            let remainder = |i: usize| exact[i] - shares[i] as f64;
+            remainder(b).total_cmp(&remainder(a))
+        });
+        let left_over = spare - shares.iter().sum::<usize>();
+        for &i in order.iter().take(left_over) {
+            shares[i] += 1;
+        }
+
+        Some((x, y, shares.map(|share| share + 1)))
+    }
+
+    /// Picks which axis to split `area` along, for the strategies that don't need to scan first.
+    fn split_axis(&self, area: Rectangle, depth: u32) -> Axis {
+        match self.settings.split_strategy {
+            SplitStrategy::LongestSide
+            | SplitStrategy::Balanced
+            | SplitStrategy::Quad
+            | SplitStrategy::Diagonal => {
+                if area.width() > area.height() {
+                    Axis::X
+                } else {
+                    Axis::Y
+                }
+            }
+            SplitStrategy::Alternate => {
+                let min_size = f64::max(1.0, 2.0 * self.settings.min_rect_size);
+                // Not `is_multiple_of`, which needs Rust 1.87.
+                #[allow(clippy::manual_is_multiple_of)]
+                let (scheduled, extent, other) = if depth % 2 == 0 {
+                    (Axis::X, area.width(), Axis::Y)
+                } else {
+                    (Axis::Y, area.height(), Axis::X)
+                };
+
+                if extent < min_size {
+                    other
+                } else {
+                    scheduled
+                }
+            }
+            SplitStrategy::VerticalOnly => Axis::X,
+            SplitStrategy::HorizontalOnly => Axis::Y,
+        }
+    }
+
+    /// Picks the axis and position at which to split `area` so that the first half contains
+    /// `target_darkness`, or returns `None` if there isn't enough darkness in `area`.
+    fn choose_split(
+        &self,
+        area: Rectangle,
+        depth: u32,
+        target_darkness: f64,
+        hint: Option<(Axis, f64)>,
+    ) -> Option<(Axis, f64)> {
+        if self.settings.split_strategy != SplitStrategy::Balanced {
+            let axis = self.split_axis(area, depth);
+            return Some((axis, self.find_split(area, axis, target_darkness, hint)?));
+        }
+
+        // Try both, and go with whichever gives the least extreme rectangles.
+        let badness = |axis, split| {
+            let (first, second) = area.split(axis, split);
+            f64::max(first.aspect_ratio(), second.aspect_ratio())
+        };
+        let x = self
+            .find_split(area, Axis::X, target_darkness, hint)
+            .map(|split| (Axis::X, split));
+        let y = self
+            .find_split(area, Axis::Y, target_darkness, hint)
+            .map(|split| (Axis::Y, split));
+        match (x, y) {
+            (Some(x), Some(y)) => {
+                if badness(x.0, x.1) <= badness(y.0, y.1) {
+                    Some(x)
+                } else {
+                    Some(y)
+                }
+            }
+            (x, y) => x.or(y),
+        }
+    }
+
+    /// Scans across `area` along `axis` until it's found `target_darkness`, and returns the
+    /// position at which it was reached, or `None` if it never was.
+    ///
+    /// If `hint` is along `axis`, the scan starts from there instead, going backwards if the
+    /// target was already reached before it. That finds the same position, give or take rounding
+    /// error.
+    fn find_split(
+        &self,
+        area: Rectangle,
+        axis: Axis,
+        target_darkness: f64,
+        hint: Option<(Axis, f64)>,
+    ) -> Option<f64> {
+        let (start, end) = match axis {
+            Axis::X => (area.left, area.right),
+            Axis::Y => (area.top, area.bottom),
+        };
+        let (first, last) = (start.floor() as u32, end.ceil() as u32);
+
+        // The amount of darkness we've found so far, and the line to carry on from.
+        let mut darkness = Sum::default();
+        let mut from = first;
+        if let Some((_, hint)) = hint.filter(|&(hint_axis, _)| hint_axis == axis) {
+            from = (hint.floor().max(0.0) as u32).clamp(first, last);
+            let (before, _) = area.split(axis, (from as f64).clamp(start, end));
+            darkness.add(self.table.block(before));
+
+            if darkness.total >= target_darkness {
+                for i in (first..from).rev() {
+                    let line_darkness = self.table.line(axis, i, area);
+                    darkness.add(-line_darkness);
+                    if darkness.total < target_darkness {
+                        let overshoot = darkness.total + line_darkness - target_darkness;
+                        return Some((i + 1) as f64 - overshoot / line_darkness);
+                    }
+                }
+                return Some(start);
+            }
+        }
+
+        for i in from..last {
+            let line_darkness = self.table.line(axis, i, area);
+            darkness.add(line_darkness);
+
+            if darkness.total >= target_darkness {
+                if line_darkness == 0.0 {
+                    // The target must've already been reached at the start of this line, so split
+                    // there rather than dividing by 0.
+                    return Some(f64::max(i as f64, start));
+                }
+                let overshoot = darkness.total - target_darkness;
+                // Find the exact point of the split by taking away the amount we overshot.
+                return Some((i + 1) as f64 - overshoot / line_darkness);
+            }
+        }
+
+        None
+    }
+}
+
+/// Rectanglifies a sequence of similar images, like the frames of a video, while trying to keep
+/// the rectangles from jumping around between them.
+///
+/// Each frame is split up the same way as [`rectanglify`], except that splits which end up close
+/// to where they were in the previous frame are left where they were, and areas whose darkness
+/// has barely changed are split exactly the same way as last time without looking at them again.
+///
+/// With both `hysteresis` and `threshold` set to 0, this gives the same output as
+/// [`rectanglify`].
+///
+/// The biggest allocation (a summed-area table of the frame's darkness) is kept between frames,
+/// so it's only made again when the frames get bigger.
+#[derive(Debug, Clone, Default)]
+pub struct Rectanglifier {
+    /// How close (in input pixels) a split has to be to where it was in the previous frame for it
+    /// to be left there.
+    pub hysteresis: f64,
+    /// How much the darkness of an area has to change by, as a fraction of what it was in the
+    /// previous frame, for it to get split up again rather than reusing last time's splits.
+    pub threshold: f64,
+    /// How the previous frame was split up, if there was one: either the whole thing, or each of
+    /// red, green and blue with `Channels::Separate`. Any of them can be missing if there was
+    /// nothing to split.
+    previous: Vec<Option<FrameTree>>,
+    /// The memory for the darkness table, kept from the previous frame so that it only needs
+    /// allocating again if the frames get bigger.
+    buffers: TableBuffers,
+    /// The node lists from the frame before `previous`, ready to be filled in again.
+    spare_nodes: Vec<Vec<FrameTreeNode>>,
+    /// The lists of things to draw, kept empty between frames.
+    spare_ops: Vec<Vec<DrawOp>>,
+    /// The report on the last frame, if there's been one since it was created or reset.
+    report: Option<Report>,
+}
+
+impl Rectanglifier {
+    pub fn new(hysteresis: f64, threshold: f64) -> Self {
+        Rectanglifier {
+            hysteresis,
+            threshold,
+            previous: Vec::new(),
+            buffers: TableBuffers::default(),
+            spare_nodes: Vec::new(),
+            spare_ops: Vec::new(),
+            report: None,
+        }
+    }
+
+    /// Forgets about the previous frame, so that the next one gets split from scratch.
+    pub fn reset(&mut self) {
+        self.previous.clear();
+        self.report = None;
+    }
+
+    /// Returns a [`Report`] on how evenly the darkness was shared out between the rectangles in
+    /// the last frame, or `None` if there hasn't been one since this was created or reset.
+    pub fn report(&self) -> Option<Report> {
+        self.report
+    }
+
+    /// Rectanglifies the next frame, `input`, drawing the result onto `output`, and returns
+    /// whether it got through all of the splits before `settings.deadline`.
+    ///
+    /// Anything left unsplit because of the deadline gets split again in the next frame, rather
+    /// than being reused.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error without drawing anything (or forgetting the previous frame) if `settings`
+    /// is invalid.
+    pub fn rectanglify<I: GenericImageView, O: GenericImage>(
+        &mut self,
+        input: &I,
+        output: &mut O,
+        settings: Settings,
+    ) -> Result<Completion, SettingsError> {
+        settings.validate()?;
+        let separate = settings.channels == Channels::Separate && O::Pixel::CHANNEL_COUNT >= 3;
+        let passes = if separate { 3 } else { 1 };
+        let mut previous = mem::take(&mut self.previous);
+        // The previous frame is no use if it was split up in a different number of passes.
+        if previous.len() != passes {
+            previous.clear();
+        }
+
+        if settings.clear_output {
+            prepare_output(input, output, &settings);
+        }
+        let mut tracker = Tracker {
+            progress: Progress { done: 0, total: 0 },
+            callback: &mut |_| {},
+            heatmap: None,
+        };
+        let mut report: Option<Report> = None;
+        for pass in 0..passes {
+            let previous = previous.get(pass).and_then(Option::as_ref);
+            let (mut ops, pass_report) = if separate {
+                let settings = channel_settings(&settings, pass);
+                let view = ChannelView {
+                    image: input,
+                    channel: pass,
+                };
+                let (ops, report) = self.plan(&view, &settings, previous);
+                uncancelled(draw_channel(
+                    input,
+                    output,
+                    pass,
+                    &settings,
+                    &ops,
+                    &mut tracker,
+                    None,
+                ));
+                (ops, report)
+            } else {
+                let (ops, report) = self.plan(input, &settings, previous);
+                let output = &mut ImageRenderer::new(&mut *output, settings.clone());
+                uncancelled(draw_ops(input, output, &settings, &ops, &mut tracker, None));
+                (ops, report)
+            };
+            report = Some(match report {
+                Some(report) => report.and(pass_report),
+                None => pass_report,
+            });
+            ops.clear();
+            self.spare_ops.push(ops);
+        }
+        if settings.preserve_alpha {
+            copy_alpha(input, output, &settings);
+        }
+
+        for tree in previous.into_iter().flatten() {
+            self.spare_nodes.push(tree.nodes);
+        }
+        let report = report.unwrap();
+        self.report = Some(report);
+        Ok(report.completion)
+    }
+
+    /// Works out everything that needs to be drawn for one pass over the next frame, using
+    /// `previous` (the same pass over the previous frame) as a guide, and adds how it got split up
+    /// to `self.previous`.
+    fn plan(
+        &mut self,
+        input: &impl GenericImageView,
+        settings: &Settings,
+        previous: Option<&FrameTree>,
+    ) -> (Vec<DrawOp>, Report) {
+        let nodes = self.spare_nodes.pop().unwrap_or_default();
+        let ops = self.spare_ops.pop().unwrap_or_default();
+        let threshold = self.threshold;
+        let mut tree = None;
+        let (ops, report) = uncancelled(plan_with(
+            input,
+            None,
+            None,
+            settings,
+            self.hysteresis,
+            &mut self.buffers,
+            |splitter, root| {
+                let split = splitter.split_tree(root, previous, threshold, nodes);
+                let ops = split.ops(settings, ops);
+                tree = Some(split);
+                Ok(ops)
+            },
+        ));
+        self.previous.push(tree);
+        (ops, report)
+    }
+}
+
+/// A record of how a frame was split up, stored as a flat list of nodes with the root first.
+#[derive(Debug, Clone)]
+struct FrameTree {
+    nodes: Vec<FrameTreeNode>,
+}
+
+#[derive(Debug, Clone)]
+struct FrameTreeNode {
+    node: Node,
+    /// The total darkness of `node.area`.
+    darkness: f64,
+    /// The axis and position this was split at, and the indices of the two halves, or `None` if
+    /// it's a leaf.
+    split: Option<(Axis, f64, [usize; 2])>,
+    /// If this is a leaf that's cut along a diagonal, which one (as described by
+    /// `Half::falling`).
+    diagonal: Option<bool>,
+    /// Whether this or anything below it was left unsplit because the deadline ran out, in which
+    /// case it can't be reused as is for the next frame.
+    truncated: bool,
+}
+
+impl FrameTree {
+    /// Returns everything that needs to be drawn for this tree, in the same order as
+    /// `Splitter::split`, in place of whatever was in `ops` before.
+    fn ops(&self, settings: &Settings, mut ops: Vec<DrawOp>) -> Vec<DrawOp> {
+        enum Task {
+            Visit(usize),
+            Draw(DrawOp),
+        }
+
+        ops.clear();
+        let mut stack = vec![Task::Visit(0)];
+        while let Some(task) = stack.pop() {
+            let index = match task {
+                Task::Visit(index) => index,
+                Task::Draw(op) => {
+                    ops.push(op);
+                    continue;
+                }
+            };
+
+            let FrameTreeNode {
+                node,
+                darkness,
+                split,
+                diagonal,
+                ..
+            } = self.nodes[index];
+            let darkness = shade(darkness, node.area);
+            let shade = if settings.shade_lines { darkness } else { 1.0 };
+            match (split, diagonal) {
+                (None, Some(falling)) => ops.push(DrawOp::Diagonal {
+                    area: node.area,
+                    falling,
+                    odd: node.odd,
+                    darkness,
+                    shade,
+                    depth: node.depth,
+                }),
+                (None, None) => ops.push(DrawOp::Leaf {
+                    area: node.area,
+                    odd: node.odd,
+                    darkness,
+                    depth: node.depth,
+                    rects: node.rects,
+                    halves: None,
+                }),
+                (Some((axis, position, [first, second])), _) => {
+                    stack.push(Task::Draw(DrawOp::Line {
+                        axis,
+                        position,
+                        area: node.area,
+                        shade,
+                        depth: node.depth,
+                        ends: [None; 2],
+                        tone: None,
+                    }));
+                    stack.push(Task::Visit(second));
+                    stack.push(Task::Visit(first));
+                }
+            }
+        }
+
+        ops
+    }
+
+    /// Copies the subtree of `self` starting at `from` into `to`, so that it starts at index
+    /// `into`. `stack` is scratch space, which is left empty.
+    fn copy_subtree(
+        &self,
+        from: usize,
+        to: &mut FrameTree,
+        into: usize,
+        stack: &mut Vec<(usize, usize)>,
+    ) {
+        stack.push((from, into));
+        while let Some((from, into)) = stack.pop() {
+            let node = &self.nodes[from];
+            to.nodes[into] = node.clone();
+            if let Some((axis, position, children)) = node.split {
+                let start = to.nodes.len();
+                for &child in &children {
+                    to.nodes.push(self.nodes[child].clone());
+                }
+                to.nodes[into].split = Some((axis, position, [start, start + 1]));
+                stack.push((children[0], start));
+                stack.push((children[1], start + 1));
+            }
+        }
+    }
+}
+
+impl Splitter<'_> {
+    /// Splits up `root` the same way as `split`, but recording the result as a tree, and using
+    /// `previous` (the tree for the previous frame) as a guide.
+    ///
+    /// Each node is matched up with the node in the same position in `previous`. If it's got the
+    /// same area and number of rectangles, and its darkness has changed by less than `threshold`,
+    /// everything below it is copied across as is. Otherwise, it's split the normal way, but
+    /// passing the previous split position to `step`.
+    ///
+    /// The tree's nodes are stored in `nodes`, after clearing out whatever was there before.
+    fn split_tree(
+        &self,
+        root: Node,
+        previous: Option<&FrameTree>,
+        threshold: f64,
+        mut nodes: Vec<FrameTreeNode>,
+    ) -> FrameTree {
+        nodes.clear();
+        nodes.push(FrameTreeNode {
+            node: root,
+            darkness: 0.0,
+            split: None,
+            diagonal: None,
+            truncated: false,
+        });
+        let mut tree = FrameTree { nodes };
+        let previous_root = previous.map(|_| 0);
+        #[cfg(feature = "rayon")]
+        if self.settings.parallel {
+            self.grow_tree_parallel(&mut tree, 0, previous, previous_root, threshold);
+        } else {
+            self.grow_tree(&mut tree, 0, previous, previous_root, threshold);
+        }
+        #[cfg(not(feature = "rayon"))]
+        self.grow_tree(&mut tree, 0, previous, previous_root, threshold);
+
+        // Children always come after their parents, so going backwards passes each one on to
+        // its parent before the parent passes it on any further.
+        if self.truncated.load(Ordering::Relaxed) {
+            for index in (0..tree.nodes.len()).rev() {
+                if let Some((_, _, children)) = tree.nodes[index].split {
+                    tree.nodes[index].truncated =
+                        children.iter().any(|&child| tree.nodes[child].truncated);
+                }
+            }
+        }
+
+        tree
+    }
+
+    /// Fills in the subtree of `tree` starting at `index`, which is matched up with the node at
+    /// `previous_index` in `previous`, the same way as `split_tree`.
+    fn grow_tree(
+        &self,
+        tree: &mut FrameTree,
+        index: usize,
+        previous: Option<&FrameTree>,
+        previous_index: Option<usize>,
+        threshold: f64,
+    ) {
+        let mut stack = vec![(index, previous_index)];
+        // Shared between all the subtrees copied from `previous`, so that it's only allocated once.
+        let mut copy_stack = Vec::new();
+        let mut splits = 0;
+        while let Some((index, previous_index)) = stack.pop() {
+            let children = self.grow_node(
+                tree,
+                index,
+                previous.zip(previous_index),
+                threshold,
+                &mut copy_stack,
+                &mut splits,
+            );
+            if let Some([first, second]) = children {
+                stack.push(second);
+                stack.push(first);
+            }
+        }
+    }
+
+    /// The same as `grow_tree`, but filling in the two halves of large nodes on different
+    /// threads. The result is laid out differently, but describes exactly the same splits.
+    #[cfg(feature = "rayon")]
+    fn grow_tree_parallel(
+        &self,
+        tree: &mut FrameTree,
+        index: usize,
+        previous: Option<&FrameTree>,
+        previous_index: Option<usize>,
+        threshold: f64,
+    ) {
+        // Past a certain depth, fall back to `grow_tree` so this can't overflow the stack either.
+        let node = tree.nodes[index].node;
+        if node.rects < PARALLEL_THRESHOLD || node.depth >= PARALLEL_MAX_DEPTH {
+            return self.grow_tree(tree, index, previous, previous_index, threshold);
+        }
+
+        // There aren't many of these, so it's fine to check the time for every one.
+        let children = self.grow_node(
+            tree,
+            index,
+            previous.zip(previous_index),
+            threshold,
+            &mut Vec::new(),
+            &mut 0,
+        );
+        let [(first, previous_first), (second, previous_second)] = match children {
+            Some(children) => children,
+            None => return,
+        };
+        // Each half gets grown as a tree of its own, and then copied into place.
+        let grow = |index: usize, previous_index| {
+            let mut subtree = FrameTree {
+                nodes: vec![tree.nodes[index].clone()],
+            };
+            self.grow_tree_parallel(&mut subtree, 0, previous, previous_index, threshold);
+            subtree
+        };
+        let (first_tree, second_tree) = rayon::join(
+            || grow(first, previous_first),
+            || grow(second, previous_second),
+        );
+        let mut stack = Vec::new();
+        first_tree.copy_subtree(0, tree, first, &mut stack);
+        second_tree.copy_subtree(0, tree, second, &mut stack);
+    }
+
+    /// Fills in the node of `tree` at `index`, which is matched up with the node at the given
+    /// index in the previous frame's tree (if there is one), by either copying across everything below the previous
+    /// node or splitting it. `copy_stack` is scratch space for copying, and `splits` is the
+    /// number of splits done so far, for `out_of_time`.
+    ///
+    /// If it got split, this returns the indices of its two halves in `tree`, each with the
+    /// matching index in `previous`, which still need filling in.
+    fn grow_node(
+        &self,
+        tree: &mut FrameTree,
+        index: usize,
+        previous: Option<(&FrameTree, usize)>,
+        threshold: f64,
+        copy_stack: &mut Vec<(usize, usize)>,
+        splits: &mut usize,
+    ) -> Option<[(usize, Option<usize>); 2]> {
+        let node = tree.nodes[index].node;
+        let darkness = self.table.area(node.area);
+        tree.nodes[index].darkness = darkness;
+
+        let previous_node =
+            previous.map(|(previous, previous_index)| &previous.nodes[previous_index]);
+        if let (Some((previous, previous_index)), Some(previous_node)) = (previous, previous_node) {
+            if previous_node.node.area == node.area
+                && previous_node.node.rects == node.rects
+                && previous_node.node.odd == node.odd
+                && !previous_node.truncated
+                && (darkness - previous_node.darkness).abs() < threshold * previous_node.darkness
+            {
+                previous.copy_subtree(previous_index, tree, index, copy_stack);
+                return None;
+            }
+        }
+
+        if self.out_of_time(*splits) {
+            tree.nodes[index].truncated = true;
+            return None;
+        }
+        *splits += 1;
+
+        let previous_split = previous_node.and_then(|node| node.split);
+        let step = self.step(
+            node,
+            previous_split.map(|(axis, position, _)| (axis, position)),
+        );
+        match step {
+            Step::Leaf => None,
+            Step::Split {
+                axis,
+                position,
+                children: [first, second],
+            } => {
+                let start = tree.nodes.len();
+                for child in [first, second] {
+                    tree.nodes.push(FrameTreeNode {
+                        node: child,
+                        darkness: 0.0,
+                        split: None,
+                        diagonal: None,
+                        truncated: false,
+                    });
+                }
+                tree.nodes[index].split = Some((axis, position, [start, start + 1]));
+
+                let previous_children = previous_split.map(|(_, _, children)| children);
+                Some([
+                    (start, previous_children.map(|children| children[0])),
+                    (start + 1, previous_children.map(|children| children[1])),
+                ])
+            }
+            Step::Diagonal { falling } => {
+                tree.nodes[index].diagonal = Some(falling);
+                None
+            }
+        }
+    }
+}
+
+/// An image backed by a raw buffer whose rows may be padded, like a video frame.
+///
+/// `ImageBuffer` requires rows to be tightly packed, which isn't the case for most capture APIs:
+/// they round each row up to some alignment, so the start of row `y` is at `y * stride` rather
+/// than `y * width * channels`. The last row doesn't need to be padded.
+pub struct StridedImage<P: Pixel, C> {
+    width: u32,
+    height: u32,
+    /// The distance between the start of one row and the next, in subpixels.
+    stride: usize,
+    data: C,
+    _pixel: PhantomData<P>,
+}
+
+impl<P: Pixel, C: Deref<Target = [P::Subpixel]>> StridedImage<P, C> {
+    /// Wraps `data`, returning `None` if the stride is too small to fit a row or `data` is too
+    /// small to fit the image.
+    pub fn from_raw(width: u32, height: u32, stride: usize, data: C) -> Option<Self> {
+        let row_len = width as usize * P::CHANNEL_COUNT as usize;
+        if stride < row_len {
+            return None;
+        }
+        let len = match height {
+            0 => 0,
+            _ => stride
+                .checked_mul(height as usize - 1)?
+                .checked_add(row_len)?,
+        };
+        if data.len() < len {
+            return None;
+        }
+
+        Some(StridedImage {
+            width,
+            height,
+            stride,
+            data,
+            _pixel: PhantomData,
+        })
+    }
+
+    pub fn stride(&self) -> usize {
+        self.stride
+    }
+
+    /// Returns the subpixels of row `y`, without any padding.
+    ///
+    /// Panics if `y` is out of bounds.
+    pub fn row(&self, y: u32) -> &[P::Subpixel] {
+        assert!(y < self.height, "row {} out of bounds", y);
+        let start = y as usize * self.stride;
+        &self.data[start..start + self.width as usize * P::CHANNEL_COUNT as usize]
+    }
+
+    pub fn into_raw(self) -> C {
+        self.data
+    }
+}
+
+impl<P: Pixel, C: DerefMut<Target = [P::Subpixel]>> StridedImage<P, C> {
+    /// Returns the subpixels of row `y` mutably, without any padding.
+    ///
+    /// Panics if `y` is out of bounds.
+    pub fn row_mut(&mut self, y: u32) -> &mut [P::Subpixel] {
+        assert!(y < self.height, "row {} out of bounds", y);
+        let start = y as usize * self.stride;
+        &mut self.data[start..start + self.width as usize * P::CHANNEL_COUNT as usize]
+    }
+
+    fn pixel_mut(&mut self, x: u32, y: u32) -> &mut P {
+        assert!(
+            self.in_bounds(x, y),
+            "pixel ({}, {}) out of bounds of {}x{} image",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        let channels = P::CHANNEL_COUNT as usize;
+        let start = x as usize * channels;
+        P::from_slice_mut(&mut self.row_mut(y)[start..start + channels])
+    }
+}
+
+impl<P: Pixel, C: Deref<Target = [P::Subpixel]>> GenericImageView for StridedImage<P, C> {
+    type Pixel = P;
+
+    fn dimensions(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    fn bounds(&self) -> (u32, u32, u32, u32) {
+        (0, 0, self.width, self.height)
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> P {
+        assert!(
+            self.in_bounds(x, y),
+            "pixel ({}, {}) out of bounds of {}x{} image",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        let channels = P::CHANNEL_COUNT as usize;
+        let start = x as usize * channels;
+        *P::from_slice(&self.row(y)[start..start + channels])
+    }
+}
+
+impl<P: Pixel, C: DerefMut<Target = [P::Subpixel]>> GenericImage for StridedImage<P, C> {
+    fn get_pixel_mut(&mut self, x: u32, y: u32) -> &mut P {
+        self.pixel_mut(x, y)
+    }
+
+    fn put_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        *self.pixel_mut(x, y) = pixel;
+    }
+
+    fn blend_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        self.pixel_mut(x, y).blend(&pixel);
+    }
+
+    fn copy_within(&mut self, source: Rect, x: u32, y: u32) -> bool {
+        let Rect {
+            x: source_x,
+            y: source_y,
+            width,
+            height,
+        } = source;
+        assert!(source_x < self.width && x < self.width);
+        assert!(source_y < self.height && y < self.height);
+        if self.width - x.max(source_x) < width || self.height - y.max(source_y) < height {
+            return false;
+        }
+
+        // Copy the rows in whichever order doesn't overwrite any that are yet to be copied.
+        let channels = P::CHANNEL_COUNT as usize;
+        let len = width as usize * channels;
+        let mut copy_row = |row: u32| {
+            let start = (source_y + row) as usize * self.stride + source_x as usize * channels;
+            let dest = (y + row) as usize * self.stride + x as usize * channels;
+            self.data.copy_within(start..start + len, dest);
+        };
+        if source_y < y {
+            (0..height).rev().for_each(&mut copy_row);
+        } else {
+            (0..height).for_each(&mut copy_row);
+        }
+        true
+    }
+}
+
+/// A read-only view of one of the red, green or blue channels of an image, along with its alpha.
+struct ChannelView<I> {
+    image: I,
+    /// 0 for red, 1 for green or 2 for blue.
+    channel: usize,
+}
+
+impl<I: Deref> GenericImageView for ChannelView<I>
+where
+    I::Target: GenericImageView,
+{
+    type Pixel = LumaA<<<I::Target as GenericImageView>::Pixel as Pixel>::Subpixel>;
+
+    fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    fn bounds(&self) -> (u32, u32, u32, u32) {
+        self.image.bounds()
+    }
+
+    fn get_pixel(&self, x: u32, y: u32) -> Self::Pixel {
+        let rgba = self.image.get_pixel(x, y).to_rgba();
+        LumaA([rgba[self.channel], rgba[3]])
     }
 }
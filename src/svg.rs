@@ -0,0 +1,109 @@
+//! Writing the rectangle layout out as an SVG instead of rasterising it.
+
+use std::io;
+use std::io::Write;
+
+use image::GenericImageView;
+
+use crate::rects::rectanglify_lines;
+use crate::rects::region;
+use crate::rects::Color;
+use crate::rects::Line;
+use crate::rects::LineStyle;
+use crate::rects::Settings;
+
+/// Formats `color` as an SVG paint and opacity, without rounding it to 8 bits.
+fn paint(color: Color) -> (String, f64) {
+    (
+        format!(
+            "rgb({}%, {}%, {}%)",
+            color.r * 100.0,
+            color.g * 100.0,
+            color.b * 100.0
+        ),
+        color.a,
+    )
+}
+
+/// Shortens `line` at each end that runs into another line (or the border, if `border` is set)
+/// so that it stops at the edge of that line's stroke, instead of going over it twice; that'd
+/// show up as a darker patch wherever the lines aren't fully opaque.
+///
+/// Returns `None` if there's nothing left of it.
+fn trim(line: Line, stroke_width: f64, border: bool) -> Option<Line> {
+    let [start, end] = line.ends.map(|end| match end {
+        Some(_) => stroke_width / 2.0,
+        None if border => stroke_width,
+        None => 0.0,
+    });
+    line.trimmed(start, end)
+}
+
+/// Rectanglifies `input`, writing the result to `writer` as an SVG document.
+///
+/// The document's `viewBox` is the size of `input`, so all the coordinates are in input pixels,
+/// and the lines are drawn `stroke_width` pixels wide. The lengths in `settings.line_style` are
+/// treated as input pixels too.
+///
+/// Only the background and dividing lines are written; `settings.fill_mode` is ignored. Lines
+/// stop at the edge of the lines they run into, rather than overlapping them.
+pub fn write_svg(
+    writer: &mut impl Write,
+    input: &impl GenericImageView,
+    settings: Settings,
+    stroke_width: f64,
+) -> io::Result<()> {
+    let settings = settings.sanitized();
+    let (width, height) = input.dimensions();
+    let (background, mut background_opacity) = paint(settings.background_color);
+    if settings.transparent_background {
+        background_opacity = 0.0;
+    }
+    let (line, line_opacity) = paint(settings.line_color);
+
+    writeln!(
+        writer,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{width}" height="{height}" viewBox="0 0 {width} {height}">"#
+    )?;
+    writeln!(
+        writer,
+        r#"<rect width="{width}" height="{height}" fill="{background}" fill-opacity="{background_opacity}"/>"#
+    )?;
+    let dashes = match settings.line_style {
+        LineStyle::Solid => String::new(),
+        LineStyle::Dashed { dash, gap } => format!(r#" stroke-dasharray="{dash} {gap}""#),
+        LineStyle::Dotted { gap } => format!(r#" stroke-dasharray="{stroke_width} {gap}""#),
+    };
+    writeln!(
+        writer,
+        r#"<g stroke="{line}" stroke-opacity="{line_opacity}" stroke-width="{stroke_width}"{dashes}>"#
+    )?;
+    let area = region(input, &settings);
+    let border = settings.border && !settings.wrap;
+    if border && !area.is_empty() {
+        // Strokes are centred on the edge of the shape, so inset it to keep the border inside.
+        let inset = stroke_width / 2.0;
+        writeln!(
+            writer,
+            r#"<rect x="{}" y="{}" width="{}" height="{}" fill="none"/>"#,
+            area.left + inset,
+            area.top + inset,
+            area.width() - stroke_width,
+            area.height() - stroke_width
+        )?;
+    }
+    let lines = rectanglify_lines(input, settings)
+        .into_iter()
+        .filter_map(|line| trim(line, stroke_width, border));
+    for line in lines {
+        writeln!(
+            writer,
+            r#"<line x1="{}" y1="{}" x2="{}" y2="{}"/>"#,
+            line.start.0, line.start.1, line.end.0, line.end.1
+        )?;
+    }
+    writeln!(writer, "</g>")?;
+    writeln!(writer, "</svg>")?;
+
+    Ok(())
+}
@@ -0,0 +1,156 @@
+//! Writing the rectangle layout out as text, with box-drawing characters, instead of rasterising
+//! it.
+
+use image::GenericImageView;
+
+use crate::rects::rectanglify_lines;
+use crate::rects::region;
+use crate::rects::Settings;
+
+/// Which characters to draw the lines with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// Unicode box-drawing characters, like `─`, `│`, `┌` and `┼`.
+    BoxDrawing,
+    /// Plain ASCII: `-`, `|`, and `+` wherever lines meet or turn a corner.
+    Ascii,
+}
+
+/// Which directions the lines through a character go in.
+#[derive(Debug, Clone, Copy, Default)]
+struct Arms {
+    up: bool,
+    down: bool,
+    left: bool,
+    right: bool,
+}
+
+impl Arms {
+    fn to_char(self, charset: Charset) -> char {
+        let Arms {
+            up,
+            down,
+            left,
+            right,
+        } = self;
+        let vertical = up || down;
+        let horizontal = left || right;
+        if charset == Charset::Ascii {
+            return match (vertical, horizontal) {
+                (false, false) => ' ',
+                (true, false) => '|',
+                (false, true) => '-',
+                (true, true) => '+',
+            };
+        }
+
+        match (up, down, left, right) {
+            (false, false, false, false) => ' ',
+            (_, _, false, false) => '│',
+            (false, false, _, _) => '─',
+            (false, true, false, true) => '┌',
+            (false, true, true, false) => '┐',
+            (true, false, false, true) => '└',
+            (true, false, true, false) => '┘',
+            (true, true, false, true) => '├',
+            (true, true, true, false) => '┤',
+            (false, true, true, true) => '┬',
+            (true, false, true, true) => '┴',
+            (true, true, true, true) => '┼',
+        }
+    }
+}
+
+/// Rectanglifies `input`, returning the result as text `columns` characters wide and `rows`
+/// lines tall, with a newline at the end of each line.
+///
+/// The layout (of `settings.roi`, if it's set) is stretched to fit the whole grid, with its
+/// outline along the edges, and each dividing line is moved to the nearest row or column of
+/// characters. Where lines meet, the characters join them up properly, so a line ending on
+/// another one gets a `├`, `┬` and so on. Rectangles narrower than a character can end up with
+/// their lines on top of each other.
+///
+/// Only the lines are written; `settings.fill_mode` and everything to do with colours and line
+/// styles is ignored. Diagonals are drawn with `╲` and `╱` (or `\` and `/`), but only in the
+/// spaces between the other lines.
+///
+/// # Panics
+///
+/// Panics if `columns` or `rows` is less than 2.
+pub fn rectanglify_text(
+    input: &impl GenericImageView,
+    settings: Settings,
+    (columns, rows): (usize, usize),
+    charset: Charset,
+) -> String {
+    let settings = settings.sanitized();
+    assert!(
+        columns >= 2 && rows >= 2,
+        "text must be at least 2x2 characters, got {}x{}",
+        columns,
+        rows
+    );
+
+    let area = region(input, &settings);
+    let column = |x: f64| ((x - area.left) / area.width() * (columns - 1) as f64).round() as usize;
+    let row = |y: f64| ((y - area.top) / area.height() * (rows - 1) as f64).round() as usize;
+
+    let mut arms = vec![Arms::default(); columns * rows];
+    let mut diagonals = vec![None; columns * rows];
+    let horizontal = |arms: &mut [Arms], row: usize, start: usize, end: usize| {
+        for column in start..end {
+            arms[row * columns + column].right = true;
+            arms[row * columns + column + 1].left = true;
+        }
+    };
+    let vertical = |arms: &mut [Arms], column: usize, start: usize, end: usize| {
+        for row in start..end {
+            arms[row * columns + column].down = true;
+            arms[(row + 1) * columns + column].up = true;
+        }
+    };
+
+    horizontal(&mut arms, 0, 0, columns - 1);
+    horizontal(&mut arms, rows - 1, 0, columns - 1);
+    vertical(&mut arms, 0, 0, rows - 1);
+    vertical(&mut arms, columns - 1, 0, rows - 1);
+
+    for line in rectanglify_lines(input, settings) {
+        let (start_x, start_y) = (column(line.start.0), row(line.start.1));
+        let (end_x, end_y) = (column(line.end.0), row(line.end.1));
+        if line.start.0 == line.end.0 {
+            vertical(&mut arms, start_x, start_y, end_y);
+        } else if line.start.1 == line.end.1 {
+            horizontal(&mut arms, start_y, start_x, end_x);
+        } else {
+            // Diagonals always go from left to right, so falling ones go downwards.
+            let falling = start_y < end_y;
+            let (top, bottom) = (start_y.min(end_y), start_y.max(end_y));
+            for y in top + 1..bottom {
+                let progress = (y - top) as f64 / (bottom - top) as f64;
+                let progress = if falling { progress } else { 1.0 - progress };
+                let x = (start_x as f64 + progress * (end_x - start_x) as f64).round() as usize;
+                diagonals[y * columns + x] = Some(falling);
+            }
+        }
+    }
+
+    let mut text = String::with_capacity((columns + 1) * rows);
+    for y in 0..rows {
+        for x in 0..columns {
+            let i = y * columns + x;
+            let c = match (arms[i].to_char(charset), diagonals[i]) {
+                (' ', Some(falling)) => match (charset, falling) {
+                    (Charset::BoxDrawing, true) => '╲',
+                    (Charset::BoxDrawing, false) => '╱',
+                    (Charset::Ascii, true) => '\\',
+                    (Charset::Ascii, false) => '/',
+                },
+                (c, _) => c,
+            };
+            text.push(c);
+        }
+        text.push('\n');
+    }
+    text
+}
@@ -0,0 +1,36 @@
+use image::{imageops, GrayImage, Luma};
+use rectanglify::rects::{rectanglify_tree, Settings, SplitTree, TreeNode};
+
+/// Returns the number of leaves in `tree`, and where its first split is.
+fn summary(tree: &SplitTree) -> (usize, f64) {
+    let leaves = tree
+        .nodes
+        .iter()
+        .filter(|node| matches!(node, TreeNode::Leaf { .. }))
+        .count();
+    match tree.nodes.last() {
+        Some(&TreeNode::Split { position, .. }) => (leaves, position),
+        root => panic!("the root is {:?}", root),
+    }
+}
+
+/// Adding up the rows in the opposite order gives exactly the same splits, since the rounding
+/// error from each addition is compensated for rather than depending on what came before.
+#[test]
+fn row_order_doesnt_matter() {
+    // Noise, so that every addition has some rounding error.
+    let mut state = 12345u32;
+    let input = GrayImage::from_fn(3000, 1000, |_, _| {
+        state ^= state << 13;
+        state ^= state >> 17;
+        state ^= state << 5;
+        Luma([(state >> 24) as u8])
+    });
+    let flipped = imageops::flip_vertical(&input);
+
+    // The image is wide enough that the first split is across its width, which flipping it
+    // vertically doesn't move.
+    let expected = summary(&rectanglify_tree(&input, Settings::default()));
+    let actual = summary(&rectanglify_tree(&flipped, Settings::default()));
+    assert_eq!(actual, expected);
+}
@@ -0,0 +1,175 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use image::{GrayImage, Luma, Rgb, RgbImage};
+use rectanglify::rects::{
+    rectanglify, Axis, Color, DrawCommand, ImageRenderer, LineStyle, Rectangle, Rectanglifier,
+    Renderer, Settings,
+};
+
+/// Counts the allocations made on each thread, so that tests running at the same time don't
+/// throw each other off.
+struct Counting;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    /// The total size of those allocations, in bytes.
+    static BYTES: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|count| count.set(count.get() + 1));
+        BYTES.with(|bytes| bytes.set(bytes.get() + layout.size()));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+/// Returns how many allocations `f` makes.
+fn allocations(f: impl FnOnce()) -> usize {
+    let before = ALLOCATIONS.with(Cell::get);
+    f();
+    ALLOCATIONS.with(Cell::get) - before
+}
+
+/// Returns how many bytes `f` allocates in total.
+fn bytes_allocated(f: impl FnOnce()) -> usize {
+    let before = BYTES.with(Cell::get);
+    f();
+    BYTES.with(Cell::get) - before
+}
+
+fn commands() -> Vec<DrawCommand> {
+    let area = Rectangle {
+        left: 0.1,
+        top: 0.15,
+        right: 0.8,
+        bottom: 0.9,
+    };
+    let color = Color::rgb(0.2, 0.4, 0.6);
+    let mut commands = vec![DrawCommand::Clear(Color::WHITE)];
+    for i in 0..100 {
+        let x = i as f64 / 100.0;
+        commands.extend([
+            DrawCommand::VLine {
+                x,
+                area,
+                color,
+                thickness: i % 5,
+            },
+            DrawCommand::HLine {
+                y: x,
+                area,
+                color: Color { a: 0.5, ..color },
+                thickness: i % 5,
+            },
+            DrawCommand::Fill {
+                area,
+                color,
+                radius: (i % 3) as f64 * 4.0,
+            },
+            DrawCommand::FillGradient {
+                area,
+                axis: Axis::Y,
+                colors: [color, Color::BLACK],
+                radius: 0.0,
+            },
+            DrawCommand::FillTriangle {
+                area,
+                falling: i % 2 == 0,
+                above: i % 3 == 0,
+                color,
+            },
+            DrawCommand::Ellipse {
+                area,
+                filled: i % 2 == 0,
+                color,
+                thickness: 3,
+            },
+            DrawCommand::Diagonal {
+                area,
+                falling: i % 2 == 0,
+                color,
+                thickness: 2,
+            },
+            DrawCommand::Border {
+                area,
+                color,
+                thickness: 3,
+            },
+        ]);
+    }
+    commands
+}
+
+#[test]
+fn drawing_does_not_allocate() {
+    for (antialias, line_style) in [
+        (false, LineStyle::Solid),
+        (true, LineStyle::Solid),
+        (
+            false,
+            LineStyle::Dashed {
+                dash: 4.0,
+                gap: 2.0,
+            },
+        ),
+        (true, LineStyle::Dotted { gap: 3.0 }),
+    ] {
+        let settings = Settings {
+            antialias,
+            line_style,
+            ..Settings::default()
+        };
+        let mut image = RgbImage::from_pixel(64, 48, Rgb([255, 255, 255]));
+        let mut renderer = ImageRenderer::new(&mut image, settings);
+        let commands = commands();
+        let count = allocations(|| {
+            for command in commands {
+                renderer.draw(command);
+            }
+        });
+        assert_eq!(count, 0, "with {:?} and {:?}", antialias, line_style);
+    }
+}
+
+/// Once a `Rectanglifier` has seen a couple of frames, it has all the memory it needs for more
+/// of the same size, and only makes a handful of small allocations for each one (the biggest
+/// being a row's worth of scratch space).
+#[test]
+fn rectanglifier_reuses_memory() {
+    // The size of the darkness table for each frame.
+    const TABLE: usize = 321 * 241 * 8;
+
+    let frames: Vec<GrayImage> = (0..6)
+        .map(|t| GrayImage::from_fn(320, 240, |x, y| Luma([((x + y * 2 + t * 3) % 256) as u8])))
+        .collect();
+    let mut output = GrayImage::new(320, 240);
+    let fresh = bytes_allocated(|| rectanglify(&frames[0], &mut output, Settings::default()));
+
+    for (hysteresis, threshold) in [(0.0, 0.0), (2.0, 0.1)] {
+        let mut rectanglifier = Rectanglifier::new(hysteresis, threshold);
+        for (i, frame) in frames.iter().enumerate() {
+            let mut count = 0;
+            let bytes = bytes_allocated(|| {
+                count = allocations(|| {
+                    rectanglifier
+                        .rectanglify(frame, &mut output, Settings::default())
+                        .unwrap();
+                });
+            });
+            if i >= 2 {
+                assert!(bytes < TABLE / 10, "frame {} allocated {} bytes", i, bytes);
+                assert!(count < 32, "frame {} made {} allocations", i, count);
+            }
+        }
+    }
+    assert!(fresh > TABLE);
+}
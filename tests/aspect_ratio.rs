@@ -0,0 +1,53 @@
+use image::{GrayImage, Luma};
+use rectanglify::rects::{rectanglify_rects, RectBudget, Settings};
+
+/// A single small dark dot on white, which all the splits crowd around.
+fn dot() -> GrayImage {
+    GrayImage::from_fn(128, 96, |x, y| {
+        let (dx, dy) = (x as i32 - 40, y as i32 - 30);
+        Luma([if dx * dx + dy * dy <= 9 { 0 } else { 255 }])
+    })
+}
+
+fn worst_aspect_ratio(input: &GrayImage, settings: Settings) -> f64 {
+    rectanglify_rects(input, settings)
+        .iter()
+        .map(|rect| {
+            assert!(
+                rect.left >= 0.0 && rect.top >= 0.0 && rect.right <= 128.0 && rect.bottom <= 96.0,
+                "{:?} is outside the image",
+                rect
+            );
+            (rect.width() / rect.height()).max(rect.height() / rect.width())
+        })
+        .fold(0.0, f64::max)
+}
+
+#[test]
+fn dot_gives_no_slivers() {
+    let input = dot();
+    for budget in [RectBudget::Count(50), RectBudget::Count(200)] {
+        // Without a limit, this is what goes wrong.
+        let settings = Settings {
+            budget,
+            ..Settings::default()
+        };
+        assert!(worst_aspect_ratio(&input, settings) > 100.0);
+
+        for max_aspect_ratio in [2.0, 4.0] {
+            let settings = Settings {
+                budget,
+                max_aspect_ratio: Some(max_aspect_ratio),
+                ..Settings::default()
+            };
+            let worst = worst_aspect_ratio(&input, settings);
+            assert!(
+                worst <= max_aspect_ratio + 1e-9,
+                "{:?} with a limit of {} gave {}",
+                budget,
+                max_aspect_ratio,
+                worst
+            );
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use image::{Rgb, RgbImage};
+use rectanglify::rects::{rectanglify, Brightness, Settings};
+use std::sync::Arc;
+
+#[test]
+fn custom_brightness_can_capture() {
+    // Red is bright and everything else is dark, so only the blue half gets split.
+    let cutoff = 0.5;
+    let settings = Settings {
+        brightness: Brightness::Custom(Arc::new(
+            move |color| {
+                if color.r > cutoff {
+                    1.0
+                } else {
+                    0.0
+                }
+            },
+        )),
+        ..Settings::default()
+    };
+    let input = RgbImage::from_fn(64, 32, |x, _| match x < 32 {
+        true => Rgb([255, 0, 0]),
+        false => Rgb([0, 0, 255]),
+    });
+    let mut output = RgbImage::new(64, 32);
+    rectanglify(&input, &mut output, settings.clone());
+
+    let white = Rgb([255, 255, 255]);
+    for (x, _, pixel) in output.enumerate_pixels() {
+        if x < 31 {
+            assert_eq!(*pixel, white);
+        }
+    }
+    assert!(output
+        .enumerate_pixels()
+        .any(|(x, _, &pixel)| x > 32 && pixel != white));
+    assert!(format!("{:?}", settings).contains("Custom(..)"));
+}
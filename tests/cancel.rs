@@ -0,0 +1,50 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+
+use image::{GrayImage, Luma};
+use rectanglify::rects::{rectanglify_with, Options, Progress, RectanglifyError, Settings};
+
+#[test]
+fn cancel_from_another_thread() {
+    let input = GrayImage::from_fn(256, 256, |x, y| Luma([(x ^ y) as u8]));
+    let mut output = GrayImage::new(256, 256);
+    let cancel = Arc::new(AtomicBool::new(false));
+
+    // Cancels as soon as it hears that drawing has started.
+    let (started, wait) = mpsc::channel();
+    let canceller = {
+        let cancel = Arc::clone(&cancel);
+        thread::spawn(move || {
+            wait.recv().unwrap();
+            cancel.store(true, Ordering::SeqCst);
+        })
+    };
+
+    let mut drawn = 0;
+    let mut drawn_after = 0;
+    let mut progress = |_: Progress| {
+        if drawn == 0 {
+            started.send(()).unwrap();
+            // Hold on until it's gone through, so that it's definitely cancelled part-way.
+            while !cancel.load(Ordering::SeqCst) {
+                thread::yield_now();
+            }
+        }
+        drawn += 1;
+        if cancel.load(Ordering::SeqCst) {
+            drawn_after += 1;
+        }
+    };
+    let options = Options {
+        progress: Some(&mut progress),
+        cancel: Some(&cancel),
+        ..Options::default()
+    };
+    let result = rectanglify_with(&input, &mut output, Settings::default(), options);
+    canceller.join().unwrap();
+
+    assert!(matches!(result, Err(RectanglifyError::Cancelled)));
+    // Only the rectangle it was in the middle of gets finished off.
+    assert_eq!(drawn_after, 1);
+}
@@ -0,0 +1,153 @@
+//! Fixture plumbing shared between the integration tests.
+//!
+//! Inputs live in `tests/fixtures/inputs`, and the outputs they're expected to give live in
+//! `tests/fixtures/expected`, named after the test case. Running the tests with
+//! `--features regen-fixtures` overwrites the expected outputs with whatever they give now
+//! instead of comparing against them, so check the new images over before committing them.
+
+// Each test target only uses some of this.
+#![allow(dead_code)]
+
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+use image::{DynamicImage, Rgba, RgbaImage};
+
+/// How far an output can be from the expected one before it counts as different.
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance {
+    /// The most any channel of a pixel can be off by before that pixel counts as different.
+    pub channel: u8,
+    /// The most pixels that can be different.
+    pub pixels: usize,
+}
+
+impl Tolerance {
+    pub const EXACT: Tolerance = Tolerance {
+        channel: 0,
+        pixels: 0,
+    };
+}
+
+fn fixtures() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures")
+}
+
+/// Loads the input fixture called `name`.
+pub fn input(name: &str) -> DynamicImage {
+    let path = fixtures().join("inputs").join(name);
+    image::open(&path).unwrap_or_else(|err| panic!("failed to open {}: {}", path.display(), err))
+}
+
+/// Checks that `actual` is within `tolerance` of the expected output for `case`, panicking with
+/// a list of the differences if it isn't.
+///
+/// On failure, an image with the expected output, the actual output, and the pixels that are
+/// different highlighted in red from left to right is written to `target/`, and the panic
+/// message says where.
+pub fn assert_matches(case: &str, actual: impl Into<DynamicImage>, tolerance: Tolerance) {
+    let actual = actual.into();
+    let path = fixtures().join("expected").join(format!("{}.png", case));
+    if cfg!(feature = "regen-fixtures") {
+        actual
+            .save(&path)
+            .unwrap_or_else(|err| panic!("failed to write {}: {}", path.display(), err));
+        return;
+    }
+
+    let expected = image::open(&path)
+        .unwrap_or_else(|err| {
+            panic!(
+                "failed to open {} (run with --features regen-fixtures to create it): {}",
+                path.display(),
+                err
+            )
+        })
+        .to_rgba8();
+    let actual = actual.to_rgba8();
+    assert_eq!(
+        expected.dimensions(),
+        actual.dimensions(),
+        "{} came out the wrong size",
+        case
+    );
+
+    let mut report = String::new();
+    let mut different = 0;
+    let mut worst = 0;
+    for (x, y, expected_pixel) in expected.enumerate_pixels() {
+        let actual_pixel = actual.get_pixel(x, y);
+        let off = channel_difference(expected_pixel, actual_pixel);
+        worst = worst.max(off);
+        if off > tolerance.channel {
+            different += 1;
+            // The first few are enough to go on; the diff image has the rest.
+            if different <= 10 {
+                writeln!(
+                    report,
+                    "  ({}, {}): expected {:?}, got {:?}",
+                    x, y, expected_pixel.0, actual_pixel.0
+                )
+                .unwrap();
+            }
+        }
+    }
+    if different <= tolerance.pixels {
+        return;
+    }
+
+    let diff_path = write_diff(case, &expected, &actual, tolerance);
+    panic!(
+        "{} has {} pixels off by more than {} (allowed {}), at most {}:\n{}diff written to {}",
+        case,
+        different,
+        tolerance.channel,
+        tolerance.pixels,
+        worst,
+        report,
+        diff_path.display()
+    );
+}
+
+/// Returns the most any channel of `a` and `b` differs by.
+fn channel_difference(a: &Rgba<u8>, b: &Rgba<u8>) -> u8 {
+    a.0.iter()
+        .zip(b.0)
+        .map(|(&a, b)| a.abs_diff(b))
+        .max()
+        .unwrap()
+}
+
+/// Writes the expected and actual outputs side by side with a map of where they differ into
+/// `target/`, and returns where it went.
+fn write_diff(
+    case: &str,
+    expected: &RgbaImage,
+    actual: &RgbaImage,
+    tolerance: Tolerance,
+) -> PathBuf {
+    let (width, height) = expected.dimensions();
+    let mut diff = RgbaImage::new(width * 3, height);
+    for (x, y, expected_pixel) in expected.enumerate_pixels() {
+        let actual_pixel = actual.get_pixel(x, y);
+        diff.put_pixel(x, y, *expected_pixel);
+        diff.put_pixel(width + x, y, *actual_pixel);
+        // Differences get brighter red the bigger they are, and everything else is a faded copy
+        // of the expected output to show where they are.
+        let off = channel_difference(expected_pixel, actual_pixel);
+        let marker = if off > tolerance.channel {
+            Rgba([128 + off / 2, 0, 0, 255])
+        } else {
+            let [r, g, b, _] = expected_pixel.0.map(u32::from);
+            let faded = (192 + (r + g + b) / 12) as u8;
+            Rgba([faded, faded, faded, 255])
+        };
+        diff.put_pixel(2 * width + x, y, marker);
+    }
+
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("golden");
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("{}.png", case));
+    diff.save(&path).unwrap();
+    path
+}
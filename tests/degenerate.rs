@@ -0,0 +1,111 @@
+use image::{GrayImage, Luma};
+use rectanglify::rects::{rectanglify, rectanglify_rects, RectBudget, Settings};
+
+/// Each way of asking for rectangles, since they each have their own way of dividing by the
+/// darkness.
+fn budgets() -> [RectBudget; 2] {
+    [RectBudget::PerDarkness(0.1), RectBudget::Count(10)]
+}
+
+#[test]
+fn all_white() {
+    let input = GrayImage::from_pixel(32, 32, Luma([255]));
+    for budget in budgets() {
+        let settings = Settings {
+            budget,
+            ..Settings::default()
+        };
+        let mut output = GrayImage::new(32, 32);
+        rectanglify(&input, &mut output, settings.clone());
+        assert!(
+            output.pixels().all(|&pixel| pixel == Luma([255])),
+            "{:?} drew something",
+            budget
+        );
+        assert!(rectanglify_rects(&input, settings).len() <= 1);
+    }
+}
+
+#[test]
+fn all_black() {
+    let input = GrayImage::from_pixel(32, 32, Luma([0]));
+    for budget in budgets() {
+        let settings = Settings {
+            budget,
+            ..Settings::default()
+        };
+        let mut output = GrayImage::new(32, 32);
+        rectanglify(&input, &mut output, settings.clone());
+        assert!(output.pixels().any(|&pixel| pixel == Luma([0])));
+
+        let rects = rectanglify_rects(&input, settings);
+        assert!(rects.len() > 1);
+        for rect in rects {
+            assert!(rect.width() > 0.0 && rect.height() > 0.0, "{:?}", rect);
+        }
+    }
+}
+
+#[test]
+fn single_pixel() {
+    for value in [0, 128, 255] {
+        let input = GrayImage::from_pixel(1, 1, Luma([value]));
+        for budget in budgets() {
+            let settings = Settings {
+                budget,
+                ..Settings::default()
+            };
+            let mut output = GrayImage::new(1, 1);
+            rectanglify(&input, &mut output, settings.clone());
+
+            for rect in rectanglify_rects(&input, settings) {
+                assert!(
+                    rect.left >= 0.0 && rect.top >= 0.0 && rect.right <= 1.0 && rect.bottom <= 1.0,
+                    "{:?} for {} with {:?}",
+                    rect,
+                    value,
+                    budget
+                );
+            }
+        }
+    }
+}
+
+/// Splits that land in a column or row with no darkness at all used to divide by zero, giving
+/// rectangles with NaN edges.
+#[test]
+fn white_gaps() {
+    // Thin black columns and rows with wide white gaps between them.
+    let input = GrayImage::from_fn(64, 48, |x, y| {
+        Luma([if x % 16 < 2 || y % 12 < 1 { 0 } else { 255 }])
+    });
+    for threshold in [None, Some(0.5)] {
+        for budget in budgets() {
+            let settings = Settings {
+                budget,
+                threshold,
+                ..Settings::default()
+            };
+            let rects = rectanglify_rects(&input, settings.clone());
+            assert!(rects.len() > 1);
+
+            let mut area = 0.0;
+            for rect in &rects {
+                assert!(
+                    rect.left >= 0.0
+                        && rect.top >= 0.0
+                        && rect.right <= 64.0
+                        && rect.bottom <= 48.0
+                        && rect.width() >= 0.0
+                        && rect.height() >= 0.0,
+                    "{:?} with {:?}",
+                    rect,
+                    settings
+                );
+                area += rect.width() * rect.height();
+            }
+            // They still cover the whole image between them.
+            assert!((area - 64.0 * 48.0).abs() < 1e-6, "area of {}", area);
+        }
+    }
+}
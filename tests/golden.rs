@@ -0,0 +1,121 @@
+mod common;
+
+use common::{assert_matches, input, Tolerance};
+use image::RgbImage;
+use rectanglify::rects::{rectanglify, FillMode, Rectangle, Settings};
+
+/// Rectanglifies the input fixture called `input` into an output the same size, and checks it
+/// against the expected output for `case`.
+fn check(case: &str, input_name: &str, settings: Settings, tolerance: Tolerance) {
+    let input = input(input_name).to_rgb8();
+    let mut output = RgbImage::new(input.width(), input.height());
+    rectanglify(&input, &mut output, settings);
+    assert_matches(case, output, tolerance);
+}
+
+fn fill_mode(case: &str, fill_mode: FillMode) {
+    let settings = Settings {
+        fill_mode,
+        ..Settings::default()
+    };
+    check(case, "shapes.png", settings, Tolerance::EXACT);
+}
+
+#[test]
+fn default_style() {
+    for input in ["shapes", "gradient", "stripes"] {
+        check(
+            &format!("default-{}", input),
+            &format!("{}.png", input),
+            Settings::default(),
+            Tolerance::EXACT,
+        );
+    }
+}
+
+#[test]
+fn invert() {
+    let settings = Settings {
+        invert: true,
+        ..Settings::default()
+    };
+    check("invert", "shapes.png", settings, Tolerance::EXACT);
+}
+
+#[test]
+fn mosaic() {
+    fill_mode("mosaic", FillMode::Mosaic);
+}
+
+#[test]
+fn checkerboard() {
+    fill_mode("checkerboard", FillMode::Checkerboard);
+}
+
+#[test]
+fn ellipses() {
+    fill_mode("ellipses", FillMode::Ellipses);
+}
+
+#[test]
+fn filled_ellipses() {
+    fill_mode("filled-ellipses", FillMode::FilledEllipses);
+}
+
+#[test]
+fn halftone() {
+    fill_mode("halftone", FillMode::Halftone);
+}
+
+#[test]
+fn mondrian() {
+    fill_mode("mondrian", FillMode::Mondrian);
+}
+
+#[test]
+fn gradient() {
+    fill_mode("gradient", FillMode::Gradient);
+}
+
+#[test]
+fn quantized() {
+    fill_mode("quantized", FillMode::Quantized { levels: 4 });
+}
+
+#[test]
+fn line_width() {
+    let settings = Settings {
+        line_thickness: 3,
+        ..Settings::default()
+    };
+    check("line-width", "stripes.png", settings, Tolerance::EXACT);
+}
+
+#[test]
+fn roi() {
+    let settings = Settings {
+        roi: Some(Rectangle {
+            left: 8.0,
+            top: 4.0,
+            right: 40.0,
+            bottom: 44.0,
+        }),
+        border: true,
+        ..Settings::default()
+    };
+    check("roi", "gradient.png", settings, Tolerance::EXACT);
+}
+
+#[test]
+fn antialias() {
+    let settings = Settings {
+        antialias: true,
+        ..Settings::default()
+    };
+    // Blending into partially covered pixels leaves room for rounding to go either way.
+    let tolerance = Tolerance {
+        channel: 1,
+        pixels: 0,
+    };
+    check("antialias", "gradient.png", settings, tolerance);
+}
@@ -0,0 +1,100 @@
+use image::{Rgb, RgbImage};
+use rectanglify::plotter::plotter_paths;
+use rectanglify::rects::{rectanglify, Color, RectBudget, Settings};
+
+/// Thick, half-transparent black lines, with the rectangles kept big enough that lines running
+/// alongside each other never touch, so the only place they could overlap is where they meet.
+fn settings(antialias: bool) -> Settings {
+    Settings {
+        budget: RectBudget::Count(24),
+        line_thickness: 5,
+        line_color: Color {
+            a: 0.5,
+            ..Color::BLACK
+        },
+        antialias,
+        border: true,
+        min_rect_size: 12.0,
+        ..Settings::default()
+    }
+}
+
+fn input() -> RgbImage {
+    RgbImage::from_fn(96, 96, |x, y| {
+        let value = ((x * x + y * 3) % 256) as u8;
+        Rgb([value, value, value])
+    })
+}
+
+#[test]
+fn semi_transparent_lines_are_drawn_once() {
+    let input = input();
+    let mut output = RgbImage::new(96, 96);
+    rectanglify(&input, &mut output, settings(false));
+
+    // Black at half opacity over white, once.
+    let line = Rgb([128, 128, 128]);
+    let mut lines = 0;
+    for (x, y, &pixel) in output.enumerate_pixels() {
+        assert!(
+            pixel == Rgb([255, 255, 255]) || pixel == line,
+            "({}, {}) is {:?}",
+            x,
+            y,
+            pixel
+        );
+        lines += usize::from(pixel == line);
+    }
+    assert!(lines > 0);
+}
+
+#[test]
+fn antialiased_junctions_are_drawn_once() {
+    let input = input();
+    let mut output = RgbImage::new(96, 96);
+    rectanglify(&input, &mut output, settings(true));
+
+    // Nothing should be any darker than one layer of the line colour.
+    for (x, y, &pixel) in output.enumerate_pixels() {
+        assert!(pixel[0] >= 127, "({}, {}) is {:?}", x, y, pixel);
+    }
+}
+
+#[test]
+fn plotter_lines_stop_at_the_pen_edge() {
+    let settings = Settings {
+        border: false,
+        ..settings(false)
+    };
+    let pen_width = 2.0;
+    let paths = plotter_paths(&input(), settings, 1.0, pen_width);
+    let segments: Vec<_> = paths
+        .iter()
+        .flat_map(|path| path.windows(2).map(|pair| (pair[0], pair[1])))
+        .collect();
+    assert!(segments.len() > 1);
+
+    // No end of a segment comes closer than half the pen's width to a segment running across it.
+    for &(start, end) in &segments {
+        for &(x, y) in &[start, end] {
+            for &((x1, y1), (x2, y2)) in &segments {
+                let distance = if x1 == x2 && (y1.min(y2)..=y1.max(y2)).contains(&y) {
+                    (x - x1).abs()
+                } else if y1 == y2 && (x1.min(x2)..=x1.max(x2)).contains(&x) {
+                    (y - y1).abs()
+                } else {
+                    continue;
+                };
+                if (start, end) != ((x1, y1), (x2, y2)) {
+                    assert!(
+                        distance >= pen_width / 2.0 - 1e-9,
+                        "({}, {}) is {} from another line",
+                        x,
+                        y,
+                        distance
+                    );
+                }
+            }
+        }
+    }
+}
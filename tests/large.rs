@@ -0,0 +1,30 @@
+use std::thread;
+
+use image::{GrayImage, Luma};
+use rectanglify::rects::{rectanglify_rects, RectBudget, Settings};
+
+/// Splitting used to recurse once per level of splits, which a dense enough image could take
+/// deeper than the stack of a streaming thread.
+#[test]
+fn huge_density_doesnt_overflow_stack() {
+    // An eighth of the 2MB Rust gives spawned threads by default, so that splitting can't lean
+    // on the stack to keep track of what's left to split.
+    let rects = thread::Builder::new()
+        .stack_size(256 * 1024)
+        .spawn(|| {
+            let input = GrayImage::from_fn(4096, 4096, |x, _| Luma([(x / 16) as u8]));
+            // Far more than there are pixels, cut down by `max_rects` to keep the memory use in
+            // check.
+            let settings = Settings {
+                budget: RectBudget::PerDarkness(100.0),
+                max_rects: Some(100_000),
+                ..Settings::default()
+            };
+            rectanglify_rects(&input, settings)
+        })
+        .unwrap()
+        .join()
+        .expect("splitting panicked");
+
+    assert_eq!(rects.len(), 100_000);
+}
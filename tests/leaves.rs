@@ -0,0 +1,71 @@
+use image::{GrayImage, Luma};
+use rectanglify::rects::{rectanglify_leaves, RectBudget, Settings};
+
+fn input() -> GrayImage {
+    GrayImage::from_fn(256, 192, |x, y| Luma([((x * y) / 193) as u8]))
+}
+
+/// Returns the standard deviation of the darkness of each leaf, as a fraction of the mean.
+fn leaf_darkness_spread(input: &GrayImage, settings: Settings) -> f64 {
+    let darkness: Vec<f64> = rectanglify_leaves(input, settings)
+        .iter()
+        .map(|leaf| leaf.mean_darkness * leaf.rect.width() * leaf.rect.height())
+        .collect();
+    let count = darkness.len() as f64;
+    let mean = darkness.iter().sum::<f64>() / count;
+    let variance = darkness
+        .iter()
+        .map(|darkness| (darkness - mean).powi(2))
+        .sum::<f64>()
+        / count;
+    variance.sqrt() / mean
+}
+
+/// Splitting by the darkness actually in each area, rather than what the whole image's average
+/// says it should have, shares it out evenly between the leaves all the way down.
+#[test]
+fn leaf_darkness_is_even() {
+    let input = input();
+    for budget in [
+        RectBudget::Count(10),
+        RectBudget::Count(1000),
+        RectBudget::PerDarkness(0.05),
+    ] {
+        let settings = Settings {
+            budget,
+            ..Settings::default()
+        };
+        let spread = leaf_darkness_spread(&input, settings);
+        assert!(spread < 1e-6, "{:?} has a spread of {}", budget, spread);
+    }
+}
+
+/// Between them, the leaves cover all of the image's darkness, and every pixel in it.
+#[test]
+fn leaves_add_up_to_image() {
+    let input = input();
+    let total: f64 = input
+        .pixels()
+        .map(|&Luma([value])| 1.0 - value as f64 / 255.0)
+        .sum();
+    for budget in [RectBudget::Count(1), RectBudget::Count(500)] {
+        let settings = Settings {
+            budget,
+            ..Settings::default()
+        };
+        let leaves = rectanglify_leaves(&input, settings);
+        let darkness: f64 = leaves
+            .iter()
+            .map(|leaf| leaf.mean_darkness * leaf.rect.width() * leaf.rect.height())
+            .sum();
+        assert!(
+            (darkness - total).abs() < total * 1e-9,
+            "{:?} gave {} rather than {}",
+            budget,
+            darkness,
+            total
+        );
+        let pixels: usize = leaves.iter().map(|leaf| leaf.pixel_count).sum();
+        assert_eq!(pixels, 256 * 192);
+    }
+}
@@ -0,0 +1,46 @@
+use image::{GrayImage, Luma};
+use rectanglify::rects::{rectanglify_with, Options, RectanglifyError, Settings};
+
+#[test]
+fn mismatched_mask_is_an_error() {
+    let input = GrayImage::from_pixel(16, 16, Luma([0]));
+    let mask = GrayImage::from_pixel(8, 16, Luma([255]));
+    let mut output = GrayImage::from_pixel(16, 16, Luma([77]));
+    let options = Options {
+        mask: Some(&mask),
+        ..Options::default()
+    };
+    let result = rectanglify_with(&input, &mut output, Settings::default(), options);
+    assert_eq!(
+        result,
+        Err(RectanglifyError::MaskSize {
+            mask: (8, 16),
+            output: (16, 16),
+        })
+    );
+    // Nothing gets drawn, not even the background.
+    assert!(output.pixels().all(|&pixel| pixel == Luma([77])));
+}
+
+#[test]
+fn mask_clips_output() {
+    let input = GrayImage::from_pixel(32, 32, Luma([0]));
+    let mask = GrayImage::from_fn(32, 32, |x, _| match x < 16 {
+        true => Luma([255]),
+        false => Luma([0]),
+    });
+    let mut output = GrayImage::new(32, 32);
+    let options = Options {
+        mask: Some(&mask),
+        ..Options::default()
+    };
+    rectanglify_with(&input, &mut output, Settings::default(), options).unwrap();
+    for (x, _, pixel) in output.enumerate_pixels() {
+        if x >= 16 {
+            assert_eq!(*pixel, Luma([255]));
+        }
+    }
+    assert!(output
+        .enumerate_pixels()
+        .any(|(x, _, &pixel)| x < 16 && pixel == Luma([0])));
+}
@@ -0,0 +1,20 @@
+use image::{GrayImage, Luma};
+use rectanglify::rects::{rectanglify_rects, RectBudget, Settings};
+
+/// A solid black image asks for as many rectangles as it possibly can, so the cap is all that
+/// decides how many there are.
+#[test]
+fn black_respects_cap() {
+    let input = GrayImage::from_pixel(640, 480, Luma([0]));
+    for budget in [RectBudget::PerDarkness(0.5), RectBudget::Count(1_000_000)] {
+        for max_rects in [1, 2, 7, 100, 1000] {
+            let settings = Settings {
+                budget,
+                max_rects: Some(max_rects),
+                ..Settings::default()
+            };
+            let rects = rectanglify_rects(&input, settings);
+            assert_eq!(rects.len(), max_rects, "{:?}", budget);
+        }
+    }
+}
@@ -0,0 +1,66 @@
+use image::{Rgba, RgbaImage};
+use rectanglify::rects::{rectanglify, Color, RectBudget, Settings};
+
+/// Opaque black lines over a copy of the input, showing through half-way onto a transparent
+/// background.
+fn settings() -> Settings {
+    Settings {
+        budget: RectBudget::Count(16),
+        line_color: Color::BLACK,
+        background_color: Color {
+            a: 0.0,
+            ..Color::WHITE
+        },
+        overlay: true,
+        source_opacity: 0.5,
+        ..Settings::default()
+    }
+}
+
+/// A light grey gradient with a transparent left half.
+fn input() -> RgbaImage {
+    RgbaImage::from_fn(64, 48, |x, y| {
+        let value = 128 + ((x + y) % 128) as u8;
+        Rgba([value, value, value, if x < 32 { 0 } else { 255 }])
+    })
+}
+
+#[test]
+fn source_shows_through_at_its_opacity() {
+    let input = input();
+    let mut output = RgbaImage::new(64, 48);
+    rectanglify(&input, &mut output, settings());
+
+    let (mut lines, mut source) = (0, 0);
+    for (x, y, &Rgba([r, g, b, a])) in output.enumerate_pixels() {
+        if [r, g, b] == [0, 0, 0] {
+            assert_eq!(a, 255, "line at ({}, {}) isn't opaque", x, y);
+            lines += 1;
+        } else if x < 32 {
+            assert_eq!(a, 0, "transparent input at ({}, {}) shows through", x, y);
+        } else {
+            // The input's colour, at half its alpha.
+            assert_eq!(Rgba([r, g, b, a]), {
+                let Rgba([r, g, b, _]) = *input.get_pixel(x, y);
+                Rgba([r, g, b, 128])
+            });
+            source += 1;
+        }
+    }
+    assert!(lines > 0 && source > 0);
+}
+
+#[test]
+fn preserve_alpha_overrides_opacity() {
+    let input = input();
+    let mut output = RgbaImage::new(64, 48);
+    let settings = Settings {
+        preserve_alpha: true,
+        ..settings()
+    };
+    rectanglify(&input, &mut output, settings);
+
+    for (x, y, pixel) in output.enumerate_pixels() {
+        assert_eq!(pixel[3], input.get_pixel(x, y)[3], "at ({}, {})", x, y);
+    }
+}
@@ -0,0 +1,81 @@
+use image::{GrayImage, Luma};
+use rectanglify::rects::{rectanglify, Settings};
+
+fn gradient(width: u32, height: u32) -> GrayImage {
+    GrayImage::from_fn(width, height, |x, y| {
+        Luma([(x * 128 / width + y * 127 / height) as u8])
+    })
+}
+
+/// Checks that drawing `input` onto an image of `width` by `height` doesn't panic, and that the
+/// drawing is stretched out over the whole thing rather than stuck in the top-left.
+fn check(input: &GrayImage, width: u32, height: u32) {
+    let settings = Settings {
+        border: true,
+        ..Settings::default()
+    };
+    let mut output = GrayImage::new(width, height);
+    rectanglify(input, &mut output, settings);
+
+    // The border goes around the edges of the output, not the input.
+    for (x, y) in [
+        (0, 0),
+        (width - 1, 0),
+        (0, height - 1),
+        (width - 1, height - 1),
+    ] {
+        assert_eq!(
+            output[(x, y)],
+            Luma([0]),
+            "{}x{} onto {}x{} has no border at ({}, {})",
+            input.width(),
+            input.height(),
+            width,
+            height,
+            x,
+            y
+        );
+    }
+    // And there's something drawn inside each quarter of it.
+    for right in [false, true] {
+        for bottom in [false, true] {
+            let drawn = output.enumerate_pixels().any(|(x, y, &pixel)| {
+                (x >= width / 2) == right && (y >= height / 2) == bottom && pixel == Luma([0])
+            });
+            assert!(
+                drawn,
+                "{}x{} onto {}x{} has nothing in the {} {} quarter",
+                input.width(),
+                input.height(),
+                width,
+                height,
+                if bottom { "bottom" } else { "top" },
+                if right { "right" } else { "left" },
+            );
+        }
+    }
+}
+
+#[test]
+fn larger_output() {
+    check(&gradient(64, 48), 256, 192);
+    check(&gradient(64, 48), 100, 50);
+}
+
+#[test]
+fn smaller_output() {
+    check(&gradient(64, 48), 16, 12);
+    check(&gradient(64, 48), 50, 40);
+
+    // Too small to have quarters, but it still shouldn't panic.
+    let mut output = GrayImage::new(1, 1);
+    rectanglify(&gradient(64, 48), &mut output, Settings::default());
+}
+
+#[test]
+fn mismatched_aspect_ratios() {
+    check(&gradient(64, 48), 400, 4);
+    check(&gradient(64, 48), 4, 400);
+    check(&gradient(400, 4), 64, 48);
+    check(&gradient(4, 400), 64, 48);
+}
@@ -0,0 +1,205 @@
+use gst::prelude::*;
+use gst_check::Harness;
+
+const CAPS: &str = "video/x-raw,format=GRAY8,width=64,height=48,framerate=0/1";
+const RGBA_CAPS: &str = "video/x-raw,format=RGBA,width=64,height=48,framerate=0/1";
+
+fn init() {
+    use std::sync::Once;
+    static INIT: Once = Once::new();
+
+    INIT.call_once(|| {
+        gst::init().unwrap();
+        rectanglify::plugin_register_static().expect("failed to register rectanglify");
+    });
+}
+
+/// A horizontal gradient to push through the element.
+fn frame() -> Vec<u8> {
+    (0..64 * 48).map(|i| ((i % 64) * 4) as u8).collect()
+}
+
+/// A vertical gradient, different enough from `frame` that reusing the splits from one changes
+/// how the other comes out.
+fn other_frame() -> Vec<u8> {
+    (0..64 * 48).map(|i| ((i / 64) * 5) as u8).collect()
+}
+
+/// Returns a harness that reuses as much as it can of the previous frame, ready to push frames
+/// into.
+fn harness() -> Harness {
+    harness_with_caps(CAPS)
+}
+
+fn harness_with_caps(caps: &str) -> Harness {
+    init();
+    let mut harness = Harness::new("rectanglify");
+    let element = harness.element().unwrap();
+    element.set_property("hysteresis", 1000.0f64);
+    element.set_property("coherence-threshold", 1000.0f64);
+    harness.set_src_caps_str(caps);
+    harness.set_sink_caps_str(caps);
+    harness.play();
+    harness
+}
+
+fn push(harness: &mut Harness, frame: &[u8]) -> Vec<u8> {
+    harness
+        .push(gst::Buffer::from_slice(frame.to_vec()))
+        .unwrap();
+    let output = harness.pull().unwrap();
+    let output = output.map_readable().unwrap();
+    output.as_slice().to_vec()
+}
+
+/// Returns how `other_frame` comes out on its own.
+fn cold() -> Vec<u8> {
+    push(&mut harness(), &other_frame())
+}
+
+#[test]
+fn reuses_previous_frame() {
+    let (first, second) = (frame(), other_frame());
+    let mut harness = harness();
+    push(&mut harness, &first);
+    // Otherwise, the other tests don't show anything.
+    assert_ne!(push(&mut harness, &second), cold());
+}
+
+#[test]
+fn flush_resets() {
+    let (first, second) = (frame(), other_frame());
+    let mut harness = harness();
+    push(&mut harness, &first);
+
+    assert!(harness.push_event(gst::event::FlushStart::new()));
+    assert!(harness.push_event(gst::event::FlushStop::new(true)));
+    // Flushing drops the segment, so it needs sending again.
+    let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+    assert!(harness.push_event(gst::event::Segment::new(&segment)));
+    assert_eq!(push(&mut harness, &second), cold());
+}
+
+#[test]
+fn segment_resets() {
+    let (first, second) = (frame(), other_frame());
+    let mut harness = harness();
+    push(&mut harness, &first);
+
+    let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+    assert!(harness.push_event(gst::event::Segment::new(&segment)));
+    assert_eq!(push(&mut harness, &second), cold());
+}
+
+#[test]
+fn state_cycle_resets() {
+    let (first, second) = (frame(), other_frame());
+    let mut harness = harness();
+    push(&mut harness, &first);
+
+    let element = harness.element().unwrap();
+    for state in [gst::State::Ready, gst::State::Null, gst::State::Ready] {
+        element.set_state(state).unwrap();
+    }
+    // Going down to READY drops the sticky events, so they need sending again.
+    harness.play();
+    assert!(harness.push_event(gst::event::StreamStart::new("rectanglify-test")));
+    harness.set_src_caps_str(CAPS);
+    assert_eq!(push(&mut harness, &second), cold());
+}
+
+#[test]
+fn edge_weight_and_blur_radius() {
+    let frame = frame();
+    let mut harness = harness();
+    // Otherwise, every frame after the first reuses its splits whatever the settings are.
+    let element = harness.element().unwrap();
+    element.set_property("hysteresis", 0.0f64);
+    element.set_property("coherence-threshold", 0.0f64);
+    let baseline = push(&mut harness, &frame);
+
+    element.set_property("edge-weight", 1.0f64);
+    assert_ne!(push(&mut harness, &frame), baseline);
+    element.set_property("edge-weight", 0.0f64);
+    assert_eq!(push(&mut harness, &frame), baseline);
+
+    element.set_property("blur-radius", 8u32);
+    assert_eq!(element.property::<u32>("blur-radius"), 8);
+    assert_ne!(push(&mut harness, &frame), baseline);
+    element.set_property("blur-radius", 0u32);
+    assert_eq!(push(&mut harness, &frame), baseline);
+}
+
+#[test]
+fn avg_frame_time() {
+    let mut harness = harness();
+    let element = harness.element().unwrap();
+    assert_eq!(element.property::<u64>("avg-frame-time"), 0);
+    push(&mut harness, &frame());
+    assert!(element.property::<u64>("avg-frame-time") > 0);
+}
+
+#[test]
+fn source_opacity_composites() {
+    // The same gradient in RGBA, kept light enough not to be mistaken for the lines.
+    let frame: Vec<u8> = frame()
+        .iter()
+        .flat_map(|&value| {
+            let value = 128 + value / 2;
+            [value, value, value, 255]
+        })
+        .collect();
+    let mut harness = harness_with_caps(RGBA_CAPS);
+    let element = harness.element().unwrap();
+    element.set_property("background-color", 0x00ffffffu32);
+    element.set_property("source-opacity", 0.5f64);
+    let output = push(&mut harness, &frame);
+
+    // Composite it over solid red, the same way a compositor would.
+    let over_red = |pixel: &[u8], alpha: u8| -> Vec<u8> {
+        let alpha = alpha as f64 / 255.0;
+        [255.0, 0.0, 0.0]
+            .iter()
+            .zip(pixel)
+            .map(|(red, &value)| (value as f64 * alpha + red * (1.0 - alpha)).round() as u8)
+            .collect()
+    };
+    let (mut lines, mut source) = (0, 0);
+    for (input, output) in frame.chunks(4).zip(output.chunks(4)) {
+        if output[3] == 255 {
+            assert_eq!(over_red(output, 255), [0, 0, 0]);
+            lines += 1;
+        } else {
+            // Half the frame and half the red underneath.
+            assert_eq!(output[3], 128);
+            assert_eq!(over_red(output, output[3]), over_red(input, 128));
+            source += 1;
+        }
+    }
+    assert!(lines > 0 && source > 0);
+
+    // preserve-alpha puts the frame's own alpha back over all of that.
+    element.set_property("preserve-alpha", true);
+    let output = push(&mut harness, &frame);
+    assert!(output.chunks(4).all(|pixel| pixel[3] == 255));
+}
+
+#[test]
+fn shape_changes_mid_stream() {
+    let frame = frame();
+    let mut harness = harness();
+    let element = harness.element().unwrap();
+    element.set_property_from_str("fill-mode", "halftone");
+    let dots = push(&mut harness, &frame);
+    while harness.try_pull_event().is_some() {}
+
+    element.set_property_from_str("shape", "rect");
+    element.set_property("dot-max-coverage", 0.5f64);
+    let squares = push(&mut harness, &frame);
+    assert!(squares.iter().any(|&pixel| pixel < 128));
+    assert_ne!(squares, dots);
+    // Changing it shouldn't renegotiate anything.
+    while let Some(event) = harness.try_pull_event() {
+        assert_ne!(event.type_(), gst::EventType::Caps);
+    }
+}
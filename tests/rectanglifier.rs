@@ -0,0 +1,74 @@
+use image::{Rgb, RgbImage};
+use rectanglify::rects::{rectanglify, Channels, Color, Rectanglifier, Settings};
+
+/// A few frames of a gradient moving to the right, with each channel moving at a different speed.
+fn frames() -> Vec<RgbImage> {
+    (0..4)
+        .map(|t| {
+            RgbImage::from_fn(128, 96, |x, y| {
+                Rgb([
+                    ((x + y + t * 2) % 256) as u8,
+                    ((x * 2 + t * 5) % 256) as u8,
+                    ((y * 2 + x + t * 9) % 256) as u8,
+                ])
+            })
+        })
+        .collect()
+}
+
+/// Returns how `rectanglifier` draws each of the frames in turn.
+fn draw_all(rectanglifier: &mut Rectanglifier, settings: &Settings) -> Vec<RgbImage> {
+    frames()
+        .iter()
+        .map(|frame| {
+            let mut output = RgbImage::new(128, 96);
+            rectanglifier
+                .rectanglify(frame, &mut output, settings.clone())
+                .unwrap();
+            output
+        })
+        .collect()
+}
+
+#[test]
+fn separate_channels_match_rectanglify() {
+    let settings = Settings {
+        channels: Channels::Separate,
+        line_color: Color::rgb(1.0, 0.0, 0.5),
+        ..Settings::default()
+    };
+    let drawn = draw_all(&mut Rectanglifier::new(0.0, 0.0), &settings);
+    for (frame, drawn) in frames().iter().zip(drawn) {
+        let mut expected = RgbImage::new(128, 96);
+        rectanglify(frame, &mut expected, settings.clone());
+        assert_eq!(drawn, expected);
+    }
+}
+
+#[test]
+fn separate_channels_reuse_previous_frame() {
+    let settings = Settings {
+        channels: Channels::Separate,
+        ..Settings::default()
+    };
+    let reused = draw_all(&mut Rectanglifier::new(1000.0, 1000.0), &settings);
+    let frames = frames();
+    let mut fresh = RgbImage::new(128, 96);
+    rectanglify(&frames[1], &mut fresh, settings);
+    assert_ne!(reused[1], fresh);
+}
+
+#[test]
+fn parallel_gives_the_same_output() {
+    let settings = Settings::default();
+    let parallel = Settings {
+        parallel: true,
+        ..Settings::default()
+    };
+    for (hysteresis, threshold) in [(0.0, 0.0), (2.0, 0.1)] {
+        assert_eq!(
+            draw_all(&mut Rectanglifier::new(hysteresis, threshold), &parallel),
+            draw_all(&mut Rectanglifier::new(hysteresis, threshold), &settings),
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use image::{GrayImage, Luma};
+use rectanglify::rects::{rectanglify, region, Rectangle, Settings};
+
+fn settings(roi: Rectangle) -> Settings {
+    Settings {
+        roi: Some(roi),
+        border: true,
+        ..Settings::default()
+    }
+}
+
+#[test]
+fn roi_outside_input_draws_only_background() {
+    let input = GrayImage::from_pixel(32, 32, Luma([0]));
+    let mut output = GrayImage::from_pixel(32, 32, Luma([123]));
+    let settings = settings(Rectangle {
+        left: 40.0,
+        top: -20.0,
+        right: 60.0,
+        bottom: -10.0,
+    });
+    assert!(region(&input, &settings).is_empty());
+    rectanglify(&input, &mut output, settings);
+    assert!(output.pixels().all(|&pixel| pixel == Luma([255])));
+}
+
+#[test]
+fn roi_is_clamped_to_input() {
+    let input = GrayImage::from_pixel(32, 32, Luma([0]));
+    let mut output = GrayImage::new(32, 32);
+    let settings = settings(Rectangle {
+        left: 16.0,
+        top: -8.0,
+        right: 64.0,
+        bottom: 16.0,
+    });
+    assert_eq!(
+        region(&input, &settings),
+        Rectangle {
+            left: 16.0,
+            top: 0.0,
+            right: 32.0,
+            bottom: 16.0,
+        }
+    );
+    rectanglify(&input, &mut output, settings);
+    for (x, y, pixel) in output.enumerate_pixels() {
+        if x < 16 || y >= 16 {
+            assert_eq!(
+                *pixel,
+                Luma([255]),
+                "drew outside the roi at ({}, {})",
+                x,
+                y
+            );
+        }
+    }
+    assert!(output.pixels().any(|&pixel| pixel == Luma([0])));
+}
+
+/// Drawing a second region without clearing the output first keeps what was drawn in the first.
+#[test]
+fn roi_passes_coexist() {
+    let input = GrayImage::from_fn(64, 32, |x, y| Luma([(x * 2 + y * 3) as u8]));
+    let left = Rectangle {
+        left: 0.0,
+        top: 0.0,
+        right: 32.0,
+        bottom: 32.0,
+    };
+    let right = Rectangle {
+        left: 32.0,
+        top: 0.0,
+        right: 64.0,
+        bottom: 32.0,
+    };
+
+    let mut left_only = GrayImage::new(64, 32);
+    rectanglify(&input, &mut left_only, settings(left));
+    let mut right_only = GrayImage::new(64, 32);
+    rectanglify(&input, &mut right_only, settings(right));
+
+    let mut output = GrayImage::new(64, 32);
+    rectanglify(&input, &mut output, settings(left));
+    let second = Settings {
+        clear_output: false,
+        ..settings(right)
+    };
+    rectanglify(&input, &mut output, second);
+
+    // The lines from both passes are all there, on top of each other.
+    for (x, y, &Luma([pixel])) in output.enumerate_pixels() {
+        let expected = left_only[(x, y)].0[0].min(right_only[(x, y)].0[0]);
+        assert_eq!(pixel, expected, "at ({}, {})", x, y);
+    }
+    assert!(left_only.pixels().any(|&pixel| pixel == Luma([0])));
+    assert!(right_only.pixels().any(|&pixel| pixel == Luma([0])));
+}
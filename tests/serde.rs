@@ -0,0 +1,138 @@
+#![cfg(feature = "serde")]
+
+use std::time::Duration;
+
+use image::{Rgb, RgbImage};
+use rectanglify::rects::{
+    rectanglify, rectanglify_tree, render, Brightness, Channels, Color, FillMode, LineStyle,
+    RectBudget, Rectangle, Settings, Shape, SplitStrategy, SplitTree, Symmetry,
+};
+
+fn input() -> RgbImage {
+    RgbImage::from_fn(96, 64, |x, y| {
+        Rgb([(x * 2) as u8, (y * 3) as u8, ((x * y) % 256) as u8])
+    })
+}
+
+#[test]
+fn tree_json_round_trip() {
+    let input = input();
+    for fill_mode in [FillMode::Lines, FillMode::Mosaic, FillMode::Halftone] {
+        let settings = Settings {
+            fill_mode,
+            border: true,
+            ..Settings::default()
+        };
+        let mut expected = RgbImage::new(96, 64);
+        rectanglify(&input, &mut expected, settings.clone());
+
+        let tree = rectanglify_tree(&input, settings.clone());
+        let json = serde_json::to_string(&tree).unwrap();
+        let tree: SplitTree = serde_json::from_str(&json).unwrap();
+        let mut output = RgbImage::new(96, 64);
+        render(&tree, &mut output, settings);
+        assert!(output == expected, "{:?} came out different", fill_mode);
+    }
+}
+
+/// Every setting that gets serialized, set to something other than its default.
+///
+/// This deliberately doesn't use `..Settings::default()`, so that adding a setting breaks it until
+/// it's added here too.
+fn non_default() -> Settings {
+    Settings {
+        budget: RectBudget::Count(123),
+        max_rects: Some(456),
+        line_thickness: 3,
+        thickness_falloff: 0.5,
+        tone_match: true,
+        tone_match_strength: 0.25,
+        line_color: Color::rgb(0.5, 0.25, 0.125),
+        line_palette: vec![Color::rgb(1.0, 0.0, 0.0), Color::rgb(0.0, 0.0, 1.0)],
+        line_style: LineStyle::Dashed {
+            dash: 4.0,
+            gap: 2.5,
+        },
+        shade_lines: true,
+        background_color: Color {
+            r: 0.75,
+            g: 0.5,
+            b: 0.25,
+            a: 0.5,
+        },
+        transparent_background: true,
+        overlay: true,
+        source_opacity: 0.5,
+        clear_output: false,
+        preserve_alpha: true,
+        invert: true,
+        brightness: Brightness::DistanceTo(Color::rgb(1.0, 0.0, 0.5)),
+        fill_mode: FillMode::Quantized { levels: 3 },
+        shape: Shape::Rectangle,
+        halftone_radius: (0.25, 0.75),
+        fill_probability: 0.125,
+        fill_palette: vec![Color::rgb(0.0, 0.5, 0.0)],
+        gamma: 1.5,
+        exposure: -0.5,
+        threshold: Some(0.5),
+        equalize: true,
+        edges: 0.25,
+        smoothing: Some(1.5),
+        antialias: true,
+        max_aspect_ratio: Some(4.0),
+        snap_to_pixels: true,
+        grid: Some(8),
+        grid_offset: (2.0, 3.0),
+        min_rect_size: 2.5,
+        max_depth: Some(7),
+        variance_threshold: Some(0.0625),
+        min_leaf_darkness: 0.125,
+        split_fraction: 0.375,
+        jitter: 0.25,
+        seed: 42,
+        split_strategy: SplitStrategy::Balanced,
+        seam_window: 5,
+        seam_max_imbalance: 0.75,
+        symmetry: Symmetry::FourWay,
+        parallel: true,
+        deadline: Some(Duration::from_millis(1500)),
+        deterministic: true,
+        scale: Some(0.5),
+        channels: Channels::Separate,
+        roi: Some(Rectangle {
+            left: 1.0,
+            top: 2.0,
+            right: 30.5,
+            bottom: 40.25,
+        }),
+        border: true,
+        wrap: true,
+        padding: 1.5,
+        corner_radius: 2.0,
+        debug_labels: true,
+    }
+}
+
+#[test]
+fn settings_json_round_trip() {
+    let settings = non_default();
+    let json = serde_json::to_string(&settings).unwrap();
+    let round_tripped: Settings = serde_json::from_str(&json).unwrap();
+    // `Settings` isn't `PartialEq`, since custom brightness functions can't be compared.
+    assert_eq!(format!("{:?}", round_tripped), format!("{:?}", settings));
+}
+
+#[test]
+fn missing_settings_are_defaults() {
+    let settings: Settings = serde_json::from_str(r#"{ "line_thickness": 3 }"#).unwrap();
+    let expected = Settings {
+        line_thickness: 3,
+        ..Settings::default()
+    };
+    assert_eq!(format!("{:?}", settings), format!("{:?}", expected));
+}
+
+#[test]
+fn unknown_settings_are_rejected() {
+    assert!(serde_json::from_str::<Settings>(r#"{ "line_thicknes": 3 }"#).is_err());
+}
@@ -0,0 +1,92 @@
+use image::{GrayImage, Luma};
+use rectanglify::rects::{
+    rectanglify, rectanglify_with, Options, Rectanglifier, RectanglifyError, Settings,
+    SettingsError,
+};
+
+#[test]
+fn builder_rejects_invalid_settings() {
+    assert_eq!(
+        Settings::builder()
+            .gamma(f64::NAN)
+            .build()
+            .unwrap_err()
+            .to_string(),
+        "gamma must be finite and positive, got NaN"
+    );
+    assert_eq!(
+        Settings::builder().split_fraction(1.0).build().unwrap_err(),
+        SettingsError::SplitFraction(1.0)
+    );
+}
+
+#[test]
+fn builder_keeps_valid_settings() {
+    let settings = Settings::builder()
+        .line_thickness(3)
+        .jitter(0.5)
+        .build()
+        .unwrap();
+    assert_eq!(settings.line_thickness, 3);
+    assert_eq!(settings.jitter, 0.5);
+
+    // Starting from existing settings keeps whatever isn't changed.
+    let settings = settings.into_builder().invert(true).build().unwrap();
+    assert_eq!(settings.line_thickness, 3);
+    assert!(settings.invert);
+}
+
+#[test]
+fn invalid_settings_are_an_error() {
+    let input = GrayImage::from_fn(16, 16, |x, _| Luma([x as u8 * 16]));
+    let settings = Settings {
+        padding: -1.0,
+        ..Settings::default()
+    };
+
+    let mut output = GrayImage::from_pixel(16, 16, Luma([77]));
+    let result = rectanglify_with(&input, &mut output, settings.clone(), Options::default());
+    assert_eq!(
+        result,
+        Err(RectanglifyError::Settings(SettingsError::Padding(-1.0)))
+    );
+    assert!(output.pixels().all(|&pixel| pixel == Luma([77])));
+
+    let mut rectanglifier = Rectanglifier::default();
+    let result = rectanglifier.rectanglify(&input, &mut output, settings);
+    assert_eq!(result, Err(SettingsError::Padding(-1.0)));
+    assert!(output.pixels().all(|&pixel| pixel == Luma([77])));
+    assert!(rectanglifier.report().is_none());
+}
+
+#[test]
+fn invalid_settings_are_sanitized() {
+    let settings = Settings {
+        gamma: f64::NAN,
+        split_fraction: 0.0,
+        jitter: 0.5,
+        ..Settings::default()
+    }
+    .sanitized();
+    assert_eq!(settings.validate(), Ok(()));
+    assert_eq!(settings.gamma, Settings::default().gamma);
+    assert_eq!(settings.split_fraction, Settings::default().split_fraction);
+    // Anything that was already in range is left alone.
+    assert_eq!(settings.jitter, 0.5);
+}
+
+#[test]
+fn rectanglify_sanitizes_invalid_settings() {
+    let input = GrayImage::from_fn(16, 16, |x, _| Luma([x as u8 * 16]));
+    let mut expected = GrayImage::new(16, 16);
+    rectanglify(&input, &mut expected, Settings::default());
+
+    let mut output = GrayImage::new(16, 16);
+    let settings = Settings {
+        gamma: f64::NAN,
+        padding: -1.0,
+        ..Settings::default()
+    };
+    rectanglify(&input, &mut output, settings);
+    assert_eq!(output, expected);
+}
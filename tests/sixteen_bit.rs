@@ -0,0 +1,64 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::process::Command;
+
+use image::{DynamicImage, ImageBuffer, Luma};
+use rectanglify::rects::{rectanglify, Settings};
+
+type Gray16Image = ImageBuffer<Luma<u16>, Vec<u16>>;
+
+/// A smooth gradient with a different level in every column, far more than 8 bits can tell
+/// apart.
+fn gradient() -> Gray16Image {
+    Gray16Image::from_fn(1024, 16, |x, _| Luma([(x * 64) as u16]))
+}
+
+/// Checks that `output` is `input` with black lines drawn over it, and that none of the levels
+/// in between got lost on the way.
+fn assert_no_banding(input: &Gray16Image, output: &Gray16Image) {
+    for (x, y, &pixel) in output.enumerate_pixels() {
+        assert!(
+            pixel == *input.get_pixel(x, y) || pixel == Luma([0]),
+            "({}, {}) is {:?}",
+            x,
+            y,
+            pixel
+        );
+    }
+    let levels: HashSet<u16> = output.pixels().map(|pixel| pixel[0]).collect();
+    assert!(levels.len() > 256, "only {} levels", levels.len());
+}
+
+#[test]
+fn sixteen_bit_overlay_keeps_every_level() {
+    let input = gradient();
+    let mut output = Gray16Image::new(input.width(), input.height());
+    let settings = Settings {
+        overlay: true,
+        ..Settings::default()
+    };
+    rectanglify(&input, &mut output, settings);
+    assert_no_banding(&input, &output);
+}
+
+#[test]
+fn sixteen_bit_png_round_trip() {
+    let dir = PathBuf::from(env!("CARGO_TARGET_TMPDIR"));
+    let in_path = dir.join("gradient-16.png");
+    let out_path = dir.join("gradient-16-rectanglified.png");
+    let input = gradient();
+    input.save(&in_path).unwrap();
+
+    let status = Command::new(env!("CARGO_BIN_EXE_rectanglify"))
+        .arg("--overlay")
+        .arg(&in_path)
+        .arg(&out_path)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    match image::open(&out_path).unwrap() {
+        DynamicImage::ImageLuma16(output) => assert_no_banding(&input, &output),
+        output => panic!("came out as {:?}", output.color()),
+    }
+}
@@ -0,0 +1,44 @@
+use image::{GrayImage, Luma};
+use rectanglify::rects::{rectanglify_lines, RectBudget, Settings};
+
+fn checkerboard() -> GrayImage {
+    GrayImage::from_fn(64, 64, |x, y| {
+        Luma([if (x / 4 + y / 4) % 2 == 0 { 0 } else { 255 }])
+    })
+}
+
+fn settings(snap_to_pixels: bool) -> Settings {
+    Settings {
+        budget: RectBudget::Count(200),
+        snap_to_pixels,
+        ..Settings::default()
+    }
+}
+
+fn is_whole(value: f64) -> bool {
+    value.fract() == 0.0
+}
+
+#[test]
+fn snapped_lines_are_on_whole_pixels() {
+    let lines = rectanglify_lines(&checkerboard(), settings(true));
+    assert!(!lines.is_empty());
+    for line in lines {
+        let ((x1, y1), (x2, y2)) = (line.start, line.end);
+        assert!(x1 == x2 || y1 == y2, "{:?} isn't axis-aligned", line);
+        assert!(
+            [x1, y1, x2, y2].into_iter().all(is_whole),
+            "{:?} isn't on whole pixels",
+            line
+        );
+    }
+}
+
+#[test]
+fn unsnapped_lines_arent() {
+    // Otherwise, the test above doesn't show anything.
+    let lines = rectanglify_lines(&checkerboard(), settings(false));
+    assert!(lines
+        .iter()
+        .any(|line| !is_whole(line.start.0) || !is_whole(line.start.1)));
+}
@@ -0,0 +1,74 @@
+use image::{GenericImage, GenericImageView, Luma, Rgb};
+use rectanglify::rects::StridedImage;
+
+#[test]
+fn rejects_stride_smaller_than_row() {
+    let data = [0u8; 64];
+    assert!(StridedImage::<Rgb<u8>, _>::from_raw(4, 2, 11, &data[..]).is_none());
+    assert!(StridedImage::<Rgb<u8>, _>::from_raw(4, 2, 12, &data[..]).is_some());
+}
+
+#[test]
+fn rejects_short_data() {
+    // 3 rows of 4 pixels with a stride of 8 needs 8 * 2 + 4 subpixels.
+    let data = [0u8; 19];
+    assert!(StridedImage::<Luma<u8>, _>::from_raw(4, 3, 8, &data[..]).is_none());
+}
+
+#[test]
+fn rejects_overflowing_stride() {
+    let data = [0u8; 16];
+    assert!(StridedImage::<Luma<u8>, _>::from_raw(4, 3, usize::MAX, &data[..]).is_none());
+}
+
+#[test]
+fn last_row_is_unpadded() {
+    let data: Vec<u8> = (0..20).collect();
+    let image = StridedImage::<Luma<u8>, _>::from_raw(4, 3, 8, &data[..]).unwrap();
+    assert_eq!(image.row(0), &[0, 1, 2, 3]);
+    assert_eq!(image.row(1), &[8, 9, 10, 11]);
+    assert_eq!(image.row(2), &[16, 17, 18, 19]);
+    assert_eq!(image.get_pixel(3, 2), Luma([19]));
+}
+
+#[test]
+fn empty_image() {
+    assert!(StridedImage::<Rgb<u8>, _>::from_raw(4, 0, 12, &[][..]).is_some());
+}
+
+#[test]
+fn writes_skip_padding() {
+    let mut data = vec![0xaau8; 2 * 8 + 6];
+    let mut image = StridedImage::<Rgb<u8>, _>::from_raw(2, 3, 8, &mut data[..]).unwrap();
+    for y in 0..3 {
+        for x in 0..2 {
+            image.put_pixel(x, y, Rgb([y as u8, x as u8, 1]));
+        }
+    }
+    assert_eq!(
+        data,
+        [
+            0, 0, 1, 0, 1, 1, 0xaa, 0xaa, //
+            1, 0, 1, 1, 1, 1, 0xaa, 0xaa, //
+            2, 0, 1, 2, 1, 1,
+        ]
+    );
+}
+
+#[test]
+fn copy_within_moves_rows_through_padding() {
+    let mut data: Vec<u8> = (0..3 * 6 + 4).collect();
+    let mut image = StridedImage::<Luma<u8>, _>::from_raw(4, 4, 6, &mut data[..]).unwrap();
+    let source = image::math::Rect {
+        x: 0,
+        y: 0,
+        width: 2,
+        height: 3,
+    };
+    assert!(image.copy_within(source, 2, 1));
+    assert_eq!(image.row(1), &[6, 7, 0, 1]);
+    assert_eq!(image.row(2), &[12, 13, 6, 7]);
+    assert_eq!(image.row(3), &[18, 19, 12, 13]);
+    // The padding is left alone.
+    assert_eq!(&data[4..6], &[4, 5]);
+}
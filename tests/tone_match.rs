@@ -0,0 +1,57 @@
+use image::{GrayImage, Luma};
+use rectanglify::rects::{rectanglify, RectBudget, Settings};
+
+/// How far apart the mean luma of the input and output are allowed to be with `tone_match`.
+const TOLERANCE: f64 = 0.03;
+
+fn mean_luma(image: &GrayImage) -> f64 {
+    let total: f64 = image.pixels().map(|&Luma([value])| value as f64).sum();
+    total / 255.0 / (image.width() * image.height()) as f64
+}
+
+/// Returns the difference between the mean luma of a gradient from black to white and its
+/// rectanglified version.
+fn luma_difference(settings: Settings) -> f64 {
+    let input = GrayImage::from_fn(512, 256, |x, _| Luma([(x * 255 / 511) as u8]));
+    let mut output = GrayImage::new(512, 256);
+    rectanglify(&input, &mut output, settings);
+    (mean_luma(&output) - mean_luma(&input)).abs()
+}
+
+#[test]
+fn tone_match_keeps_mean_luma() {
+    for budget in [
+        RectBudget::PerDarkness(0.1),
+        RectBudget::PerDarkness(0.01),
+        RectBudget::Count(300),
+    ] {
+        let settings = Settings {
+            budget,
+            ..Settings::default()
+        };
+        let unmatched = luma_difference(settings.clone());
+        let matched = luma_difference(Settings {
+            tone_match: true,
+            ..settings.clone()
+        });
+        let halfway = luma_difference(Settings {
+            tone_match: true,
+            tone_match_strength: 0.5,
+            ..settings
+        });
+        assert!(
+            matched < TOLERANCE,
+            "{:?} is off by {} with tone_match",
+            budget,
+            matched
+        );
+        assert!(
+            matched < halfway && halfway < unmatched,
+            "{:?} is off by {}, {} and {}",
+            budget,
+            matched,
+            halfway,
+            unmatched
+        );
+    }
+}
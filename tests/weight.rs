@@ -0,0 +1,77 @@
+use image::{GrayImage, Luma};
+use rectanglify::rects::{rectanglify, rectanglify_with, Options, RectanglifyError, Settings};
+
+#[test]
+fn mismatched_weight_is_an_error() {
+    let input = GrayImage::from_pixel(16, 16, Luma([0]));
+    let weight = GrayImage::from_pixel(16, 15, Luma([255]));
+    let mut output = GrayImage::from_pixel(16, 16, Luma([77]));
+    let options = Options {
+        weight: Some(&weight),
+        ..Options::default()
+    };
+    let result = rectanglify_with(&input, &mut output, Settings::default(), options);
+    assert_eq!(
+        result,
+        Err(RectanglifyError::WeightSize {
+            weight: (16, 15),
+            input: (16, 16),
+        })
+    );
+    assert!(output.pixels().all(|&pixel| pixel == Luma([77])));
+}
+
+#[test]
+fn white_weight_changes_nothing() {
+    let input = GrayImage::from_fn(48, 32, |x, y| Luma([(x * 5 + y * 3) as u8]));
+    let weight = GrayImage::from_pixel(48, 32, Luma([255]));
+    let mut plain = GrayImage::new(48, 32);
+    rectanglify(&input, &mut plain, Settings::default());
+    let mut weighted = GrayImage::new(48, 32);
+    let options = Options {
+        weight: Some(&weight),
+        ..Options::default()
+    };
+    rectanglify_with(&input, &mut weighted, Settings::default(), options).unwrap();
+    assert_eq!(plain, weighted);
+}
+
+#[test]
+fn extras_combine() {
+    let input = GrayImage::from_pixel(32, 32, Luma([0]));
+    // Only the left half is weighted, and only the top half is drawn.
+    let weight = GrayImage::from_fn(32, 32, |x, _| Luma([if x < 16 { 255 } else { 0 }]));
+    let mask = GrayImage::from_fn(32, 32, |_, y| Luma([if y < 16 { 255 } else { 0 }]));
+    let mut heatmap = GrayImage::new(32, 32);
+    let mut calls = 0;
+    let mut progress = |_| calls += 1;
+    let mut output = GrayImage::new(32, 32);
+    let options = Options {
+        weight: Some(&weight),
+        mask: Some(&mask),
+        progress: Some(&mut progress),
+        heatmap: Some(&mut heatmap),
+        cancel: None,
+    };
+    let report = rectanglify_with(&input, &mut output, Settings::default(), options).unwrap();
+
+    assert_eq!(calls, report.rects);
+    for (x, y, pixel) in output.enumerate_pixels() {
+        if y >= 16 {
+            assert_eq!(
+                *pixel,
+                Luma([255]),
+                "drew outside the mask at ({}, {})",
+                x,
+                y
+            );
+        }
+    }
+    // The unweighted half has no darkness, so its rectangles are much sparser.
+    let density = |xs: std::ops::Range<u32>| {
+        xs.flat_map(|x| (0..32).map(move |y| (x, y)))
+            .map(|(x, y)| u32::from(heatmap.get_pixel(x, y)[0]))
+            .sum::<u32>()
+    };
+    assert!(density(0..16) > density(16..32));
+}
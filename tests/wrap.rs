@@ -0,0 +1,57 @@
+use image::{GenericImage, GrayImage, Luma};
+use rectanglify::rects::{rectanglify, Settings};
+
+/// Rectanglifies a 96x64 gradient, and tiles the result 2x2.
+fn tiled(wrap: bool) -> GrayImage {
+    let input = GrayImage::from_fn(96, 64, |x, y| Luma([((x * 3 + y * 5) % 256) as u8]));
+    let settings = Settings {
+        wrap,
+        border: true,
+        ..Settings::default()
+    };
+    let mut output = GrayImage::new(96, 64);
+    rectanglify(&input, &mut output, settings);
+
+    let mut tiled = GrayImage::new(192, 128);
+    for (x, y) in [(0, 0), (96, 0), (0, 64), (96, 64)] {
+        tiled.copy_from(&output, x, y).unwrap();
+    }
+    tiled
+}
+
+/// Returns the columns and rows of `image` that are black all the way along.
+fn solid_lines(image: &GrayImage) -> (Vec<u32>, Vec<u32>) {
+    let black = |x, y| image[(x, y)] == Luma([0]);
+    let columns = (0..image.width())
+        .filter(|&x| (0..image.height()).all(|y| black(x, y)))
+        .collect();
+    let rows = (0..image.height())
+        .filter(|&y| (0..image.width()).all(|x| black(x, y)))
+        .collect();
+    (columns, rows)
+}
+
+/// The lines from the first split carry straight on across the seams between tiles, and there's
+/// no border doubled up along them.
+#[test]
+fn tiles_line_up() {
+    let (columns, rows) = solid_lines(&tiled(true));
+    assert_eq!(columns.len(), 2, "{:?}", columns);
+    assert_eq!(columns[1], columns[0] + 96);
+    assert_eq!(rows.len(), 2, "{:?}", rows);
+    assert_eq!(rows[1], rows[0] + 64);
+    for x in [0, 95, 96, 191] {
+        assert!(!columns.contains(&x), "border at x = {}", x);
+    }
+    for y in [0, 63, 64, 127] {
+        assert!(!rows.contains(&y), "border at y = {}", y);
+    }
+}
+
+/// Otherwise, the first split is only one way, so nothing runs across the other seam but the
+/// border.
+#[test]
+fn no_wrap_has_only_border_across_seam() {
+    let (_, rows) = solid_lines(&tiled(false));
+    assert_eq!(rows, [0, 63, 64, 127]);
+}